@@ -0,0 +1,68 @@
+//! `rsalloc` under Embassy: the global heap is shared by every task, and a task keeps an arena of
+//! its own through an `AllocatorHandle` across `.await` points.
+//!
+//! ```sh
+//! cargo run --release --bin embassy
+//! ```
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use cortex_m_semihosting::{debug, hprintln};
+use embassy_executor::Spawner;
+use embassy_futures::yield_now;
+use panic_halt as _;
+use rsalloc::prelude::*;
+
+rsalloc::static_arena!(HEAP_ARENA, 16 * 1024);
+rsalloc::static_arena!(TASK_ARENA, 2 * 1024);
+
+// interrupts are masked while the lock is held, so a handler can't spin on it forever
+#[global_allocator]
+static HEAP: InterruptFree<SpinLock<FreeListAllocator>> = InterruptFree::new(SpinLock::new(
+    FreeListAllocator::new(unsafe { HEAP_ARENA.region() }, PlacementPolicy::FindFirst),
+));
+
+static TASK_HEAP: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+    unsafe { TASK_ARENA.region() },
+    PlacementPolicy::FindBest,
+));
+
+#[derive(Debug)]
+struct Reading {
+    channel: u8,
+    value: u16,
+}
+
+#[embassy_executor::task]
+async fn sampler(alloc: AllocatorHandle<'static, SpinLock<FreeListAllocator>>) {
+    for channel in 0..4 {
+        // lives in the task's own arena, across the await below
+        let reading = alloc
+            .alloc_with(|| Reading { channel, value: 0 })
+            .expect("task arena is full");
+        yield_now().await;
+
+        reading.value = u16::from(channel) * 100;
+        hprintln!("{:?}", reading);
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    spawner.spawn(sampler(TASK_HEAP.handle())).unwrap();
+
+    // the global heap works as usual
+    let mut history: Vec<Box<u32>> = Vec::new();
+    for i in 0..8 {
+        history.push(Box::new(i));
+        yield_now().await;
+    }
+
+    hprintln!("{} entries on the global heap", history.len());
+    debug::exit(debug::EXIT_SUCCESS);
+}
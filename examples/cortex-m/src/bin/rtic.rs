@@ -0,0 +1,68 @@
+//! `rsalloc` as the `#[global_allocator]` of an RTIC application, allocating from both a task
+//! and an interrupt handler that preempts it.
+//!
+//! ```sh
+//! cargo run --release --bin rtic
+//! ```
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use panic_halt as _;
+use rsalloc::prelude::*;
+
+rsalloc::static_arena!(ARENA, 16 * 1024);
+
+// the allocator is only reached with interrupts masked, so the single core never runs two
+// allocations at once, whatever the priorities of the tasks
+#[global_allocator]
+static HEAP: InterruptFree<SingleThreaded<FreeListAllocator>> = InterruptFree::new(unsafe {
+    SingleThreaded::new(FreeListAllocator::new(
+        ARENA.region(),
+        PlacementPolicy::FindFirst,
+    ))
+});
+
+#[rtic::app(device = lm3s6965, dispatchers = [SSI0])]
+mod app {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use cortex_m_semihosting::{debug, hprintln};
+    use lm3s6965::Interrupt;
+
+    #[shared]
+    struct Shared {}
+
+    #[local]
+    struct Local {
+        log: Vec<String>,
+    }
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local) {
+        producer::spawn().ok();
+
+        (Shared {}, Local { log: Vec::new() })
+    }
+
+    #[task(priority = 1, local = [log])]
+    async fn producer(cx: producer::Context) {
+        for i in 0..8 {
+            cx.local.log.push(alloc::format!("sample {i}"));
+
+            // the handler allocates while this task may be in the middle of an allocation
+            rtic::pend(Interrupt::UART0);
+        }
+
+        hprintln!("{} samples logged", cx.local.log.len());
+        debug::exit(debug::EXIT_SUCCESS);
+    }
+
+    #[task(binds = UART0, priority = 2)]
+    fn uart0(_: uart0::Context) {
+        let frame: Vec<u8> = (0..32).collect();
+        hprintln!("received {} bytes", frame.len());
+    }
+}
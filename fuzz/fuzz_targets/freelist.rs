@@ -0,0 +1,114 @@
+//! Replays arbitrary sequences of alloc/realloc/dealloc against a `FreeListAllocator` and
+//! cross-checks every block against a shadow model of the live ranges.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rsalloc::{FreeListAllocator, PlacementPolicy, Region, SpinLock};
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::BTreeMap;
+
+const ARENA_SIZE: usize = 16 * 1024;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Alloc { size: u16, align_shift: u8 },
+    Dealloc { index: u8 },
+    Realloc { index: u8, new_size: u16 },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    find_best: bool,
+    ops: Vec<Op>,
+}
+
+// live blocks by start address: (end, layout)
+type Shadow = BTreeMap<usize, (usize, Layout)>;
+
+fn layout(size: u16, align_shift: u8) -> Layout {
+    Layout::from_size_align(size.max(1) as usize, 1 << (align_shift % 8)).unwrap()
+}
+
+// the `index`th live block, wrapping around
+fn pick(shadow: &Shadow, index: u8) -> Option<(usize, Layout)> {
+    let len = shadow.len();
+    (len > 0).then(|| {
+        let (&start, &(_, layout)) = shadow.iter().nth(index as usize % len).unwrap();
+        (start, layout)
+    })
+}
+
+fn insert(shadow: &mut Shadow, arena: &Region, ptr: *mut u8, layout: Layout) {
+    let (start, end) = (ptr as usize, ptr as usize + layout.size());
+
+    assert!(start.is_multiple_of(layout.align()), "{ptr:p} isn't aligned to {}", layout.align());
+    assert!(arena.start() <= start && end <= arena.end(), "{ptr:p} is outside of the arena");
+
+    // the closest live block below must end before this one starts, the one above start after
+    if let Some((_, &(prev_end, _))) = shadow.range(..=start).next_back() {
+        assert!(prev_end <= start, "{ptr:p} overlaps a live block");
+    }
+    if let Some((&next_start, _)) = shadow.range(start..).next() {
+        assert!(end <= next_start, "{ptr:p} overlaps a live block");
+    }
+
+    shadow.insert(start, (end, layout));
+}
+
+fuzz_target!(|input: Input| {
+    let mut memory = vec![0u64; ARENA_SIZE / 8];
+    let arena = unsafe { Region::from_raw_parts(memory.as_mut_ptr() as *mut u8, ARENA_SIZE) };
+
+    let policy = match input.find_best {
+        true => PlacementPolicy::FindBest,
+        false => PlacementPolicy::FindFirst,
+    };
+    let allocator = SpinLock::new(FreeListAllocator::new(arena, policy));
+
+    let mut shadow = Shadow::new();
+
+    for op in input.ops {
+        match op {
+            Op::Alloc { size, align_shift } => {
+                let layout = layout(size, align_shift);
+                let ptr = unsafe { allocator.alloc(layout) };
+
+                if !ptr.is_null() {
+                    insert(&mut shadow, &arena, ptr, layout);
+                }
+            }
+            Op::Dealloc { index } => {
+                if let Some((start, layout)) = pick(&shadow, index) {
+                    shadow.remove(&start);
+                    unsafe { allocator.dealloc(start as *mut u8, layout) };
+                }
+            }
+            Op::Realloc { index, new_size } => {
+                if let Some((start, layout)) = pick(&shadow, index) {
+                    let new_size = new_size.max(1) as usize;
+                    let ptr = unsafe { allocator.realloc(start as *mut u8, layout, new_size) };
+
+                    if !ptr.is_null() {
+                        shadow.remove(&start);
+                        let new_layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+                        insert(&mut shadow, &arena, ptr, new_layout);
+                    }
+                }
+            }
+        }
+    }
+
+    for (start, (_, layout)) in shadow {
+        unsafe { allocator.dealloc(start as *mut u8, layout) };
+    }
+
+    // everything was freed, so no memory may be lost: the whole arena coalesces back into a
+    // single block
+    let whole = Layout::from_size_align(ARENA_SIZE - FreeListAllocator::overhead_per_alloc(), 8);
+    let ptr = unsafe { allocator.alloc(whole.unwrap()) };
+    assert!(!ptr.is_null(), "memory was lost after freeing every block");
+
+    drop(memory);
+});
@@ -0,0 +1,114 @@
+use super::AllocError;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+/// Allocation of blocks whose alignment applies at an offset into them, available on every
+/// [`GlobalAlloc`], e.g. for the DMA engines and graphics APIs that want the payload after a
+/// prefix header aligned rather than the header itself:
+///
+/// ```ignore
+/// // a 16 byte descriptor followed by a payload aligned to a cache line
+/// let ptr = HEAP.alloc_aligned(16 + len, 64, 16)?;
+/// ```
+///
+/// The block is aligned to `align` itself and shifted forward by up to `align - 1` bytes, it's
+/// given back with [`dealloc_aligned`](AllocAligned::dealloc_aligned) and the same arguments.
+pub trait AllocAligned: GlobalAlloc {
+    /// Allocates `size` bytes such that `ptr + offset` is aligned to `align`, a power of two.
+    fn alloc_aligned(
+        &self,
+        size: usize,
+        align: usize,
+        offset: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let (layout, shift) = block_layout(size, align, offset)?;
+
+        // zero sized requests can't go through the allocator, they get a dangling pointer
+        if layout.size() == 0 {
+            return Ok(NonNull::new((align + shift) as *mut u8).unwrap());
+        }
+
+        let block = unsafe { self.alloc(layout) };
+        let block = NonNull::new(block).ok_or(AllocError::OutOfMemory)?;
+
+        Ok(unsafe { block.add(shift) })
+    }
+
+    /// Frees a block returned by [`alloc_aligned`](AllocAligned::alloc_aligned).
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with the same `size`, `align` and
+    /// `offset`, and it may not be used afterwards.
+    unsafe fn dealloc_aligned(&self, ptr: NonNull<u8>, size: usize, align: usize, offset: usize) {
+        // the arguments were accepted when the block was allocated
+        let Ok((layout, shift)) = block_layout(size, align, offset) else {
+            return;
+        };
+
+        if layout.size() != 0 {
+            unsafe { self.dealloc(ptr.as_ptr().sub(shift), layout) };
+        }
+    }
+}
+
+impl<A: GlobalAlloc> AllocAligned for A {}
+
+// the layout of the block to allocate and how far into it the pointer handed out is, the block
+// is aligned to `align` so shifting it forward by the distance of `offset` to the next multiple
+// of `align` does it
+fn block_layout(size: usize, align: usize, offset: usize) -> Result<(Layout, usize), AllocError> {
+    if !align.is_power_of_two() {
+        return Err(AllocError::BadAlignment);
+    }
+
+    let shift = offset.wrapping_neg() & (align - 1);
+    let layout = size
+        .checked_add(shift)
+        .and_then(|size| Layout::from_size_align(size, align).ok())
+        .ok_or(AllocError::OutOfMemory)?;
+
+    Ok((layout, shift))
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+
+    #[test]
+    fn test_alloc_aligned() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        for (size, align, offset) in [(80, 64, 16), (24, 8, 13), (300, 256, 8), (40, 16, 0)] {
+            let ptr = ALLOC.alloc_aligned(size, align, offset).unwrap();
+            assert!((ptr.as_ptr() as usize + offset).is_multiple_of(align));
+
+            unsafe { ptr.write_bytes(0xAB, size) };
+            unsafe { ALLOC.dealloc_aligned(ptr, size, align, offset) };
+        }
+
+        // everything was given back
+        ALLOC.lock_with(|allocator| {
+            assert_eq!(
+                allocator.free_blocks().map(|(_, size)| size).sum::<usize>(),
+                4096
+            )
+        });
+
+        // zero sized blocks are aligned as well
+        let ptr = ALLOC.alloc_aligned(0, 32, 4).unwrap();
+        assert!((ptr.as_ptr() as usize + 4).is_multiple_of(32));
+        unsafe { ALLOC.dealloc_aligned(ptr, 0, 32, 4) };
+
+        assert_eq!(ALLOC.alloc_aligned(8, 24, 0), Err(AllocError::BadAlignment));
+        assert_eq!(
+            ALLOC.alloc_aligned(8192, 8, 0),
+            Err(AllocError::OutOfMemory)
+        );
+    }
+}
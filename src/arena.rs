@@ -1,29 +1,345 @@
-use super::ARENA_SIZE;
+use super::{ARENA_SIZE, CACHE_LINE_SIZE};
 use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
 
-pub struct Arena {
-    arena: UnsafeCell<[u8; ARENA_SIZE]>,
+/// Backing storage for the allocators.
+///
+/// An `Arena` is meant to live in its own `static`, allocators only keep a [`Region`] pointing
+/// into it, so the allocator values themselves stay a handful of words.
+///
+/// The storage is left uninitialized, bytes only hold meaningful data once they were handed out
+/// by an allocator and written to. Enable the `zero-on-alloc` feature to get zeroed blocks.
+///
+/// The storage starts on a cache line boundary, see [`CACHE_LINE_SIZE`](crate::CACHE_LINE_SIZE).
+// the allocators write their nodes and headers at the very start of the arena
+#[cfg_attr(
+    not(any(feature = "cache-line-32", feature = "cache-line-128")),
+    repr(align(64))
+)]
+#[cfg_attr(
+    all(feature = "cache-line-32", not(feature = "cache-line-128")),
+    repr(align(32))
+)]
+#[cfg_attr(feature = "cache-line-128", repr(align(128)))]
+pub struct Arena<const N: usize = ARENA_SIZE> {
+    arena: UnsafeCell<MaybeUninit<[u8; N]>>,
 }
 
-impl Arena {
+// SAFETY: the arena is only ever accessed through the one allocator its region is handed to (see
+// `region`), which is behind a lock
+unsafe impl<const N: usize> Sync for Arena<N> {}
+
+impl<const N: usize> Arena<N> {
+    /// Creates the arena, its size has to be a multiple of
+    /// [`CACHE_LINE_SIZE`](crate::CACHE_LINE_SIZE) (the storage is padded to one anyway), which
+    /// is checked at compile time:
+    ///
+    /// ```compile_fail
+    /// static ARENA: rsalloc::Arena<100> = rsalloc::Arena::new();
+    /// ```
     pub const fn new() -> Self {
+        const {
+            assert!(
+                N.is_multiple_of(CACHE_LINE_SIZE),
+                "arena size must be a multiple of the cache line size"
+            )
+        };
+
+        Self {
+            arena: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Returns the region of memory covered by this arena.
+    ///
+    /// # Safety
+    ///
+    /// The memory of the arena belongs to a single allocator: the region, or any part of it, may
+    /// be handed to at most one allocator, and nothing else may access it while that allocator
+    /// is in use. Calling this again, e.g. to compare addresses, is fine.
+    pub const unsafe fn region(&'static self) -> Region {
+        Region {
+            start: self.arena.get() as *mut u8,
+            size: N,
+        }
+    }
+
+    /// Splits the arena into named sub-arenas, see [`Region::split`].
+    ///
+    /// ```ignore
+    /// static ARENA: Arena = Arena::new();
+    /// static BUDGETS: Split<2> =
+    ///     unsafe { ARENA.split([("render", 64 * 1024), ("audio", 32 * 1024)]) };
+    ///
+    /// static AUDIO: SpinLock<PoolAllocator> =
+    ///     SpinLock::new(PoolAllocator::new(unsafe { BUDGETS.region(1) }, 256));
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// Same as [`region`](Self::region), the parts are handed to the allocators instead of the
+    /// whole arena.
+    pub const unsafe fn split<const K: usize>(
+        &'static self,
+        parts: [(&'static str, usize); K],
+    ) -> Split<K> {
+        unsafe { self.region() }.split(parts)
+    }
+}
+
+impl<const N: usize> Default for Arena<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A contiguous block of memory handed to an allocator at construction.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    start: *mut u8,
+    size: usize,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+// SAFETY: a region is just a description of some memory, access to it is synchronized by the
+// allocator that owns it
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    /// Creates a region from a raw pointer and a size.
+    ///
+    /// # Safety
+    ///
+    /// `start` must be valid for reads and writes of `size` bytes for as long as the region is
+    /// used, and no one else may access that memory.
+    pub const unsafe fn from_raw_parts(start: *mut u8, size: usize) -> Self {
+        Self { start, size }
+    }
+
+    /// Creates a region spanning `[start, end)`, typically the `__heap_start`/`__heap_end`
+    /// symbols exported by the linker script.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Region::from_raw_parts`], additionally `end` must not be before `start`.
+    pub unsafe fn from_bounds(start: *mut u8, end: *mut u8) -> Self {
+        Self {
+            start,
+            size: end as usize - start as usize,
+        }
+    }
+
+    /// A region without any memory, for allocators whose memory is only known at runtime.
+    pub const fn empty() -> Self {
         Self {
-            arena: UnsafeCell::new([0x00; ARENA_SIZE]),
+            start: core::ptr::null_mut(),
+            size: 0,
         }
     }
 
     #[inline]
     pub fn start(&self) -> usize {
-        self.arena.get() as usize
+        self.start as usize
     }
 
     #[inline]
     pub fn end(&self) -> usize {
-        self.start() + ARENA_SIZE
+        self.start() + self.size
     }
 
     #[inline(always)]
-    pub fn size(&self) -> usize {
-        ARENA_SIZE
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    // the first `size` bytes of the region
+    #[cfg(feature = "freelist")]
+    pub(crate) const fn truncate(self, size: usize) -> Self {
+        assert!(size <= self.size, "arena size doesn't fit in the region");
+
+        Self {
+            start: self.start,
+            size,
+        }
+    }
+
+    // whether `ptr` points into the region, whatever its `mte` tag
+    pub(crate) fn contains(&self, ptr: *mut u8) -> bool {
+        #[cfg(feature = "mte")]
+        let ptr = super::mte::strip(ptr);
+
+        self.start() <= ptr as usize && (ptr as usize) < self.end()
+    }
+
+    /// Splits the region into consecutive named parts of the given sizes, each of them usable as
+    /// the memory of its own allocator, so a single arena holds the budget of every subsystem.
+    ///
+    /// Parts are placed a multiple of [`CACHE_LINE_SIZE`](crate::CACHE_LINE_SIZE) apart, so the
+    /// parts of an [`Arena`] start on a cache line boundary like the arena itself does. Whatever
+    /// is left over is available through [`Split::rest`].
+    ///
+    /// Panics if the parts don't fit in the region.
+    pub const fn split<const K: usize>(self, parts: [(&'static str, usize); K]) -> Split<K> {
+        let mut split = Split {
+            parts: [("", Region::empty()); K],
+            rest: self,
+        };
+
+        let mut i = 0;
+        while i < K {
+            let (name, size) = parts[i];
+            assert!(
+                size <= split.rest.size,
+                "sub-arenas don't fit in the region"
+            );
+
+            split.parts[i] = (
+                name,
+                Region {
+                    start: split.rest.start,
+                    size,
+                },
+            );
+
+            // the next part starts on the next cache line, if there's anything left at all
+            let taken = match size.checked_next_multiple_of(CACHE_LINE_SIZE) {
+                Some(taken) if taken < split.rest.size => taken,
+                _ => split.rest.size,
+            };
+            split.rest = Region {
+                start: split.rest.start.wrapping_add(taken),
+                size: split.rest.size - taken,
+            };
+
+            i += 1;
+        }
+
+        split
+    }
+}
+
+/// Named parts of a region, created with [`Region::split`] or [`Arena::split`].
+#[derive(Clone, Copy)]
+pub struct Split<const K: usize> {
+    parts: [(&'static str, Region); K],
+    rest: Region,
+}
+
+impl<const K: usize> Split<K> {
+    /// The `index`th part, in the order they were given in.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Arena::region`], for the part.
+    pub const unsafe fn region(&self, index: usize) -> Region {
+        self.parts[index].1
+    }
+
+    /// The part called `name`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Arena::region`], for the part.
+    pub unsafe fn get(&self, name: &str) -> Option<Region> {
+        self.parts
+            .iter()
+            .find(|(part, _)| *part == name)
+            .map(|&(_, region)| region)
+    }
+
+    /// Names and regions of the parts.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Arena::region`], for each of the parts.
+    pub unsafe fn iter(&self) -> impl Iterator<Item = (&'static str, Region)> + '_ {
+        self.parts.iter().copied()
+    }
+
+    /// What's left of the region after the last part.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Arena::region`], for the rest.
+    pub const unsafe fn rest(&self) -> Region {
+        self.rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SpinLock, CACHE_LINE_SIZE};
+
+    #[test]
+    fn test_cache_line_alignment() {
+        static ARENA: Arena<128> = Arena::new();
+        static LOCK: SpinLock<usize> = SpinLock::new(0);
+
+        assert!(unsafe { ARENA.region() }
+            .start()
+            .is_multiple_of(CACHE_LINE_SIZE));
+        assert!((&LOCK as *const SpinLock<usize> as usize).is_multiple_of(CACHE_LINE_SIZE));
+        assert_eq!(core::mem::size_of::<SpinLock<usize>>(), CACHE_LINE_SIZE);
+    }
+
+    #[test]
+    fn test_split() {
+        static ARENA: Arena<4096> = Arena::new();
+        static PARTS: Split<3> =
+            unsafe { ARENA.split([("render", 1000), ("audio", 512), ("empty", 0)]) };
+
+        let render = unsafe { PARTS.get("render") }.unwrap();
+        let audio = unsafe { PARTS.region(1) };
+        assert_eq!(render.start(), unsafe { ARENA.region() }.start());
+        assert_eq!(render.size(), 1000);
+        assert_eq!(audio.size(), 512);
+        assert!(audio.start() >= render.end());
+        assert!(audio.start().is_multiple_of(CACHE_LINE_SIZE));
+        assert!(unsafe { PARTS.get("physics") }.is_none());
+
+        // the rest runs up to the end of the arena
+        assert_eq!(
+            unsafe { PARTS.rest() }.end(),
+            unsafe { ARENA.region() }.end()
+        );
+        assert!(unsafe { PARTS.rest() }.start() >= audio.end());
+
+        let names: Vec<_> = unsafe { PARTS.iter() }.map(|(name, _)| name).collect();
+        assert_eq!(names, ["render", "audio", "empty"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "don't fit")]
+    fn test_split_too_large() {
+        static ARENA: Arena<1024> = Arena::new();
+
+        unsafe { ARENA.split([("render", 1000), ("audio", 100)]) };
+    }
+
+    #[test]
+    #[cfg(feature = "linear")]
+    fn test_split_allocators() {
+        use crate::ArenaAllocator;
+        use core::alloc::{GlobalAlloc, Layout};
+
+        static ARENA: Arena<4096> = Arena::new();
+        static PARTS: Split<2> = unsafe { ARENA.split([("frame", 256), ("level", 1024)]) };
+        static FRAME: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { PARTS.region(0) }));
+
+        let frame = unsafe { PARTS.region(0) };
+        let layout = Layout::new::<[u8; 200]>();
+        let ptr = unsafe { FRAME.alloc(layout) } as usize;
+        assert!(frame.start() <= ptr && ptr + 200 <= frame.end());
+
+        // the budget of the part is enforced
+        assert!(unsafe { FRAME.alloc(layout) }.is_null());
     }
 }
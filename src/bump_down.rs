@@ -0,0 +1,204 @@
+#[cfg(feature = "metrics")]
+use super::metrics::{Metered, Usage};
+use super::scoped::Resettable;
+use super::utils::{init_block, release_block, zeroed};
+#[cfg(feature = "valgrind")]
+use super::valgrind;
+use super::{Region, Relax, SingleThreaded, SpinLock};
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr;
+
+/// A linear allocator that hands out blocks from the end of the arena towards its start.
+///
+/// Bumping down, the start of a block is its end minus its size rounded down to the alignment,
+/// a single mask instead of the padding computation of the
+/// [`ArenaAllocator`](crate::ArenaAllocator), which shortens the allocation path. Blocks are
+/// freed all at once, with [`reset`](Self::reset) or by rewinding a
+/// [`ScopedArena`](crate::ScopedArena).
+pub struct BumpDownAllocator {
+    arena: Region,
+    // offset of the lowest block handed out so far, the size of the arena while it's empty
+    curr_offset: usize,
+}
+
+impl BumpDownAllocator {
+    pub const fn new(arena: Region) -> Self {
+        Self {
+            curr_offset: arena.size(),
+            arena,
+        }
+    }
+
+    /// Bytes of metadata every allocation costs, on top of the padding needed for its alignment.
+    pub const fn overhead_per_alloc() -> usize {
+        0
+    }
+
+    /// Smallest number of bytes an allocation takes up in the arena.
+    pub const fn min_block_size() -> usize {
+        0
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let end = self.arena.start() + self.curr_offset;
+
+        // the alignment is a power of two, rounding down is masking off the low bits
+        let start = match end.checked_sub(layout.size()) {
+            Some(start) => start & !(layout.align() - 1),
+            None => return ptr::null_mut(),
+        };
+
+        if start < self.arena.start() {
+            // arena out of memory
+            return ptr::null_mut();
+        }
+
+        self.curr_offset = start - self.arena.start();
+
+        start as *mut u8
+    }
+
+    /// Releases every block allocated so far.
+    ///
+    /// # Safety
+    ///
+    /// None of the released blocks may be used afterwards.
+    pub unsafe fn reset(&mut self) {
+        unsafe { self.rewind(self.arena.size()) };
+    }
+
+    /// Bytes handed out so far, padding included.
+    pub fn used(&self) -> usize {
+        self.arena.size() - self.curr_offset
+    }
+}
+
+impl Resettable for BumpDownAllocator {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.curr_offset
+    }
+
+    unsafe fn rewind(&mut self, checkpoint: usize) {
+        let start = (self.arena.start() + self.curr_offset) as *mut u8;
+        unsafe { release_block(start, checkpoint - self.curr_offset) };
+
+        self.curr_offset = checkpoint;
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.allocate(layout)
+    }
+}
+
+impl fmt::Debug for BumpDownAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BumpDownAllocator")
+            .field("used", &self.used())
+            .field("free", &self.curr_offset)
+            .finish()
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Relax> Metered for SpinLock<BumpDownAllocator, R> {
+    fn usage(&self) -> Usage {
+        self.lock_with(|allocator| Usage {
+            used: allocator.used(),
+            capacity: allocator.arena.size(),
+        })
+    }
+}
+
+impl_global_alloc!(BumpDownAllocator, {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .lock_with_or_timeout(|allocator| allocator.allocate(layout))
+            .unwrap_or(ptr::null_mut());
+
+        #[cfg(feature = "valgrind")]
+        let ptr = valgrind::malloc_like(ptr, layout.size());
+
+        unsafe { init_block(ptr, layout.size()) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "valgrind")]
+        valgrind::free_like(ptr);
+
+        // blocks are only released all at once
+        unsafe { release_block(ptr, layout.size()) };
+    }
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arena, ScopedArena};
+
+    #[test]
+    fn test_bump_down() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<BumpDownAllocator> =
+            SpinLock::new(BumpDownAllocator::new(unsafe { ARENA.region() }));
+
+        let end = unsafe { ARENA.region() }.end();
+
+        // the first block ends at the end of the arena
+        let ptr_1 = unsafe { ALLOC.alloc(Layout::new::<u32>()) };
+        assert_eq!(ptr_1 as usize, end - 4);
+
+        // the next one below it, rounded down to its alignment
+        let ptr_2 = unsafe { ALLOC.alloc(Layout::new::<u64>()) };
+        assert_eq!(ptr_2 as usize, end - 16);
+
+        let ptr_3 = unsafe { ALLOC.alloc(Layout::from_size_align(10, 64).unwrap()) };
+        assert_eq!(ptr_3 as usize, end - 64);
+        assert_eq!(ALLOC.lock_with(|allocator| allocator.used()), 64);
+
+        // more than what's left
+        assert!(unsafe { ALLOC.alloc(Layout::new::<[u8; 1024]>()) }.is_null());
+        let rest = unsafe { ALLOC.alloc(Layout::new::<[u8; 960]>()) };
+        assert_eq!(rest as usize, unsafe { ARENA.region() }.start());
+        assert!(unsafe { ALLOC.alloc(Layout::new::<u8>()) }.is_null());
+
+        unsafe { ALLOC.lock_with(|allocator| allocator.reset()) };
+        assert_eq!(unsafe { ALLOC.alloc(Layout::new::<u32>()) }, ptr_1);
+    }
+
+    #[test]
+    fn test_huge_layout() {
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = BumpDownAllocator::new(unsafe { ARENA.region() });
+
+        // the start of the block would be below address zero
+        let layout = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+        assert!(allocator.allocate(layout).is_null());
+
+        assert!(!allocator.allocate(Layout::new::<u64>()).is_null());
+    }
+
+    #[test]
+    fn test_scoped() {
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = BumpDownAllocator::new(unsafe { ARENA.region() });
+
+        let layout = Layout::new::<u64>();
+        let kept = allocator.allocate(layout);
+
+        {
+            let mut scope = ScopedArena::new(&mut allocator);
+            assert_eq!(scope.alloc(layout), kept.wrapping_sub(8));
+        }
+
+        // the block of the scope was released
+        assert_eq!(allocator.used(), 8);
+        assert_eq!(allocator.allocate(layout), kept.wrapping_sub(8));
+    }
+}
@@ -0,0 +1,203 @@
+use super::Region;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// An allocator [`DynGlobalAllocator`] can delegate to, with the memory it manages so the
+/// blocks it handed out still find their way back to it after the global allocator switched.
+///
+/// Backends are meant to live in `static`s next to their allocator:
+///
+/// ```ignore
+/// static BOOT_ARENA: Arena<{ 64 * 1024 }> = Arena::new();
+/// static BOOT_HEAP: SpinLock<FreeListAllocator> = ...;
+/// static BOOT: Backend = Backend::new(&BOOT_HEAP).with_region(unsafe { BOOT_ARENA.region() });
+/// ```
+pub struct Backend {
+    allocator: &'static (dyn GlobalAlloc + Sync),
+    region: Option<Region>,
+    // the backend that was active before this one, set when switching to it
+    previous: AtomicPtr<Backend>,
+}
+
+impl Backend {
+    pub const fn new(allocator: &'static (dyn GlobalAlloc + Sync)) -> Self {
+        Self {
+            allocator,
+            region: None,
+            previous: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// The memory the allocator hands blocks out from. Backends without a region are assumed to
+    /// own every block no other backend claims, so only the last one switched to may leave it
+    /// out, e.g. one backed by the system allocator.
+    pub const fn with_region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    fn owns(&self, ptr: *mut u8) -> bool {
+        self.region.is_some_and(|region| region.contains(ptr))
+    }
+
+    fn previous(&self) -> Option<&'static Backend> {
+        unsafe { self.previous.load(Ordering::Acquire).as_ref() }
+    }
+}
+
+/// A global allocator delegating to a backend that can be switched at runtime, e.g. to start
+/// on a small static arena and move to an mmap backed heap once the OS services are up.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static GLOBAL: DynGlobalAllocator = DynGlobalAllocator::new(&BOOT);
+///
+/// // once paging is set up
+/// GLOBAL.switch_to(&KERNEL);
+/// ```
+///
+/// New blocks are allocated by the current backend, blocks allocated before a switch are freed
+/// by the backend whose region they belong to. Reallocating such a block moves it to the current
+/// backend.
+pub struct DynGlobalAllocator {
+    current: AtomicPtr<Backend>,
+}
+
+impl DynGlobalAllocator {
+    pub const fn new(initial: &'static Backend) -> Self {
+        Self {
+            current: AtomicPtr::new(initial as *const Backend as *mut Backend),
+        }
+    }
+
+    /// Makes `backend` the one new blocks are allocated by.
+    ///
+    /// Panics if `backend` was used before, switching back and forth isn't supported.
+    pub fn switch_to(&self, backend: &'static Backend) {
+        let new = backend as *const Backend as *mut Backend;
+
+        let mut current = self.current.load(Ordering::Acquire);
+        loop {
+            let mut used = unsafe { current.as_ref() };
+            while let Some(val) = used {
+                assert!(!ptr::eq(val, backend), "switched to the same backend twice");
+                used = val.previous();
+            }
+            backend.previous.store(current, Ordering::Release);
+
+            match self
+                .current
+                .compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return,
+                // someone else switched in the meantime
+                Err(actual) => {
+                    backend.previous.store(ptr::null_mut(), Ordering::Release);
+                    current = actual;
+                }
+            }
+        }
+    }
+
+    fn current(&self) -> &'static Backend {
+        unsafe { &*self.current.load(Ordering::Acquire) }
+    }
+
+    // the backend `ptr` was allocated by, the newest one whose region holds it
+    fn owner(&self, ptr: *mut u8) -> &'static Backend {
+        let current = self.current();
+
+        let mut backend = Some(current);
+        while let Some(val) = backend {
+            if val.owns(ptr) {
+                return val;
+            }
+            backend = val.previous();
+        }
+
+        current
+    }
+}
+
+unsafe impl GlobalAlloc for DynGlobalAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.current().allocator.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.current().allocator.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.owner(ptr).allocator.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let owner = self.owner(ptr);
+
+        // blocks of the current backend may be resized in place
+        if ptr::eq(owner, self.current()) {
+            return unsafe { owner.allocator.realloc(ptr, layout, new_size) };
+        }
+
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                owner.allocator.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+
+    static BOOT_ARENA: Arena<1024> = Arena::new();
+    static BOOT_HEAP: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+        unsafe { BOOT_ARENA.region() },
+        PlacementPolicy::FindFirst,
+    ));
+    static BOOT: Backend = Backend::new(&BOOT_HEAP).with_region(unsafe { BOOT_ARENA.region() });
+
+    static KERNEL_ARENA: Arena<4096> = Arena::new();
+    static KERNEL_HEAP: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+        unsafe { KERNEL_ARENA.region() },
+        PlacementPolicy::FindFirst,
+    ));
+    static KERNEL: Backend =
+        Backend::new(&KERNEL_HEAP).with_region(unsafe { KERNEL_ARENA.region() });
+
+    static GLOBAL: DynGlobalAllocator = DynGlobalAllocator::new(&BOOT);
+
+    #[test]
+    fn test_switch_backend() {
+        let layout = Layout::new::<[u64; 8]>();
+
+        let (early, moved) = unsafe { (GLOBAL.alloc(layout), GLOBAL.alloc(layout)) };
+        assert!(BOOT.owns(early) && BOOT.owns(moved));
+
+        GLOBAL.switch_to(&KERNEL);
+
+        let late = unsafe { GLOBAL.alloc(layout) };
+        assert!(KERNEL.owns(late));
+
+        // the early block goes back to the boot heap, which hands it out again
+        unsafe { GLOBAL.dealloc(early, layout) };
+        assert_eq!(unsafe { BOOT_HEAP.alloc(layout) }, early);
+
+        // reallocating moves the block over to the kernel heap, keeping its contents
+        unsafe { moved.write(42) };
+        let moved = unsafe { GLOBAL.realloc(moved, layout, 128) };
+        assert!(KERNEL.owns(moved));
+        assert_eq!(unsafe { moved.read() }, 42);
+
+        let result = std::panic::catch_unwind(|| GLOBAL.switch_to(&KERNEL));
+        assert!(result.is_err());
+    }
+}
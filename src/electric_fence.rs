@@ -0,0 +1,119 @@
+use super::virtual_arena::page_size;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// Debugging allocator that places large allocations right before an inaccessible guard page,
+/// so overrunning them traps immediately instead of corrupting a neighbor.
+///
+/// Allocations smaller than the threshold (or aligned to more than a page) are forwarded to the
+/// inner allocator. Each fenced allocation costs at least two pages, so keep the threshold high.
+pub struct ElectricFence<A> {
+    inner: A,
+    threshold: usize,
+}
+
+impl<A> ElectricFence<A> {
+    /// Fences every allocation of at least `threshold` bytes.
+    pub const fn new(inner: A, threshold: usize) -> Self {
+        Self { inner, threshold }
+    }
+
+    fn is_fenced(&self, layout: Layout) -> bool {
+        layout.size() >= self.threshold && layout.size() > 0 && layout.align() <= page_size()
+    }
+}
+
+// pages holding the data of a fenced allocation, the guard page comes right after them
+fn data_pages_size(layout: Layout) -> usize {
+    layout.size().next_multiple_of(page_size())
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for ElectricFence<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !self.is_fenced(layout) {
+            return unsafe { self.inner.alloc(layout) };
+        }
+
+        let data_size = data_pages_size(layout);
+
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                data_size + page_size(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+
+        if base == libc::MAP_FAILED {
+            return ptr::null_mut();
+        }
+
+        let guard_page = base as usize + data_size;
+        unsafe {
+            libc::mprotect(
+                guard_page as *mut libc::c_void,
+                page_size(),
+                libc::PROT_NONE,
+            )
+        };
+
+        // the block ends as close as its alignment allows to the guard page
+        let start = (guard_page - layout.size()) & !(layout.align() - 1);
+
+        start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !self.is_fenced(layout) {
+            return unsafe { self.inner.dealloc(ptr, layout) };
+        }
+
+        let data_size = data_pages_size(layout);
+
+        // the end of the block is less than a page (its alignment) away from the guard page
+        let guard_page = (ptr as usize + layout.size()).next_multiple_of(page_size());
+        let base = guard_page - data_size;
+
+        unsafe { libc::munmap(base as *mut libc::c_void, data_size + page_size()) };
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+
+    #[test]
+    fn test_fenced_allocation() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: ElectricFence<SpinLock<FreeListAllocator>> = ElectricFence::new(
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { ARENA.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            1024,
+        );
+
+        // small allocations come from the arena
+        let small = Layout::new::<u64>();
+        let ptr = unsafe { ALLOC.alloc(small) };
+        assert!(
+            unsafe { ARENA.region() }.start() <= ptr as usize
+                && (ptr as usize) < unsafe { ARENA.region() }.end()
+        );
+        unsafe { ALLOC.dealloc(ptr, small) };
+
+        // large ones end right before the guard page
+        let large = Layout::from_size_align(10_000, 8).unwrap();
+        let ptr = unsafe { ALLOC.alloc(large) };
+        assert!(!ptr.is_null());
+        assert_eq!((ptr as usize + large.size()) % page_size(), 0);
+
+        // the whole block is usable
+        unsafe { ptr::write_bytes(ptr, 0xAB, large.size()) };
+        unsafe { ALLOC.dealloc(ptr, large) };
+    }
+}
@@ -0,0 +1,100 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+
+/// Construction of values directly in allocated memory, available on every [`GlobalAlloc`].
+pub trait AllocWith: GlobalAlloc {
+    /// Allocates room for a `T` and writes the value returned by `f` into it, returns `None`
+    /// (without calling `f`) if the allocator is out of memory.
+    ///
+    /// Unlike allocating for an existing value, `f` is inlined into the allocation, so large
+    /// values are built in the allocated memory instead of on the stack and then moved there.
+    ///
+    /// The value is never dropped or freed on its own, give it back with
+    /// [`GlobalAlloc::dealloc`] using `Layout::new::<T>()` if the allocator supports it.
+    // the value points into the allocator's memory, not into `self`
+    #[allow(clippy::mut_from_ref)]
+    #[inline(always)]
+    fn alloc_with<T, F: FnOnce() -> T>(&self, f: F) -> Option<&mut T> {
+        let layout = Layout::new::<T>();
+
+        // zero sized values can't go through the allocator
+        let ptr = if layout.size() == 0 {
+            NonNull::<T>::dangling().as_ptr()
+        } else {
+            unsafe { self.alloc(layout) as *mut T }
+        };
+
+        if ptr.is_null() {
+            return None;
+        }
+
+        unsafe {
+            ptr.write(f());
+            Some(&mut *ptr)
+        }
+    }
+}
+
+impl<A: GlobalAlloc> AllocWith for A {}
+
+/// Allocates a value with [`AllocWith::alloc_with`], building it in place.
+///
+#[cfg_attr(feature = "stack", doc = "```")]
+#[cfg_attr(not(feature = "stack"), doc = "```ignore")]
+/// use rsalloc::{emplace, SpinLock, StackAllocator};
+///
+/// rsalloc::static_arena!(ARENA, 8192);
+/// static ALLOC: SpinLock<StackAllocator> =
+///     SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+///
+/// // never lives on the stack, even in debug builds on small embedded stacks
+/// let buffer = emplace!(ALLOC, [0u8; 4096]).unwrap();
+/// assert_eq!(buffer.len(), 4096);
+/// ```
+#[macro_export]
+macro_rules! emplace {
+    ($allocator:expr, $value:expr) => {
+        $crate::AllocWith::alloc_with(&$allocator, || $value)
+    };
+}
+
+#[cfg(all(test, feature = "linear"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, ArenaAllocator, SpinLock};
+
+    #[derive(Debug, PartialEq)]
+    struct Packet {
+        id: u32,
+        payload: [u8; 256],
+    }
+
+    #[test]
+    fn test_alloc_with() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
+        let packet = emplace!(
+            ALLOC,
+            Packet {
+                id: 7,
+                payload: [0xAB; 256],
+            }
+        )
+        .unwrap();
+        assert_eq!(packet.id, 7);
+        assert!(packet.payload.iter().all(|&byte| byte == 0xAB));
+
+        // the initializer isn't called when there's no memory for the value
+        let mut called = false;
+        let too_large = ALLOC.alloc_with(|| {
+            called = true;
+            [0u64; 1024]
+        });
+        assert!(too_large.is_none());
+        assert!(!called);
+
+        assert_eq!(ALLOC.alloc_with(|| ()), Some(&mut ()));
+    }
+}
@@ -0,0 +1,47 @@
+use core::fmt;
+
+/// Why an allocator couldn't serve a request, returned by its fallible APIs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AllocError {
+    /// There's no free block large enough left.
+    OutOfMemory,
+    /// The alignment asked for is more than the allocator supports.
+    BadAlignment,
+    /// The pointer wasn't handed out by this allocator.
+    ForeignPointer,
+    /// The block was freed already, or never handed out.
+    DoubleFree,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AllocError::OutOfMemory => "out of memory",
+            AllocError::BadAlignment => "alignment not supported by the allocator",
+            AllocError::ForeignPointer => "pointer doesn't belong to the allocator",
+            AllocError::DoubleFree => "block was freed already or never allocated",
+        })
+    }
+}
+
+impl core::error::Error for AllocError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(AllocError::OutOfMemory.to_string(), "out of memory");
+
+        // it works with the error handling of std
+        let error: Box<dyn core::error::Error> = Box::new(AllocError::DoubleFree);
+        assert_eq!(
+            error.to_string(),
+            "block was freed already or never allocated"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! C ABI `malloc`/`free`/`realloc`/`calloc`/`aligned_alloc` on top of any [`GlobalAlloc`].
+//!
+//! C frees memory without giving its size back, so each block is prefixed with a small header
+//! storing the size and alignment it was allocated with. The exported symbols are declared with
+//! [`ffi_exports!`](crate::ffi_exports).
+use super::layout_header::LayoutHeader;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ffi::c_void;
+use core::mem::align_of;
+use core::ptr;
+
+// alignment used by `malloc`, enough for any fundamental C type
+const MALLOC_ALIGN: usize = 2 * align_of::<usize>();
+
+/// Allocates `size` bytes aligned to `align`, returns null on failure.
+///
+/// # Safety
+///
+/// Same as [`GlobalAlloc::alloc`].
+pub unsafe fn aligned_alloc<A: GlobalAlloc>(
+    allocator: &A,
+    align: usize,
+    size: usize,
+) -> *mut c_void {
+    let layout = match Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => return ptr::null_mut(),
+    };
+    let block_layout = match LayoutHeader::block_layout(layout) {
+        Some(block_layout) => block_layout,
+        None => return ptr::null_mut(),
+    };
+
+    let block = unsafe { allocator.alloc(block_layout) };
+    if block.is_null() {
+        return ptr::null_mut();
+    }
+
+    unsafe { LayoutHeader::write(block, layout) as *mut c_void }
+}
+
+/// Allocates `size` bytes aligned for any fundamental type, returns null on failure.
+///
+/// # Safety
+///
+/// Same as [`GlobalAlloc::alloc`].
+pub unsafe fn malloc<A: GlobalAlloc>(allocator: &A, size: usize) -> *mut c_void {
+    unsafe { aligned_alloc(allocator, MALLOC_ALIGN, size) }
+}
+
+/// Allocates `count * size` zeroed bytes, returns null on failure or overflow.
+///
+/// # Safety
+///
+/// Same as [`GlobalAlloc::alloc`].
+pub unsafe fn calloc<A: GlobalAlloc>(allocator: &A, count: usize, size: usize) -> *mut c_void {
+    let total = match count.checked_mul(size) {
+        Some(total) => total,
+        None => return ptr::null_mut(),
+    };
+
+    let ptr = unsafe { malloc(allocator, total) };
+    if !ptr.is_null() {
+        unsafe { ptr::write_bytes(ptr as *mut u8, 0x00, total) };
+    }
+
+    ptr
+}
+
+/// Frees a block, null is ignored.
+///
+/// # Safety
+///
+/// `ptr` must be null or have been returned by one of the functions of this module using the
+/// same allocator, and not freed yet.
+pub unsafe fn free<A: GlobalAlloc>(allocator: &A, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+
+    let header = unsafe { LayoutHeader::read(ptr as *mut u8) };
+    let (block, layout) = header.block(ptr as *mut u8);
+
+    unsafe { allocator.dealloc(block, layout) };
+}
+
+/// Resizes a block keeping its contents, on failure null is returned and the block is untouched.
+///
+/// # Safety
+///
+/// Same as [`free`].
+pub unsafe fn realloc<A: GlobalAlloc>(allocator: &A, ptr: *mut c_void, size: usize) -> *mut c_void {
+    if ptr.is_null() {
+        return unsafe { malloc(allocator, size) };
+    }
+
+    let header = unsafe { LayoutHeader::read(ptr as *mut u8) };
+
+    let new_ptr = unsafe { aligned_alloc(allocator, header.align, size) };
+    if new_ptr.is_null() {
+        // like C, the old block is left untouched
+        return ptr::null_mut();
+    }
+
+    unsafe {
+        ptr::copy_nonoverlapping(ptr as *const u8, new_ptr as *mut u8, header.size.min(size));
+        free(allocator, ptr);
+    }
+
+    new_ptr
+}
+
+/// Exports `rsalloc_malloc`, `rsalloc_free`, `rsalloc_realloc`, `rsalloc_calloc` and
+/// `rsalloc_aligned_alloc` with the C ABI, backed by the given allocator `static`.
+///
+/// ```ignore
+/// static HEAP: SpinLock<FreeListAllocator> = ...;
+///
+/// rsalloc::ffi_exports!(HEAP);
+/// ```
+#[macro_export]
+macro_rules! ffi_exports {
+    ($allocator:path) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn rsalloc_malloc(size: usize) -> *mut core::ffi::c_void {
+            unsafe { $crate::ffi::malloc(&$allocator, size) }
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn rsalloc_calloc(
+            count: usize,
+            size: usize,
+        ) -> *mut core::ffi::c_void {
+            unsafe { $crate::ffi::calloc(&$allocator, count, size) }
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn rsalloc_realloc(
+            ptr: *mut core::ffi::c_void,
+            size: usize,
+        ) -> *mut core::ffi::c_void {
+            unsafe { $crate::ffi::realloc(&$allocator, ptr, size) }
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn rsalloc_aligned_alloc(
+            align: usize,
+            size: usize,
+        ) -> *mut core::ffi::c_void {
+            unsafe { $crate::ffi::aligned_alloc(&$allocator, align, size) }
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn rsalloc_free(ptr: *mut core::ffi::c_void) {
+            unsafe { $crate::ffi::free(&$allocator, ptr) }
+        }
+    };
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+
+    static ARENA: Arena<4096> = Arena::new();
+    static HEAP: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+        unsafe { ARENA.region() },
+        PlacementPolicy::FindFirst,
+    ));
+
+    #[test]
+    fn test_malloc_realloc_free() {
+        let ptr = unsafe { malloc(&HEAP, 16) } as *mut u8;
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % MALLOC_ALIGN, 0);
+
+        unsafe { ptr::write_bytes(ptr, 0xAB, 16) };
+
+        // the contents survive growing the block
+        let ptr = unsafe { realloc(&HEAP, ptr as *mut c_void, 64) } as *mut u8;
+        assert!(!ptr.is_null());
+        let block = unsafe { core::slice::from_raw_parts(ptr, 16) };
+        assert!(block.iter().all(|&byte| byte == 0xAB));
+
+        unsafe { free(&HEAP, ptr as *mut c_void) };
+    }
+
+    #[test]
+    fn test_calloc_and_aligned_alloc() {
+        let ptr = unsafe { calloc(&HEAP, 8, 4) } as *mut u8;
+        let block = unsafe { core::slice::from_raw_parts(ptr, 32) };
+        assert!(block.iter().all(|&byte| byte == 0x00));
+
+        let aligned = unsafe { aligned_alloc(&HEAP, 64, 8) };
+        assert_eq!(aligned as usize % 64, 0);
+
+        // overflowing sizes and bad alignments fail instead of allocating
+        assert!(unsafe { calloc(&HEAP, usize::MAX, 2) }.is_null());
+        assert!(unsafe { aligned_alloc(&HEAP, 3, 8) }.is_null());
+
+        unsafe { free(&HEAP, aligned) };
+        unsafe { free(&HEAP, ptr as *mut c_void) };
+    }
+}
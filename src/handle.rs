@@ -0,0 +1,148 @@
+use super::{Relax, SpinLock};
+use core::alloc::{GlobalAlloc, Layout};
+
+/// A cheap, copyable reference to a shared allocator, for async tasks that allocate from an
+/// arena of their own.
+///
+/// The handle is `Send` and `Sync` whenever the allocator is `Sync`, as every [`SpinLock`]
+/// allocator is, and it only takes the lock for the duration of each call, so it can be kept
+/// in a task across `.await` points without blocking other tasks on the same allocator:
+///
+/// ```ignore
+/// static ARENA: Arena<4096> = Arena::new();
+/// static TASK_ARENA: SpinLock<ArenaAllocator> =
+///     SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+///
+/// #[embassy_executor::task]
+/// async fn sensor_task(alloc: AllocatorHandle<'static, SpinLock<ArenaAllocator>>) {
+///     loop {
+///         let sample = alloc.alloc_with(|| read_sample()).expect("task arena is full");
+///         Timer::after_millis(100).await;
+///         ...
+///     }
+/// }
+///
+/// spawner.spawn(sensor_task(TASK_ARENA.handle()));
+/// ```
+#[derive(Debug)]
+pub struct AllocatorHandle<'a, A: ?Sized> {
+    allocator: &'a A,
+}
+
+impl<'a, A: GlobalAlloc + Sync + ?Sized> AllocatorHandle<'a, A> {
+    pub const fn new(allocator: &'a A) -> Self {
+        Self { allocator }
+    }
+
+    /// The allocator behind the handle.
+    pub fn get(&self) -> &'a A {
+        self.allocator
+    }
+}
+
+impl<A: ?Sized> Clone for AllocatorHandle<'_, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A: ?Sized> Copy for AllocatorHandle<'_, A> {}
+
+unsafe impl<A: GlobalAlloc + ?Sized> GlobalAlloc for AllocatorHandle<'_, A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.allocator.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.allocator.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.allocator.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        unsafe { self.allocator.realloc(ptr, layout, new_size) }
+    }
+}
+
+impl<T: Send, R: Relax> SpinLock<T, R>
+where
+    Self: GlobalAlloc,
+{
+    /// A handle to the allocator that can be handed to async tasks, see [`AllocatorHandle`].
+    pub fn handle(&self) -> AllocatorHandle<'_, Self> {
+        AllocatorHandle::new(self)
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{AllocWith, Arena, FreeListAllocator, PlacementPolicy};
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    static ARENA: Arena<4096> = Arena::new();
+    static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+        unsafe { ARENA.region() },
+        PlacementPolicy::FindFirst,
+    ));
+
+    // yields to the executor once
+    async fn yield_now() {
+        let mut yielded = false;
+        core::future::poll_fn(|_| {
+            if yielded {
+                return Poll::Ready(());
+            }
+            yielded = true;
+            Poll::Pending
+        })
+        .await
+    }
+
+    async fn task(alloc: AllocatorHandle<'static, SpinLock<FreeListAllocator>>) -> u64 {
+        let value = alloc.alloc_with(|| 42u64).unwrap();
+        yield_now().await;
+
+        let value = *value;
+        // nothing is locked between the calls, so other users aren't blocked across the await
+        assert!(!alloc.get().is_locked());
+        value
+    }
+
+    fn assert_send<T: Send>(value: T) -> T {
+        value
+    }
+
+    #[test]
+    fn test_handle_across_await() {
+        let handle = ALLOC.handle();
+
+        // the future can be moved to another thread of the executor
+        let mut future = pin!(assert_send(task(handle)));
+        let mut cx = Context::from_waker(Waker::noop());
+
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        assert!(!ALLOC.is_locked());
+        assert_eq!(future.as_mut().poll(&mut cx), Poll::Ready(42));
+    }
+
+    #[test]
+    fn test_handle_shared_between_threads() {
+        let handle = ALLOC.handle();
+        let layout = Layout::new::<[u64; 4]>();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(move || {
+                    let ptr = unsafe { handle.alloc(layout) };
+                    assert!(!ptr.is_null());
+                    unsafe { handle.dealloc(ptr, layout) };
+                });
+            }
+        });
+    }
+}
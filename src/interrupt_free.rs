@@ -0,0 +1,47 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Runs every call to the wrapped allocator with interrupts masked, so an interrupt handler that
+/// allocates can't preempt an allocation in progress on cortex-m.
+///
+/// Behind a [`SpinLock`](crate::SpinLock) that rules out the deadlock of a handler spinning on
+/// the lock held by the code it interrupted. On single core parts it also makes a
+/// [`SingleThreaded`](crate::SingleThreaded) allocator sound to use from handlers, and it's
+/// cheaper than the lock:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static HEAP: InterruptFree<SingleThreaded<FreeListAllocator>> = InterruptFree::new(unsafe {
+///     // every access goes through `InterruptFree`, so nothing runs concurrently on one core
+///     SingleThreaded::new(FreeListAllocator::new(ARENA.region(), PlacementPolicy::FindFirst))
+/// });
+/// ```
+///
+/// Interrupts are restored to their previous state afterwards, so allocating from a critical
+/// section is fine. Masking only covers the current core, multi core parts still need the lock.
+pub struct InterruptFree<A> {
+    inner: A,
+}
+
+impl<A> InterruptFree<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for InterruptFree<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        cortex_m::interrupt::free(|_| unsafe { self.inner.alloc(layout) })
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        cortex_m::interrupt::free(|_| unsafe { self.inner.alloc_zeroed(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        cortex_m::interrupt::free(|_| unsafe { self.inner.dealloc(ptr, layout) })
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        cortex_m::interrupt::free(|_| unsafe { self.inner.realloc(ptr, layout, new_size) })
+    }
+}
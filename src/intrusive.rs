@@ -0,0 +1,313 @@
+//! Intrusive doubly linked lists, whose nodes live in the memory they keep track of.
+//!
+//! An allocator writes a [`Link`] at the start of every free block and threads the blocks on a
+//! [`List`], so the list needs no memory of its own and any block can be unlinked in constant
+//! time, e.g. when it's merged with a neighbour that was just freed:
+//!
+//! ```ignore
+//! let mut free = List::new();
+//!
+//! // a block is freed
+//! unsafe { free.push_front(Link::init(block)) };
+//!
+//! // and handed out again
+//! let block = free.pop_front().map(|link| link.as_ptr() as *mut u8);
+//! ```
+//!
+//! The list only stores pointers, it's up to the caller to keep the linked memory valid: every
+//! method taking a link is `unsafe` and states what it relies on.
+
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+
+/// The node of a [`List`], embedded in the memory it tracks.
+#[derive(Debug)]
+pub struct Link {
+    next: Option<NonNull<Link>>,
+    prev: Option<NonNull<Link>>,
+}
+
+impl Link {
+    /// A link that isn't on any list.
+    pub const fn new() -> Self {
+        Self {
+            next: None,
+            prev: None,
+        }
+    }
+
+    /// Writes a new link at the start of `block`.
+    ///
+    /// # Safety
+    ///
+    /// `block` must be valid for writes of a `Link` and aligned for it.
+    pub unsafe fn init(block: *mut u8) -> NonNull<Link> {
+        let link = block as *mut Link;
+        unsafe { link.write(Link::new()) };
+
+        // SAFETY: the caller guarantees the block is valid, so it isn't null
+        unsafe { NonNull::new_unchecked(link) }
+    }
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A doubly linked list of [`Link`]s.
+///
+/// Links are added with the `unsafe` push methods, whose callers guarantee that:
+///
+/// - the link is valid for reads and writes and isn't moved while it's on the list,
+/// - the link isn't on this or any other list already.
+#[derive(Debug)]
+pub struct List {
+    head: Option<NonNull<Link>>,
+    tail: Option<NonNull<Link>>,
+    len: usize,
+}
+
+// SAFETY: the list only points to memory it was handed by the caller, who is responsible for
+// that memory being accessible from the thread the list is used on
+unsafe impl Send for List {}
+
+impl List {
+    pub const fn new() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn front(&self) -> Option<NonNull<Link>> {
+        self.head
+    }
+
+    pub fn back(&self) -> Option<NonNull<Link>> {
+        self.tail
+    }
+
+    /// Adds `link` at the front of the list.
+    ///
+    /// # Safety
+    ///
+    /// See [`List`].
+    pub unsafe fn push_front(&mut self, mut link: NonNull<Link>) {
+        unsafe {
+            *link.as_mut() = Link {
+                next: self.head,
+                prev: None,
+            };
+
+            match self.head {
+                Some(mut head) => head.as_mut().prev = Some(link),
+                None => self.tail = Some(link),
+            }
+        }
+
+        self.head = Some(link);
+        self.len += 1;
+    }
+
+    /// Adds `link` at the back of the list.
+    ///
+    /// # Safety
+    ///
+    /// See [`List`].
+    pub unsafe fn push_back(&mut self, mut link: NonNull<Link>) {
+        unsafe {
+            *link.as_mut() = Link {
+                next: None,
+                prev: self.tail,
+            };
+
+            match self.tail {
+                Some(mut tail) => tail.as_mut().next = Some(link),
+                None => self.head = Some(link),
+            }
+        }
+
+        self.tail = Some(link);
+        self.len += 1;
+    }
+
+    /// Takes the first link off the list.
+    pub fn pop_front(&mut self) -> Option<NonNull<Link>> {
+        let head = self.head?;
+        unsafe { self.remove(head) };
+        Some(head)
+    }
+
+    /// Takes the last link off the list.
+    pub fn pop_back(&mut self) -> Option<NonNull<Link>> {
+        let tail = self.tail?;
+        unsafe { self.remove(tail) };
+        Some(tail)
+    }
+
+    /// Unlinks `link` from the list, wherever it is.
+    ///
+    /// # Safety
+    ///
+    /// `link` must be on this list.
+    pub unsafe fn remove(&mut self, mut link: NonNull<Link>) {
+        let Link { next, prev } = unsafe { ptr::replace(link.as_mut(), Link::new()) };
+
+        unsafe {
+            match prev {
+                Some(mut prev) => prev.as_mut().next = next,
+                None => self.head = next,
+            }
+            match next {
+                Some(mut next) => next.as_mut().prev = prev,
+                None => self.tail = prev,
+            }
+        }
+
+        self.len -= 1;
+    }
+
+    /// Links of the list from front to back. The list can't be changed while iterating, use
+    /// [`next`](Self::next) to walk it while unlinking.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            link: self.head,
+            list: PhantomData,
+        }
+    }
+
+    /// The link following `link`.
+    ///
+    /// # Safety
+    ///
+    /// `link` must be on this list.
+    pub unsafe fn next(&self, link: NonNull<Link>) -> Option<NonNull<Link>> {
+        unsafe { link.as_ref().next }
+    }
+}
+
+impl Default for List {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Iterator over the links of a [`List`], see [`List::iter`].
+pub struct Iter<'a> {
+    link: Option<NonNull<Link>>,
+    list: PhantomData<&'a List>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = NonNull<Link>;
+
+    fn next(&mut self) -> Option<NonNull<Link>> {
+        let link = self.link?;
+        // SAFETY: links on the list are valid while it's borrowed
+        self.link = unsafe { link.as_ref().next };
+        Some(link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links<const N: usize>() -> [Link; N] {
+        [(); N].map(|_| Link::new())
+    }
+
+    fn contents(list: &List, links: &[Link]) -> Vec<usize> {
+        list.iter()
+            .map(|link| {
+                links
+                    .iter()
+                    .position(|val| ptr::eq(val, link.as_ptr()))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut links = links::<3>();
+        let [a, b, c] = links.each_mut().map(NonNull::from);
+        let mut list = List::new();
+
+        unsafe {
+            list.push_front(b);
+            list.push_front(a);
+            list.push_back(c);
+        }
+        assert_eq!(list.len(), 3);
+        assert_eq!(contents(&list, &links), [0, 1, 2]);
+
+        assert_eq!(list.pop_back(), Some(c));
+        assert_eq!(list.pop_front(), Some(a));
+        assert_eq!(list.front(), Some(b));
+        assert_eq!(list.back(), Some(b));
+
+        assert_eq!(list.pop_front(), Some(b));
+        assert!(list.is_empty());
+        assert_eq!(list.pop_back(), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut links = links::<4>();
+        let nodes = links.each_mut().map(NonNull::from);
+        let mut list = List::new();
+
+        for node in nodes {
+            unsafe { list.push_back(node) };
+        }
+
+        // from the middle, the front and the back
+        unsafe {
+            list.remove(nodes[1]);
+            list.remove(nodes[0]);
+            list.remove(nodes[3]);
+        }
+        assert_eq!(contents(&list, &links), [2]);
+        assert_eq!(list.len(), 1);
+
+        // removed links can be added again
+        unsafe { list.push_front(nodes[1]) };
+        assert_eq!(contents(&list, &links), [1, 2]);
+    }
+
+    #[test]
+    fn test_links_in_blocks() {
+        let mut memory = [0u64; 16];
+        let blocks = [0, 4, 8, 12].map(|offset| memory[offset..].as_mut_ptr() as *mut u8);
+        let mut list = List::new();
+
+        for block in blocks {
+            unsafe { list.push_front(Link::init(block)) };
+        }
+
+        // walk the list, unlinking every other block
+        let mut link = list.front();
+        while let Some(val) = link {
+            link = unsafe { list.next(val) };
+            if let Some(next) = link {
+                link = unsafe { list.next(next) };
+                unsafe { list.remove(next) };
+            }
+        }
+
+        let left: Vec<_> = list.iter().map(|link| link.as_ptr() as *mut u8).collect();
+        assert_eq!(left, [blocks[3], blocks[1]]);
+    }
+}
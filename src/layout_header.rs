@@ -0,0 +1,62 @@
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr;
+
+/// Size and alignment of an allocation, stored right before the pointer handed out, for the
+/// wrappers that need them back when the block is freed.
+pub struct LayoutHeader {
+    pub size: usize,
+    pub align: usize,
+}
+
+const HEADER_SIZE: usize = size_of::<LayoutHeader>();
+
+impl LayoutHeader {
+    // distance between the start of the block and the pointer handed out
+    fn offset(align: usize) -> usize {
+        if align > HEADER_SIZE {
+            align
+        } else {
+            HEADER_SIZE
+        }
+    }
+
+    /// Layout of a block holding the header followed by `layout`.
+    pub fn block_layout(layout: Layout) -> Option<Layout> {
+        let align = layout.align().max(align_of::<LayoutHeader>());
+        let size = layout.size().checked_add(Self::offset(align))?;
+
+        Layout::from_size_align(size, align).ok()
+    }
+
+    /// Writes the header into a block allocated with `block_layout(layout)`, returns the
+    /// pointer to hand out.
+    pub unsafe fn write(block: *mut u8, layout: Layout) -> *mut u8 {
+        let align = layout.align().max(align_of::<LayoutHeader>());
+
+        let ptr = block as usize + Self::offset(align);
+        let header = LayoutHeader {
+            size: layout.size(),
+            align: layout.align(),
+        };
+        unsafe { ptr::write((ptr - HEADER_SIZE) as *mut LayoutHeader, header) };
+
+        ptr as *mut u8
+    }
+
+    /// Reads the header of a pointer returned by [`LayoutHeader::write`].
+    pub unsafe fn read(ptr: *mut u8) -> Self {
+        unsafe { ptr::read((ptr as usize - HEADER_SIZE) as *const LayoutHeader) }
+    }
+
+    /// Returns the block `ptr` was handed out from and the layout it was allocated with.
+    pub fn block(&self, ptr: *mut u8) -> (*mut u8, Layout) {
+        let align = self.align.max(align_of::<LayoutHeader>());
+        let offset = Self::offset(align);
+
+        // the layout was valid when the block was allocated
+        let layout = unsafe { Layout::from_size_align_unchecked(self.size + offset, align) };
+
+        ((ptr as usize - offset) as *mut u8, layout)
+    }
+}
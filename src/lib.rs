@@ -1,14 +1,182 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
 extern crate alloc;
 
+#[macro_use]
+mod macros;
+
+mod aligned;
 mod arena;
+#[cfg(feature = "linear")]
+mod bump_down;
+mod dyn_global;
+#[cfg(all(feature = "mmap", unix))]
+mod electric_fence;
+mod emplace;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod handle;
+#[cfg(feature = "cortex-m")]
+mod interrupt_free;
+pub mod intrusive;
+#[cfg(any(feature = "ffi", feature = "validate-layout"))]
+mod layout_header;
+#[cfg(feature = "linear")]
 mod linear_arena;
+#[cfg(any(feature = "pool", feature = "freelist"))]
+mod link;
+#[cfg(feature = "freelist")]
 mod linked_list;
+#[cfg(feature = "pool")]
+mod magazine;
+mod markers;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "mte")]
+mod mte;
+#[cfg(feature = "freelist")]
+mod multi_region;
+mod once;
+#[cfg(feature = "oom-handler")]
+pub mod oom;
+mod per_cpu;
+#[cfg(feature = "pool")]
 mod pool;
+pub mod prelude;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+mod published;
+#[cfg(all(feature = "quarantine", any(feature = "pool", feature = "freelist")))]
+mod quarantine;
+mod range_table;
+#[cfg(feature = "linear")]
+mod request_pool;
+#[cfg(all(
+    feature = "std",
+    any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )
+))]
+mod save_state;
+#[cfg(any(feature = "linear", feature = "stack"))]
+mod scoped;
+#[cfg(all(feature = "pool", feature = "freelist"))]
+mod segregated;
+mod seq_lock;
+#[cfg(feature = "freelist")]
+mod sharded;
+mod single_threaded;
 mod spin_lock;
+#[cfg(feature = "stack")]
 mod stack;
+mod stats;
+#[cfg(feature = "std")]
+mod system_fallback;
+#[cfg(feature = "tags")]
+pub mod tags;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+#[cfg(feature = "trace")]
+pub mod trace;
+mod uninit;
 mod utils;
+#[cfg(feature = "valgrind")]
+mod valgrind;
+#[cfg(feature = "validate-layout")]
+mod validate_layout;
+#[cfg(all(feature = "mmap", unix))]
+mod virtual_arena;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub mod wasm;
+mod zeroable;
 
-pub use arena::Arena;
-pub use spin_lock::SpinLock;
+pub use aligned::AllocAligned;
+pub use arena::{Arena, Region, Split};
+#[cfg(feature = "linear")]
+pub use bump_down::BumpDownAllocator;
+pub use dyn_global::{Backend, DynGlobalAllocator};
+#[cfg(all(feature = "mmap", unix))]
+pub use electric_fence::ElectricFence;
+pub use emplace::AllocWith;
+pub use error::AllocError;
+pub use handle::AllocatorHandle;
+#[cfg(feature = "cortex-m")]
+pub use interrupt_free::InterruptFree;
+#[cfg(feature = "linear")]
+pub use linear_arena::{ArenaAllocator, ArenaBox, ChildArena, FrozenArena};
+#[cfg(all(feature = "freelist", feature = "heap-walk"))]
+pub use linked_list::LiveBlocks;
+#[cfg(feature = "freelist")]
+pub use linked_list::{
+    FreeBlocks, FreeListAllocator, FreeListBuilder, PlacementPolicy, SearchBounds,
+};
+#[cfg(feature = "pool")]
+pub use magazine::MagazineCache;
+pub use markers::{AssertSend, AssertSync};
+#[cfg(feature = "freelist")]
+pub use multi_region::MultiRegionAllocator;
+pub use once::Once;
+pub use per_cpu::PerCpu;
+#[cfg(feature = "pool")]
+pub use pool::{PoolAllocator, DEBUG_TRACKED_CHUNKS};
+pub use published::{AllocStats, Published};
+#[cfg(all(feature = "quarantine", any(feature = "pool", feature = "freelist")))]
+pub use quarantine::{POISON_BYTE, QUARANTINE_CAPACITY};
+pub use range_table::RangeTable;
+#[cfg(feature = "linear")]
+pub use request_pool::{PooledArena, RequestArenaPool};
+#[cfg(all(
+    feature = "std",
+    any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )
+))]
+pub use save_state::{SaveState, RELOCATION_ALIGN};
+#[cfg(any(feature = "linear", feature = "stack"))]
+pub use scoped::{Resettable, ScopedArena, Snapshot};
+#[cfg(all(feature = "pool", feature = "freelist"))]
+pub use segregated::SegregatedAllocator;
+pub use seq_lock::SeqLock;
+#[cfg(feature = "freelist")]
+pub use sharded::ShardedAllocator;
+pub use single_threaded::SingleThreaded;
+#[cfg(feature = "lock-stats")]
+pub use spin_lock::LockStats;
+#[cfg(feature = "std")]
+pub use spin_lock::Yield;
+pub use spin_lock::{
+    Bounded, Guard, MappedGuard, Relax, RwSpinLock, Spin, SpinLock, SpinTimeout, Wfe,
+};
+#[cfg(feature = "stack")]
+pub use stack::{StackAllocator, StackFreeError, DEFERRED_FREES};
+#[cfg(feature = "std")]
+pub use system_fallback::WithSystemFallback;
+#[cfg(feature = "tags")]
+pub use tags::Tagged;
+pub use uninit::{header_with_slice_layout, AllocUninit};
+#[cfg(feature = "validate-layout")]
+pub use validate_layout::ValidateLayout;
+#[cfg(all(feature = "mmap", unix))]
+pub use virtual_arena::VirtualArena;
+#[cfg(all(feature = "mmap", target_os = "linux"))]
+pub use virtual_arena::{HugePages, HUGE_PAGE_SIZE};
+pub use zeroable::{AllocZeroedSlice, Zeroable};
 
 pub const ARENA_SIZE: usize = 128 * 1024;
+
+/// Alignment of the arenas and the allocator locks, so they don't share cache lines with each
+/// other or with neighbouring statics. 64 bytes unless the `cache-line-32` or `cache-line-128`
+/// feature picks another size.
+#[cfg(not(any(feature = "cache-line-32", feature = "cache-line-128")))]
+pub const CACHE_LINE_SIZE: usize = 64;
+#[cfg(all(feature = "cache-line-32", not(feature = "cache-line-128")))]
+pub const CACHE_LINE_SIZE: usize = 32;
+#[cfg(feature = "cache-line-128")]
+pub const CACHE_LINE_SIZE: usize = 128;
@@ -1,72 +1,459 @@
-use super::utils::align_forward;
-use super::{Arena, SpinLock, ARENA_SIZE};
+#[cfg(feature = "metrics")]
+use super::metrics::{Metered, Usage};
+#[cfg(feature = "std")]
+use super::save_state::{restore_arena, save_arena, SaveState};
+use super::scoped::{Resettable, Snapshot};
+use super::utils::{align_forward, init_block, release_block, zeroed};
+#[cfg(feature = "valgrind")]
+use super::valgrind;
+use super::{Region, Relax, SingleThreaded, Spin, SpinLock};
 use core::alloc::{GlobalAlloc, Layout};
-use core::ptr;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
 
 pub struct ArenaAllocator {
-    arena: Arena,
+    arena: Region,
     curr_offset: usize,
+    // live `ArenaBox`es, and the end of the highest of them, the arena can't be reset below it
+    pinned: usize,
+    pinned_end: usize,
+    // given the memory released by a reset, see `with_purge_fn`
+    purge: Option<fn(Region)>,
 }
 
 impl ArenaAllocator {
-    pub const fn new() -> Self {
+    pub const fn new(arena: Region) -> Self {
         ArenaAllocator {
-            arena: Arena::new(),
+            arena,
             curr_offset: 0,
+            pinned: 0,
+            pinned_end: 0,
+            purge: None,
         }
     }
-}
 
-unsafe impl GlobalAlloc for SpinLock<ArenaAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // Start of the critical section
-        let guard = self.lock();
+    /// Sets the function given the memory released whenever the arena is reset, rewound or
+    /// restored, e.g. `VirtualArena::purge` (feature `mmap`) to hand the physical
+    /// pages back to the OS so the resident memory of a server drops after a spike of requests.
+    pub const fn with_purge_fn(mut self, purge: fn(Region)) -> Self {
+        self.purge = Some(purge);
+        self
+    }
 
-        let allocator = guard.get_mut();
+    /// Bytes of metadata every allocation costs, on top of the padding needed for its alignment.
+    pub const fn overhead_per_alloc() -> usize {
+        0
+    }
+
+    /// Smallest number of bytes an allocation takes up in the arena.
+    pub const fn min_block_size() -> usize {
+        0
+    }
 
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
         // start position of the new allocation
-        let start = align_forward(
-            allocator.curr_offset + allocator.arena.start(),
-            layout.align(),
-        );
+        let start = match align_forward(self.curr_offset + self.arena.start(), layout.align()) {
+            Some(start) => start,
+            None => return ptr::null_mut(),
+        };
 
         // end position of the new allocation
         let end = match start.checked_add(layout.size()) {
             Some(end) => end,
-            None => {
-                SpinLock::unlock(guard);
-                return ptr::null_mut();
-            }
+            None => return ptr::null_mut(),
         };
 
-        if end > start + ARENA_SIZE {
+        if end > self.arena.end() {
             // arena out of memory
-            SpinLock::unlock(guard);
             return ptr::null_mut();
         }
 
         // update the offset
-        allocator.curr_offset = end - allocator.arena.start();
-
-        SpinLock::unlock(guard);
+        self.curr_offset = end - self.arena.start();
 
         start as *mut u8
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        // arena allocator doesn't allow to free certain blocks of memory
+    /// Opens a child arena over the memory that's still free. Everything allocated through the
+    /// child, and through its own children, is released at once when it's reset or dropped, so
+    /// nested lifetimes (level, frame, task) share one region:
+    ///
+    /// ```ignore
+    /// let mut allocator = LEVEL.lock();
+    /// let mut frame = allocator.get_mut().child();
+    ///
+    /// let mesh = frame.alloc(layout);
+    /// for task in tasks {
+    ///     let mut task = frame.child();
+    ///     let scratch = task.alloc(scratch_layout);
+    /// } // the scratch memory is released here
+    /// ```
+    ///
+    /// The child borrows the allocator, so the parent can't allocate while it's open.
+    pub fn child(&mut self) -> ChildArena<'_> {
+        ChildArena {
+            start_offset: self.curr_offset,
+            parent: self,
+        }
+    }
+
+    /// Freezes the arena after a build phase, handing out read-only access to everything
+    /// allocated so far that any number of threads can share without locking:
+    ///
+    /// ```ignore
+    /// let mut allocator = ASSETS.lock();
+    /// let mesh = load_mesh(allocator.get_mut());
+    ///
+    /// let frozen = allocator.get_mut().freeze();
+    /// thread::scope(|scope| {
+    ///     for _ in 0..4 {
+    ///         scope.spawn(|| render(unsafe { frozen.get(mesh) }));
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// The frozen arena borrows the allocator, so nothing can be allocated (or reset) until it's
+    /// dropped, which unfreezes it:
+    ///
+    /// ```compile_fail
+    /// # use rsalloc::{Arena, ArenaAllocator, Resettable};
+    /// # use core::alloc::Layout;
+    /// static ARENA: Arena<1024> = Arena::new();
+    /// let mut allocator = ArenaAllocator::new(unsafe { ARENA.region() });
+    ///
+    /// let frozen = allocator.freeze();
+    /// allocator.alloc(Layout::new::<u32>());
+    /// # let _ = frozen.len();
+    /// ```
+    pub fn freeze(&mut self) -> FrozenArena<'_> {
+        FrozenArena {
+            arena: self.arena,
+            len: self.curr_offset,
+            allocator: PhantomData,
+        }
+    }
+
+    /// Releases every block allocated so far.
+    ///
+    /// # Safety
+    ///
+    /// None of the released blocks may be used afterwards.
+    pub unsafe fn reset(&mut self) {
+        self.release_to(0);
+    }
+
+    /// Saves the current state of the allocator, see [`restore`](Self::restore).
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.arena, 0, self.curr_offset)
+    }
+
+    /// Releases every block allocated after `snapshot` was taken.
+    ///
+    /// # Safety
+    ///
+    /// `snapshot` must have been taken from this allocator, and none of the released blocks may
+    /// be used afterwards.
+    pub unsafe fn restore(&mut self, snapshot: Snapshot) {
+        let (_, offset) = snapshot.offsets(self.arena, self.curr_offset);
+        self.release_to(offset);
+    }
+
+    // frees everything allocated past `offset`
+    fn release_to(&mut self, offset: usize) {
+        assert!(
+            offset >= self.pinned_end,
+            "arena reset while pinned allocations are live"
+        );
+
+        let start = (self.arena.start() + offset) as *mut u8;
+        unsafe { release_block(start, self.curr_offset - offset) };
+
+        if let Some(purge) = self.purge.filter(|_| self.curr_offset > offset) {
+            purge(unsafe { Region::from_raw_parts(start, self.curr_offset - offset) });
+        }
+
+        self.curr_offset = offset;
+    }
+}
+
+impl Resettable for ArenaAllocator {
+    type Checkpoint = usize;
+
+    fn checkpoint(&self) -> usize {
+        self.curr_offset
+    }
+
+    unsafe fn rewind(&mut self, checkpoint: usize) {
+        self.release_to(checkpoint);
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.allocate(layout)
+    }
+}
+
+/// A nested lifetime over an [`ArenaAllocator`], see [`ArenaAllocator::child`].
+pub struct ChildArena<'a> {
+    parent: &'a mut ArenaAllocator,
+    // offset of the parent when the child was opened
+    start_offset: usize,
+}
+
+impl ChildArena<'_> {
+    /// Allocates a block that lives until the child is reset or dropped, null if the parent's
+    /// arena is out of memory.
+    pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.parent.allocate(layout);
+
+        unsafe { init_block(ptr, layout.size()) }
+    }
+
+    /// Opens a grandchild, released independently of (and before) this child.
+    pub fn child(&mut self) -> ChildArena<'_> {
+        self.parent.child()
+    }
+
+    /// Releases everything allocated through the child so far.
+    pub fn reset(&mut self) {
+        self.parent.release_to(self.start_offset);
+    }
+
+    /// Bytes allocated through the child, padding included.
+    pub fn used(&self) -> usize {
+        self.parent.curr_offset - self.start_offset
+    }
+}
+
+impl Drop for ChildArena<'_> {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
+
+/// The blocks of an [`ArenaAllocator`] frozen after a build phase, see
+/// [`ArenaAllocator::freeze`].
+#[derive(Debug, Clone, Copy)]
+pub struct FrozenArena<'a> {
+    arena: Region,
+    // bytes allocated when the arena was frozen
+    len: usize,
+    allocator: PhantomData<&'a ArenaAllocator>,
+}
+
+impl<'a> FrozenArena<'a> {
+    /// Bytes allocated when the arena was frozen, padding included.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Everything allocated when the arena was frozen, from the start of the arena.
+    ///
+    /// # Safety
+    ///
+    /// Every byte has to be initialized, the padding between blocks and inside the values
+    /// included.
+    pub unsafe fn bytes(&self) -> &'a [u8] {
+        unsafe { core::slice::from_raw_parts(self.arena.start() as *const u8, self.len) }
+    }
+
+    /// The value at `ptr`, allocated from the arena before it was frozen.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to an initialized `T`.
+    ///
+    /// # Panics
+    ///
+    /// If `ptr` isn't aligned, or the value isn't within the frozen blocks.
+    pub unsafe fn get<T: Sync>(&self, ptr: *const T) -> &'a T {
+        unsafe { &self.slice(ptr, 1)[0] }
+    }
+
+    /// The `len` values starting at `ptr`, allocated from the arena before it was frozen.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to `len` initialized values of `T`.
+    ///
+    /// # Panics
+    ///
+    /// If `ptr` isn't aligned, or the values aren't within the frozen blocks.
+    pub unsafe fn slice<T: Sync>(&self, ptr: *const T, len: usize) -> &'a [T] {
+        let start = ptr as usize;
+        let end = size_of::<T>()
+            .checked_mul(len)
+            .and_then(|size| start.checked_add(size));
+
+        assert!(
+            start >= self.arena.start()
+                && end.is_some_and(|end| end <= self.arena.start() + self.len),
+            "value isn't within the frozen arena"
+        );
+        assert!(ptr.is_aligned(), "value isn't aligned");
+
+        unsafe { core::slice::from_raw_parts(ptr, len) }
+    }
+}
+
+impl<R: Relax> SpinLock<ArenaAllocator, R> {
+    /// Moves `value` into the arena and pins it there, e.g. a future or a node of an intrusive
+    /// list, returns `None` if the arena is out of memory.
+    ///
+    /// The value is dropped with the box, but its memory is only reclaimed when the arena is
+    /// reset. Resetting the arena (through a child arena, a scope or a snapshot) below a live
+    /// box panics, so the memory of a pinned value is never reused before it's dropped. A box
+    /// that's leaked keeps the arena from being reset below it for good.
+    pub fn alloc_pinned<T>(&self, value: T) -> Option<Pin<ArenaBox<'_, T, R>>> {
+        let layout = Layout::new::<T>();
+
+        let ptr = self.lock_with(|allocator| {
+            let ptr = allocator.allocate(layout);
+
+            if !ptr.is_null() {
+                allocator.pinned += 1;
+                allocator.pinned_end = allocator.curr_offset;
+            }
+            ptr
+        });
+
+        let ptr = NonNull::new(ptr as *mut T)?;
+        unsafe { ptr.as_ptr().write(value) };
+
+        // SAFETY: the memory isn't reused until the box is dropped, which drops the value
+        Some(unsafe {
+            Pin::new_unchecked(ArenaBox {
+                ptr,
+                allocator: self,
+            })
+        })
+    }
+}
+
+/// A value owned by an [`ArenaAllocator`], see [`alloc_pinned`](SpinLock::alloc_pinned).
+pub struct ArenaBox<'a, T, R: Relax = Spin> {
+    ptr: NonNull<T>,
+    allocator: &'a SpinLock<ArenaAllocator, R>,
+}
+
+// SAFETY: the box owns its value like a `Box` does, and the allocator is shared behind its lock
+unsafe impl<T: Send, R: Relax> Send for ArenaBox<'_, T, R> {}
+unsafe impl<T: Sync, R: Relax> Sync for ArenaBox<'_, T, R> {}
+
+impl<T, R: Relax> Deref for ArenaBox<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T, R: Relax> DerefMut for ArenaBox<'_, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T, R: Relax> Drop for ArenaBox<'_, T, R> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.ptr.as_ptr());
+            release_block(self.ptr.as_ptr() as *mut u8, size_of::<T>());
+        }
+
+        self.allocator.lock_with(|allocator| {
+            allocator.pinned -= 1;
+
+            // the arena can be reset freely once the last box is gone
+            if allocator.pinned == 0 {
+                allocator.pinned_end = 0;
+            }
+        });
+    }
+}
+
+impl fmt::Debug for ArenaAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArenaAllocator")
+            .field("used", &self.curr_offset)
+            .field("free", &(self.arena.size() - self.curr_offset))
+            .field("pinned", &self.pinned)
+            .finish()
+    }
+}
+
+// only the blocks up to the offset are saved
+#[cfg(feature = "std")]
+impl SaveState for ArenaAllocator {
+    fn save_state<W: Write>(&self, writer: W) -> io::Result<()> {
+        save_arena(writer, self.arena, &[self.curr_offset], self.curr_offset)
+    }
+
+    unsafe fn restore_state<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        self.release_to(0);
+
+        let [curr_offset] = unsafe { restore_arena(reader, self.arena, true)? };
+        self.curr_offset = curr_offset;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Relax> Metered for SpinLock<ArenaAllocator, R> {
+    fn usage(&self) -> Usage {
+        self.lock_with(|allocator| Usage {
+            used: allocator.curr_offset,
+            capacity: allocator.arena.size(),
+        })
     }
 }
 
+impl_global_alloc!(ArenaAllocator, {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .lock_with_or_timeout(|allocator| allocator.allocate(layout))
+            .unwrap_or(ptr::null_mut());
+
+        #[cfg(feature = "valgrind")]
+        let ptr = valgrind::malloc_like(ptr, layout.size());
+
+        unsafe { init_block(ptr, layout.size()) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "valgrind")]
+        valgrind::free_like(ptr);
+
+        // arena allocator doesn't allow to free certain blocks of memory
+        unsafe { release_block(ptr, layout.size()) };
+    }
+});
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Arena;
     use core::alloc::Layout;
 
-    static GLOBAL_ARENA: SpinLock<ArenaAllocator> = SpinLock::new(ArenaAllocator::new());
-
     #[test]
     fn single_alignment() {
+        static ARENA: Arena = Arena::new();
+        static GLOBAL_ARENA: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
         let layout = Layout::new::<u32>();
 
         let ptr_1 = unsafe { GLOBAL_ARENA.alloc(layout) };
@@ -87,6 +474,10 @@ mod tests {
 
     #[test]
     fn multiple_alignment() {
+        static ARENA: Arena = Arena::new();
+        static GLOBAL_ARENA: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
         let layout_u32 = Layout::new::<u32>();
         let layout_u64 = Layout::new::<u64>();
 
@@ -107,4 +498,227 @@ mod tests {
         // align of u64 => 8
         assert!(ptr_1 as usize + 8 == ptr_2 as usize);
     }
+
+    #[test]
+    fn test_lock_released_under_contention() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
+        // the arena runs out early on, so most allocations take the out of memory path
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..1000 {
+                        unsafe { ALLOC.alloc(Layout::new::<u64>()) };
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(ALLOC.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_child_arenas() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
+        let layout = Layout::new::<[u64; 4]>();
+        let level_block = unsafe { ALLOC.alloc(layout) };
+
+        let mut allocator = ALLOC.lock();
+        let mut frame = allocator.get_mut().child();
+
+        let frame_block = frame.alloc(layout);
+        assert_eq!(frame_block as usize, level_block as usize + 32);
+
+        {
+            let mut task = frame.child();
+            let task_block = task.alloc(layout);
+            assert_eq!(task_block as usize, frame_block as usize + 32);
+            assert_eq!(task.used(), 32);
+        }
+
+        // the task's memory was released, the frame's wasn't
+        assert_eq!(frame.used(), 32);
+        assert_eq!(frame.alloc(layout) as usize, frame_block as usize + 32);
+
+        frame.reset();
+        assert_eq!(frame.used(), 0);
+        assert_eq!(frame.alloc(layout), frame_block);
+
+        // the child is bounded by what the parent has left
+        assert!(frame.alloc(Layout::new::<[u8; 1024]>()).is_null());
+
+        drop(frame);
+        assert_eq!(allocator.get().curr_offset, 32);
+        SpinLock::unlock(allocator);
+    }
+
+    #[test]
+    fn test_freeze() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
+        let values = unsafe { ALLOC.alloc(Layout::new::<[u64; 4]>()) }.cast::<u64>();
+        for i in 0..4 {
+            unsafe { values.add(i).write(i as u64 * 10) };
+        }
+
+        let mut allocator = ALLOC.lock();
+        let frozen = allocator.get_mut().freeze();
+        assert_eq!(frozen.len(), 32);
+
+        // readers share the blocks without the lock
+        let shared = unsafe { frozen.slice(values, 4) };
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                scope.spawn(move || {
+                    assert_eq!(unsafe { *frozen.get(&shared[i]) }, i as u64 * 10);
+                    assert_eq!(shared, [0, 10, 20, 30]);
+                });
+            }
+        });
+        assert_eq!(unsafe { &frozen.bytes()[8..16] }, 10u64.to_ne_bytes());
+
+        // past the frozen blocks
+        let past = std::panic::catch_unwind(|| unsafe { frozen.get(values.wrapping_add(4)) });
+        assert!(past.is_err());
+
+        // dropping it unfreezes the arena
+        assert_eq!(
+            allocator.get_mut().allocate(Layout::new::<u64>()) as usize,
+            values as usize + 32
+        );
+        SpinLock::unlock(allocator);
+    }
+
+    #[test]
+    fn test_huge_layout() {
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = ArenaAllocator::new(unsafe { ARENA.region() });
+
+        // fails whether or not the end of the block overflows
+        let layout = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+        assert!(allocator.allocate(layout).is_null());
+
+        let layout = Layout::from_size_align(isize::MAX as usize / 2, 4096).unwrap();
+        assert!(allocator.allocate(layout).is_null());
+
+        assert!(!allocator.allocate(Layout::new::<u64>()).is_null());
+    }
+
+    #[test]
+    fn test_pinned_allocations() {
+        use core::marker::PhantomPinned;
+        use std::rc::Rc;
+
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
+        struct Node {
+            value: Rc<u32>,
+            _pinned: PhantomPinned,
+        }
+
+        let counter = Rc::new(1);
+        let node = ALLOC
+            .alloc_pinned(Node {
+                value: counter.clone(),
+                _pinned: PhantomPinned,
+            })
+            .unwrap();
+        assert_eq!(*node.value, 1);
+        assert_eq!(Rc::strong_count(&counter), 2);
+
+        let mut allocator = ALLOC.lock();
+        let snapshot = allocator.get().snapshot();
+
+        // the arena can still be reset above the pinned value
+        {
+            let mut child = allocator.get_mut().child();
+            child.alloc(Layout::new::<u64>());
+        }
+        SpinLock::unlock(allocator);
+
+        // but not below it
+        let result =
+            std::panic::catch_unwind(|| ALLOC.lock_with(|allocator| allocator.release_to(0)));
+        assert!(result.is_err());
+
+        // dropping the box drops the value, and lifts the restriction
+        drop(node);
+        assert_eq!(Rc::strong_count(&counter), 1);
+        ALLOC.lock_with(|allocator| unsafe { allocator.restore(snapshot) });
+        ALLOC.lock_with(|allocator| allocator.release_to(0));
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        static ARENA: Arena<1024> = Arena::new();
+        static OTHER_ARENA: Arena<1024> = Arena::new();
+        let mut allocator = ArenaAllocator::new(unsafe { ARENA.region() });
+
+        let layout = Layout::new::<[u64; 4]>();
+        let kept = allocator.allocate(layout);
+
+        let snapshot = allocator.snapshot();
+        let speculative = allocator.allocate(layout);
+        allocator.allocate(Layout::new::<[u8; 100]>());
+
+        // the alternative didn't work out, everything since the snapshot is released
+        unsafe { allocator.restore(snapshot) };
+        assert_eq!(allocator.allocate(layout), speculative);
+        assert_eq!(speculative as usize, kept as usize + 32);
+
+        // a snapshot can be restored more than once
+        unsafe { allocator.restore(snapshot) };
+        assert_eq!(allocator.curr_offset, 32);
+
+        if cfg!(debug_assertions) {
+            let mut other = ArenaAllocator::new(unsafe { OTHER_ARENA.region() });
+            other.allocate(Layout::new::<[u8; 512]>());
+
+            let result = std::panic::catch_unwind(move || unsafe { other.restore(snapshot) });
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_purge_fn() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static ARENA: Arena<1024> = Arena::new();
+        static PURGED: AtomicUsize = AtomicUsize::new(0);
+
+        let mut allocator =
+            ArenaAllocator::new(unsafe { ARENA.region() }).with_purge_fn(|region| {
+                assert_eq!(region.end(), unsafe { ARENA.region() }.start() + 96);
+                PURGED.fetch_add(region.size(), Ordering::Relaxed);
+            });
+
+        let layout = Layout::new::<[u64; 4]>();
+        allocator.allocate(layout);
+        let snapshot = allocator.snapshot();
+        allocator.allocate(Layout::new::<[u64; 8]>());
+
+        unsafe { allocator.restore(snapshot) };
+        assert_eq!(PURGED.load(Ordering::Relaxed), 64);
+
+        // nothing to purge when nothing was allocated since
+        unsafe { allocator.restore(snapshot) };
+        assert_eq!(PURGED.load(Ordering::Relaxed), 64);
+
+        allocator.allocate(Layout::new::<[u64; 8]>());
+        unsafe { allocator.reset() };
+        assert_eq!(PURGED.load(Ordering::Relaxed), 160);
+    }
 }
@@ -0,0 +1,84 @@
+use super::Region;
+
+/// Encodes the `next` links that the free nodes store inside the arena.
+///
+/// Links are offsets of the nodes from the start of the arena, off by one so that 0 stands for
+/// the end of the list. With the `safe-linking` feature they're XOR-ed with a per-allocator
+/// secret (like glibc's safe-linking) and checked to point inside the arena when decoded, so
+/// overwriting a free node can't redirect the allocator to arbitrary memory. A link failing the
+/// check aborts the program.
+#[derive(Clone, Copy)]
+pub struct LinkCodec {
+    #[cfg_attr(not(feature = "safe-linking"), allow(dead_code))]
+    key: usize,
+    #[cfg_attr(not(feature = "safe-linking"), allow(dead_code))]
+    start: usize,
+    #[cfg_attr(not(feature = "safe-linking"), allow(dead_code))]
+    size: usize,
+}
+
+impl LinkCodec {
+    pub fn new(arena: &Region, secret: usize) -> Self {
+        Self {
+            // mix in the address bits of the arena, they're randomized under ASLR
+            key: secret ^ (arena.start() >> 12),
+            start: arena.start(),
+            size: arena.size(),
+        }
+    }
+
+    /// Encodes a link to the node at `offset` in the arena, `None` for the end of the list.
+    #[inline]
+    pub fn encode(&self, offset: Option<usize>) -> usize {
+        let link = offset.map_or(0, |offset| offset + 1);
+
+        #[cfg(feature = "safe-linking")]
+        let link = link ^ self.key;
+
+        link
+    }
+
+    /// Decodes a link to the offset of a `T` in the arena.
+    #[inline]
+    #[cfg_attr(
+        not(feature = "safe-linking"),
+        allow(clippy::extra_unused_type_parameters)
+    )]
+    pub fn decode<T>(&self, link: usize) -> Option<usize> {
+        #[cfg(feature = "safe-linking")]
+        let link = link ^ self.key;
+
+        let offset = link.checked_sub(1)?;
+
+        #[cfg(feature = "safe-linking")]
+        if offset > self.size.saturating_sub(core::mem::size_of::<T>())
+            || (self.start + offset) & (core::mem::align_of::<T>() - 1) != 0
+        {
+            corrupted();
+        }
+
+        Some(offset)
+    }
+}
+
+// the arena was overwritten, nothing in it can be trusted anymore: stop right away instead of
+// unwinding out of the global allocator, which could allocate on the way and re-enter it
+#[cfg(feature = "safe-linking")]
+#[cold]
+#[inline(never)]
+fn corrupted() -> ! {
+    #[cfg(any(test, feature = "std"))]
+    {
+        std::eprintln!("corrupted free list link");
+        std::process::abort();
+    }
+
+    // a panic can't unwind out of an `extern "C"` function, it aborts instead
+    #[cfg(not(any(test, feature = "std")))]
+    {
+        extern "C" fn abort() -> ! {
+            panic!("corrupted free list link");
+        }
+        abort()
+    }
+}
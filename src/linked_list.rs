@@ -1,364 +1,1412 @@
-use super::utils::{calc_padding_with_header, ref_as_usize};
-use super::{Arena, SpinLock};
+use super::link::LinkCodec;
+#[cfg(feature = "metrics")]
+use super::metrics::{Metered, Usage};
+#[cfg(feature = "mte")]
+use super::mte;
+#[cfg(feature = "quarantine")]
+use super::quarantine::Quarantine;
+#[cfg(feature = "std")]
+use super::save_state::{self, restore_arena, save_arena, SaveState};
+use super::utils::{
+    align_forward, calc_padding_with_header, init_block, release_block, unpoison, zeroed,
+    PackedHeader,
+};
+#[cfg(feature = "valgrind")]
+use super::valgrind;
+use super::{Region, Relax, SingleThreaded, SpinLock};
 use core::alloc::{GlobalAlloc, Layout};
-use core::mem::size_of;
+use core::fmt;
+use core::mem::{align_of, size_of};
 use core::ptr;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum PlacementPolicy {
+    #[default]
     FindFirst,
     FindBest,
 }
 
+/// Most free list nodes a single allocation and a single free visited so far.
+///
+/// The time an operation takes grows with the number of nodes it visits, so running the
+/// worst-case workload and reading these gives the bounds a WCET analysis starts from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SearchBounds {
+    pub alloc: usize,
+    pub dealloc: usize,
+}
+
+// the size of the block and the padding before the data (packed into a single word on 64-bit
+// targets), right before the data, with the links between the live blocks in front of it under
+// `heap-walk`
+#[derive(Clone, Copy)]
+#[repr(C)]
 struct AllocationHeader {
-    block_size: usize,
-    padding: usize,
+    #[cfg(feature = "heap-walk")]
+    walk: WalkLinks,
+    packed: PackedHeader,
 }
 
-pub struct FreeListAllocator<'a> {
-    arena: Arena,
+// the headers of the live blocks are chained into a list to walk them
+#[cfg(feature = "heap-walk")]
+#[derive(Clone, Copy)]
+struct WalkLinks {
+    // encoded by the allocator's `LinkCodec`
+    prev: usize,
+    next: usize,
+    tag: usize,
+}
 
-    head: Option<&'a FreeNode<'a>>,
-    policy: PlacementPolicy,
+// sizes and paddings are stored in units of this, under `compact-header` they're all multiples
+// of a word so a header covers `size_of::<usize>()` times more
+#[cfg(feature = "compact-header")]
+const GRAIN: usize = align_of::<FreeNode>();
+#[cfg(not(feature = "compact-header"))]
+const GRAIN: usize = 1;
+
+// the largest padding and block a header can describe
+const MAX_PADDING: usize = PackedHeader::MAX_PADDING.saturating_mul(GRAIN);
+const MAX_BLOCK_SIZE: usize = PackedHeader::MAX_SIZE.saturating_mul(GRAIN);
+
+impl AllocationHeader {
+    fn new(block_size: usize, padding: usize) -> Self {
+        debug_assert!(
+            block_size.is_multiple_of(GRAIN) && padding.is_multiple_of(GRAIN),
+            "block isn't made of whole words"
+        );
+
+        Self {
+            #[cfg(feature = "heap-walk")]
+            walk: WalkLinks {
+                prev: 0,
+                next: 0,
+                tag: 0,
+            },
+            packed: PackedHeader::new(block_size / GRAIN, padding / GRAIN),
+        }
+    }
+
+    fn block_size(self) -> usize {
+        self.packed.size() * GRAIN
+    }
+
+    fn padding(self) -> usize {
+        self.packed.padding() * GRAIN
+    }
+}
 
-    initialized: bool,
+pub struct FreeListAllocator {
+    arena: Region,
+
+    // offset of the first free node in the arena
+    head: Option<usize>,
+    policy: PlacementPolicy,
+    secret: usize,
+    #[cfg(feature = "quarantine")]
+    quarantine: Quarantine,
+
+    // freed blocks waiting to be coalesced into the list, when coalescing is deferred
+    pending: Option<usize>,
+    pending_len: usize,
+    coalesce_threshold: Option<usize>,
+    // the most recently freed block `(address, size)`, kept out of the list for a quick reuse
+    last_freed: Option<(usize, usize)>,
+
+    // asked for more memory when the arena is full, see `with_grow_fn`
+    grow: Option<fn(usize) -> Option<Region>>,
+
+    // most nodes a search may visit, `None` to search the whole list
+    search_limit: Option<usize>,
+    // blocks that would be left with less than this are only picked if nothing else fits
+    min_remainder: usize,
+    // smallest free block split off the end of an allocation, less is handed out with it
+    min_split_size: usize,
+    // every block is aligned to at least this, see `with_min_align`
+    min_align: usize,
+    // nodes visited by the current operation
+    visited: usize,
+    bounds: SearchBounds,
+
+    // offset of the header of the most recent live block, and the tag given to new ones
+    #[cfg(feature = "heap-walk")]
+    live: Option<usize>,
+    #[cfg(feature = "heap-walk")]
+    tag: usize,
+
+    // offset of the untouched end of the arena, free but kept out of the list, so a new
+    // allocator is ready to go without writing a node first
+    top: usize,
 }
 
-struct FreeNode<'a> {
-    next: Option<&'a FreeNode<'a>>,
+#[derive(Clone, Copy)]
+struct FreeNode {
+    // encoded by the allocator's `LinkCodec`
+    next: usize,
     block_size: usize,
 }
 
-impl FreeListAllocator<'_> {
-    pub const fn new(policy: PlacementPolicy) -> Self {
+// the padding in front of a block covers its header, and a freed block holds a node
+const _: () = assert!(FreeListAllocator::overhead_per_alloc() <= MAX_PADDING);
+const _: () = assert!(size_of::<FreeNode>() <= FreeListAllocator::min_block_size());
+
+impl FreeListAllocator {
+    pub const fn new(arena: Region, policy: PlacementPolicy) -> Self {
         Self {
-            arena: Arena::new(),
+            arena,
             head: None,
             policy,
-            initialized: false,
+            secret: 0,
+            #[cfg(feature = "quarantine")]
+            quarantine: Quarantine::new(0),
+            pending: None,
+            pending_len: 0,
+            coalesce_threshold: None,
+            last_freed: None,
+            grow: None,
+            search_limit: None,
+            min_remainder: 0,
+            min_split_size: size_of::<FreeNode>(),
+            min_align: 8,
+            visited: 0,
+            bounds: SearchBounds {
+                alloc: 0,
+                dealloc: 0,
+            },
+            #[cfg(feature = "heap-walk")]
+            live: None,
+            #[cfg(feature = "heap-walk")]
+            tag: 0,
+            top: 0,
         }
     }
 
-    fn init(&mut self) {
-        self.initialized = true;
+    /// Sets how a free block is picked for an allocation.
+    pub const fn with_policy(mut self, policy: PlacementPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
 
-        // write the free node into the arena
-        let node_addr = self.arena.start() as *mut FreeNode;
-        let node = FreeNode {
-            block_size: self.arena.size(),
-            next: None,
+    /// Starts building an allocator, see [`FreeListBuilder`].
+    pub const fn builder() -> FreeListBuilder {
+        FreeListBuilder::new()
+    }
+
+    /// Defers coalescing, freed blocks are set aside and only merged back into the list once
+    /// more than `threshold` of them piled up or an allocation can't be served otherwise. Frees
+    /// get much cheaper, at the cost of some fragmentation in the meantime.
+    pub const fn with_deferred_coalescing(mut self, threshold: usize) -> Self {
+        self.coalesce_threshold = Some(threshold);
+        self
+    }
+
+    /// Sets the function asked for more memory when an allocation doesn't fit, it's given the
+    /// number of bytes needed and returns a region of at least that size, or `None` if there's
+    /// no memory left, e.g. `rsalloc::wasm::grow_memory`.
+    ///
+    /// The list spans a single contiguous region, so only regions starting right at the end of
+    /// the arena can be added to it (any other region is given back as unusable), which is how
+    /// memory grows when nothing else claims it in between. An allocator created
+    /// [`empty`](Self::empty) takes its first region as the arena.
+    pub const fn with_grow_fn(mut self, grow: fn(usize) -> Option<Region>) -> Self {
+        self.grow = Some(grow);
+        self
+    }
+
+    /// Gives up on an allocation after visiting `max_nodes` nodes of the list, returning null
+    /// even if a block further down would have fit. Bounds the search for hard real-time use,
+    /// at the cost of failing allocations a full search would serve.
+    ///
+    /// Frees still walk the list to keep it sorted, they're bounded by the number of free
+    /// blocks, at most the size of the arena over [`min_block_size`](Self::min_block_size).
+    /// Deferred coalescing merges all the pending blocks at once, so it's best left off when
+    /// every operation has to be bounded.
+    pub const fn with_bounded_search(mut self, max_nodes: usize) -> Self {
+        self.search_limit = Some(max_nodes);
+        self
+    }
+
+    /// Steers the search away from blocks that a split would leave with less than
+    /// `min_remainder` bytes, such slivers only fit the smallest of allocations and pile up
+    /// between the live blocks. Exact fits, and blocks with enough left over, are preferred; a
+    /// block leaving a sliver is only picked when nothing else fits.
+    ///
+    /// Under [`FindBest`](PlacementPolicy::FindBest) the best fit among the preferred blocks
+    /// wins, under [`FindFirst`](PlacementPolicy::FindFirst) the first of them.
+    ///
+    /// Larger blocks are cut into sooner in exchange, so whether it pays off depends on the
+    /// workload, a trace recorded with the `trace` feature and replayed with and without it tells.
+    pub const fn with_min_remainder(mut self, min_remainder: usize) -> Self {
+        self.min_remainder = min_remainder;
+        self
+    }
+
+    /// Hands out the whole free block when splitting it would leave a free block of less than
+    /// `min_split_size` bytes, instead of adding a block to the list that hardly anything fits
+    /// in. The block keeps its true size in its header and is freed whole.
+    ///
+    /// Sizes below a free list node, the smallest block there can be, are rounded up to it.
+    pub const fn with_min_split_size(mut self, min_split_size: usize) -> Self {
+        if min_split_size > self.min_split_size {
+            self.min_split_size = min_split_size;
+        }
+        self
+    }
+
+    /// Lowest alignment a block can have, the one of a free list node (the size of a pointer).
+    pub const MIN_ALIGN: usize = align_of::<FreeNode>();
+
+    /// Aligns every block to at least `min_align` bytes, 8 by default. On 32-bit targets it can
+    /// go down to [`MIN_ALIGN`](Self::MIN_ALIGN), saving the padding small allocations would
+    /// otherwise take; lower values are raised to it.
+    ///
+    /// Whatever the alignment, a block holds at least a free list node, see
+    /// [`min_block_size`](Self::min_block_size).
+    ///
+    /// # Panics
+    ///
+    /// If `min_align` isn't a power of two.
+    pub const fn with_min_align(mut self, min_align: usize) -> Self {
+        assert!(
+            min_align.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+
+        self.min_align = if min_align < Self::MIN_ALIGN {
+            Self::MIN_ALIGN
+        } else {
+            min_align
         };
-        unsafe { ptr::write(node_addr, node) };
+        self
+    }
 
-        // make the head of the allocator point to the free node
-        self.head = Some(unsafe { &*node_addr });
+    /// Bytes of metadata every allocation costs, on top of the padding needed for its alignment
+    /// (blocks are aligned to at least 8 bytes, see [`with_min_align`](Self::with_min_align)).
+    ///
+    /// With `mte` blocks are aligned and sized to whole 16 byte granules, and the header takes up
+    /// a granule of its own.
+    pub const fn overhead_per_alloc() -> usize {
+        #[cfg(feature = "mte")]
+        return size_of::<AllocationHeader>().next_multiple_of(mte::GRANULE);
+
+        #[cfg(not(feature = "mte"))]
+        size_of::<AllocationHeader>()
     }
-}
 
-// iterates over the entire list and finds the best fit
-fn find_best<'a>(
-    node: &'a FreeNode<'a>,
-    size: usize,
-    align: usize,
-) -> (Option<&'a FreeNode<'a>>, Option<&'a FreeNode<'a>>, usize) {
-    let mut node = Some(node);
-    let mut prev_node: Option<&FreeNode> = None;
+    /// Smallest number of bytes an allocation takes up in the arena, a freed block has to be
+    /// able to hold a node of the free list.
+    pub const fn min_block_size() -> usize {
+        // a block has to hold a node once it's freed
+        #[cfg(not(feature = "mte"))]
+        let data = size_of::<FreeNode>();
+        #[cfg(feature = "mte")]
+        let data = mte::GRANULE;
 
-    let mut prev_to_best: Option<&FreeNode> = None;
-    let mut best_node: Option<&FreeNode> = None;
+        Self::overhead_per_alloc() + data
+    }
 
-    let mut padding: usize = 0;
+    /// Sets the secret the free list links are encoded with when `safe-linking` is enabled.
+    pub const fn with_secret(mut self, secret: usize) -> Self {
+        self.secret = secret;
+        self
+    }
 
-    let mut smallest_diff = usize::MAX;
+    /// Holds freed blocks back for `delay` frees (at most `QUARANTINE_CAPACITY`) before they
+    /// can be reused, poisoning them in the meantime.
+    #[cfg(feature = "quarantine")]
+    pub const fn with_quarantine(mut self, delay: usize) -> Self {
+        self.quarantine = Quarantine::new(delay);
+        self
+    }
+
+    fn links(&self) -> LinkCodec {
+        LinkCodec::new(&self.arena, self.secret)
+    }
 
-    while let Some(val) = node {
-        let node_addr = ref_as_usize(val);
-        padding = calc_padding_with_header(node_addr, align, size_of::<FreeNode>());
+    // the free node at `offset` in the arena
+    fn node(&self, offset: usize) -> FreeNode {
+        unsafe { ptr::read((self.arena.start() + offset) as *const FreeNode) }
+    }
 
-        let required_space = size + padding;
+    fn write_node(&mut self, offset: usize, node: FreeNode) {
+        let addr = (self.arena.start() + offset) as *mut FreeNode;
 
-        if val.block_size >= required_space && (val.block_size - required_space < smallest_diff) {
-            prev_to_best = prev_node;
-            best_node = Some(val);
-            smallest_diff = val.block_size - required_space;
+        unsafe {
+            unpoison(addr.cast(), size_of::<FreeNode>());
+            ptr::write(addr, node);
         }
+    }
 
-        prev_node = node;
-        node = val.next;
+    // the header at `offset` in the arena
+    fn header(&self, offset: usize) -> AllocationHeader {
+        unsafe { ptr::read((self.arena.start() + offset) as *const AllocationHeader) }
     }
 
-    // prev_node is always the last node
-    (best_node, prev_to_best, padding)
-}
+    fn write_header(&mut self, offset: usize, header: AllocationHeader) {
+        let addr = (self.arena.start() + offset) as *mut AllocationHeader;
 
-// iterates the list and finds the first free block with enough space
-fn find_first<'a>(
-    node: &'a FreeNode<'a>,
-    size: usize,
-    align: usize,
-) -> (Option<&'a FreeNode<'a>>, Option<&'a FreeNode<'a>>, usize) {
-    let mut node = Some(node);
-    let mut prev_node: Option<&FreeNode> = None;
-    let mut first_node: Option<&FreeNode> = None;
+        // the header may land on memory of a block freed earlier
+        unsafe {
+            unpoison(addr.cast(), size_of::<AllocationHeader>());
+            ptr::write(addr, header);
+        }
+    }
 
-    let mut padding: usize = 0;
+    // writes the header of a block being handed out, under `heap-walk` it's put in front of the
+    // list of live blocks
+    fn hand_out(&mut self, header_addr: usize, block_size: usize, padding: usize) {
+        let offset = header_addr - self.arena.start();
+        #[allow(unused_mut)]
+        let mut header = AllocationHeader::new(block_size, padding);
+
+        #[cfg(feature = "heap-walk")]
+        {
+            let links = self.links();
+            header.walk = WalkLinks {
+                prev: links.encode(None),
+                next: links.encode(self.live),
+                tag: self.tag,
+            };
 
-    while let Some(val) = node {
-        let node_addr = ref_as_usize(val);
-        padding = calc_padding_with_header(node_addr, align, size_of::<FreeNode>());
+            if let Some(head) = self.live {
+                let mut head_header = self.header(head);
+                head_header.walk.prev = links.encode(Some(offset));
+                self.write_header(head, head_header);
+            }
+            self.live = Some(offset);
+        }
 
-        let required_space = size + padding;
+        self.write_header(offset, header);
+    }
 
-        if val.block_size >= required_space {
-            first_node = Some(val);
-            break;
+    // takes the block with the header at `offset` out of the list of live blocks
+    #[cfg(feature = "heap-walk")]
+    fn unlink(&mut self, offset: usize) {
+        let links = self.links();
+        let walk = self.header(offset).walk;
+        let prev = links.decode::<AllocationHeader>(walk.prev);
+        let next = links.decode::<AllocationHeader>(walk.next);
+
+        match prev {
+            Some(prev) => {
+                let mut header = self.header(prev);
+                header.walk.next = walk.next;
+                self.write_header(prev, header);
+            }
+            None => self.live = next,
         }
 
-        prev_node = node;
-        node = val.next;
+        if let Some(next) = next {
+            let mut header = self.header(next);
+            header.walk.prev = walk.prev;
+            self.write_header(next, header);
+        }
     }
 
-    (first_node, prev_node, padding)
-}
+    /// Creates an allocator without memory, it has to be given a region with
+    /// [`init`](SpinLock::<FreeListAllocator>::init) before it can allocate.
+    pub const fn empty(policy: PlacementPolicy) -> Self {
+        Self::new(Region::empty(), policy)
+    }
 
-unsafe impl GlobalAlloc for SpinLock<FreeListAllocator<'_>> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let guard = self.lock();
+    // offset the tail of the arena ends at, only whole grains of it can be handed out
+    fn tail_end(&self) -> usize {
+        whole_grains(self.arena.size())
+    }
+
+    // the untouched end of the arena `(offset, size)`, if a node would fit in it
+    fn tail(&self) -> Option<(usize, usize)> {
+        let size = self.tail_end() - self.top;
+        (size >= size_of::<FreeNode>()).then_some((self.top, size))
+    }
+
+    // the memory managed so far
+    #[cfg(not(loom))]
+    pub(crate) const fn arena(&self) -> Region {
+        self.arena
+    }
+
+    /// How a free block is picked for an allocation.
+    pub fn policy(&self) -> PlacementPolicy {
+        self.policy
+    }
 
-        let mut allocator = guard.get_mut();
+    /// Changes the policy of a live allocator, e.g. to switch to [`FindBest`] once fragmentation
+    /// gets out of hand. It applies from the next allocation on, the blocks already handed out
+    /// stay where they are.
+    ///
+    /// ```ignore
+    /// HEAP.lock_with(|heap| heap.set_policy(PlacementPolicy::FindBest));
+    /// ```
+    ///
+    /// [`FindBest`]: PlacementPolicy::FindBest
+    pub fn set_policy(&mut self, policy: PlacementPolicy) {
+        self.policy = policy;
+    }
 
-        if !allocator.initialized {
-            allocator.init();
+    /// Releases every block allocated so far, the whole arena is a single free block again, e.g.
+    /// to wipe a request heap between batches. The arena is scrubbed with `zeroize-on-free` and
+    /// poisoned with `asan` or `valgrind`, like freed blocks are.
+    ///
+    /// # Safety
+    ///
+    /// None of the released blocks may be used afterwards.
+    pub unsafe fn reset(&mut self) {
+        unsafe { release_block(self.arena.start() as *mut u8, self.arena.size()) };
+
+        self.head = None;
+        self.pending = None;
+        self.pending_len = 0;
+        self.last_freed = None;
+        #[cfg(feature = "quarantine")]
+        self.quarantine.clear();
+        #[cfg(feature = "heap-walk")]
+        {
+            self.live = None;
         }
 
-        // allocator out of memory
-        if allocator.head.is_none() {
-            SpinLock::unlock(guard);
-            return ptr::null_mut();
+        self.top = 0;
+    }
+
+    /// The free blocks, as their offset from the start of the arena and their size, to inspect
+    /// the heap while holding the lock:
+    ///
+    /// ```ignore
+    /// let largest = HEAP.lock_with(|heap| heap.free_blocks().map(|(_, size)| size).max());
+    /// ```
+    ///
+    /// The blocks of the list come in address order, ending with the untouched end of the arena,
+    /// followed by the ones not merged into it yet: the most recent free and, with deferred
+    /// coalescing, the pending ones.
+    pub fn free_blocks(&self) -> FreeBlocks<'_> {
+        let loose = self
+            .last_freed
+            .map(|(addr, block_size)| (addr - self.arena.start(), block_size));
+
+        FreeBlocks {
+            allocator: self,
+            links: self.links(),
+            node: self.head,
+            tail: self.tail(),
+            loose,
+            pending: self.pending,
+        }
+    }
+
+    /// Sets the tag the blocks allocated from now on carry, e.g. the current frame or
+    /// subsystem, reported by [`live_blocks`](Self::live_blocks). Blocks are tagged 0 until it's
+    /// set.
+    #[cfg(feature = "heap-walk")]
+    pub fn set_tag(&mut self, tag: usize) {
+        self.tag = tag;
+    }
+
+    /// The blocks handed out and not freed yet, as the offset of their data from the start of
+    /// the arena, their size and their tag, to walk the heap while holding the lock, e.g. for a
+    /// leak report:
+    ///
+    /// ```ignore
+    /// HEAP.lock_with(|heap| {
+    ///     for (offset, size, tag) in heap.live_blocks().filter(|&(_, _, tag)| tag < frame) {
+    ///         println!("{size} bytes at {offset} leaked by frame {tag}");
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// The most recent allocation comes first. The size is what the block can hold, at least
+    /// the size it was allocated with. Blocks in the quarantine aren't live anymore.
+    #[cfg(feature = "heap-walk")]
+    pub fn live_blocks(&self) -> LiveBlocks<'_> {
+        LiveBlocks {
+            allocator: self,
+            links: self.links(),
+            header: self.live,
         }
+    }
+
+    // number of free blocks and their total size, including the ones held back from the list
+    fn free_summary(&self) -> (usize, usize) {
+        self.free_blocks()
+            .fold((0, 0), |(count, total), (_, size)| {
+                (count + 1, total + size)
+            })
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        self.visited = 0;
+        let ptr = self.allocate_block(layout);
+        self.bounds.alloc = self.bounds.alloc.max(self.visited);
+
+        ptr
+    }
 
+    fn allocate_block(&mut self, layout: Layout) -> *mut u8 {
+        // a freed block has to fit a node, and the block after it has to be aligned for one
         let size = if layout.size() < size_of::<FreeNode>() {
             size_of::<FreeNode>()
         } else {
-            layout.size()
+            layout.size().next_multiple_of(align_of::<FreeNode>())
         };
 
-        let alignment = if layout.align() < 8 {
-            8
-        } else {
-            layout.align()
+        let alignment = layout.align().max(self.min_align);
+
+        // blocks are tagged a whole granule at a time
+        #[cfg(feature = "mte")]
+        let (size, alignment) = (
+            size.next_multiple_of(mte::GRANULE),
+            alignment.max(mte::GRANULE),
+        );
+
+        // the padding doesn't fit in the header
+        if alignment + size_of::<AllocationHeader>() > MAX_PADDING {
+            return ptr::null_mut();
+        }
+
+        // neither does the size of the block, with whatever a split leaves attached to it (only
+        // ever the case for arenas of more than 256 TB, with packed 64-bit headers)
+        let max_size = MAX_BLOCK_SIZE
+            .saturating_sub(alignment + size_of::<AllocationHeader>() + self.min_split_size);
+        if size > max_size {
+            return ptr::null_mut();
+        }
+
+        if let Some(ptr) = self.take_recent(size, alignment) {
+            return ptr;
+        }
+
+        let mut ptr = self.allocate_from_list(size, alignment);
+
+        // the blocks held back from the list might make room
+        if ptr.is_null() && (self.pending.is_some() || self.last_freed.is_some()) {
+            self.coalesce_pending();
+            ptr = self.allocate_from_list(size, alignment);
+        }
+
+        // enough for the block wherever the new memory starts
+        if ptr.is_null() && self.grow_arena(size + alignment + size_of::<AllocationHeader>()) {
+            ptr = self.allocate_from_list(size, alignment);
+        }
+
+        ptr
+    }
+
+    // asks the grow function for at least `min_size` more bytes, returns whether the arena grew
+    fn grow_arena(&mut self, min_size: usize) -> bool {
+        let Some(region) = self.grow.and_then(|grow| grow(min_size)) else {
+            return false;
         };
 
-        // if we reach this section then the head is not null, i.e. there is a at least one free node
+        // an allocator without memory takes the region as is
+        if self.arena.size() == 0 {
+            self.arena = aligned_for_nodes(region);
+            self.head = None;
+            self.top = 0;
+            #[cfg(feature = "heap-walk")]
+            {
+                self.live = None;
+            }
+            return true;
+        }
+
+        if region.start() != self.arena.end() || region.size() < size_of::<FreeNode>() {
+            return false;
+        }
+
+        // the new memory extends the tail, the end of the arena is always handed out last
+        self.arena = unsafe {
+            Region::from_raw_parts(
+                self.arena.start() as *mut u8,
+                self.arena.size() + region.size(),
+            )
+        };
+        true
+    }
+
+    // hands out the most recently freed block without searching the list, if it's an exact fit,
+    // e.g. when a block is freed and a block of the same size is allocated right after
+    fn take_recent(&mut self, size: usize, alignment: usize) -> Option<*mut u8> {
+        // with deferred coalescing the most recent free is the top of the pending list
+        let (addr, block_size) = match self.coalesce_threshold {
+            Some(_) => self
+                .pending
+                .map(|offset| (self.arena.start() + offset, self.node(offset).block_size))?,
+            None => self.last_freed?,
+        };
+
+        let (padding, left) = fit(addr, block_size, size, alignment)?;
+        if left != 0 {
+            return None;
+        }
+
+        match self.coalesce_threshold {
+            Some(_) => {
+                let offset = addr - self.arena.start();
+                self.pending = self.links().decode::<FreeNode>(self.node(offset).next);
+                self.pending_len -= 1;
+            }
+            None => self.last_freed = None,
+        }
+
+        let header_addr = addr + padding - size_of::<AllocationHeader>();
+        self.hand_out(header_addr, block_size, padding);
+
+        Some((addr + padding) as *mut u8)
+    }
+
+    fn allocate_from_list(&mut self, size: usize, alignment: usize) -> *mut u8 {
         // free_node will still be none if the data doesn't fit
-        let (free_node, prev_node, padding) = match allocator.policy {
-            PlacementPolicy::FindFirst => find_first(allocator.head.unwrap(), size, alignment),
-            PlacementPolicy::FindBest => find_best(allocator.head.unwrap(), size, alignment),
+        let links = self.links();
+        let (free_node, prev_node, padding) = match self.policy {
+            PlacementPolicy::FindFirst => self.find_first(size, alignment),
+            PlacementPolicy::FindBest => self.find_best(size, alignment),
         };
 
         // not enough memory left
-        if free_node.is_none() {
-            SpinLock::unlock(guard);
+        let Some(free_offset) = free_node else {
             return ptr::null_mut();
+        };
+        let free_node_addr = self.arena.start() + free_offset;
+
+        // the block is cut from the front of the tail, what's left of it stays out of the list
+        if free_offset == self.top {
+            let remaining = self.tail_end() - free_offset - (padding + size);
+            let block_size = if remaining >= self.min_split_size {
+                padding + size
+            } else {
+                self.tail_end() - free_offset
+            };
+            self.top += block_size;
+
+            let header_addr = free_node_addr + padding - size_of::<AllocationHeader>();
+            self.hand_out(header_addr, block_size, padding);
+
+            return (free_node_addr + padding) as *mut u8;
         }
-        let free_node_addr = ref_as_usize(free_node.unwrap());
+        let free_node = self.node(free_offset);
+
+        // split off what's left of the block if it's large enough, otherwise hand out all of it
+        let remaining = free_node.block_size - (padding + size);
+        let (block_size, next) = if remaining >= self.min_split_size {
+            let new_free_offset = free_offset + padding + size;
+            let new_free_node = FreeNode {
+                block_size: remaining,
+                next: free_node.next,
+            };
+            self.write_node(new_free_offset, new_free_node);
+
+            (padding + size, links.encode(Some(new_free_offset)))
+        } else {
+            (free_node.block_size, free_node.next)
+        };
 
         // remove the selected node from the list
-        if let Some(prev_node_ref) = prev_node {
+        if let Some(prev_offset) = prev_node {
             // if there is a previous node then update it to point to the next FreeNode
-            let prev_node_addr = prev_node_ref as *const FreeNode as *mut FreeNode;
             let new_prev_node = FreeNode {
-                block_size: prev_node_ref.block_size,
-                next: free_node.unwrap().next,
+                block_size: self.node(prev_offset).block_size,
+                next,
             };
-            unsafe { ptr::write(prev_node_addr, new_prev_node) };
+            self.write_node(prev_offset, new_prev_node);
         } else {
             // if the previous node is None, this means the head is the next free area
-            if let Some(val) = free_node.unwrap().next {
-                allocator.head = Some(val);
-            } else {
-                // if there is no next area, resize the free area if possible
-                let remaining = free_node.unwrap().block_size as i128 - (padding + size) as i128;
-
-                if remaining > 0 {
-                    let new_free_node = FreeNode {
-                        block_size: remaining.try_into().unwrap(),
-                        next: None,
-                    };
-                    let new_free_node_addr = free_node_addr.checked_add(padding + size).unwrap();
-                    unsafe { ptr::write(new_free_node_addr as *mut FreeNode, new_free_node) };
-
-                    allocator.head = unsafe { Some(&*(new_free_node_addr as *const FreeNode)) };
-                } else {
-                    allocator.head = None;
-                }
-            }
+            self.head = links.decode::<FreeNode>(next);
         }
 
         // insert the header into the memory region
-        if free_node.unwrap().block_size > padding + size {
-            let header = AllocationHeader {
-                block_size: padding + size,
-                padding,
-            };
-            let header_addr = free_node_addr + padding - size_of::<AllocationHeader>();
+        let header_addr = free_node_addr + padding - size_of::<AllocationHeader>();
+        self.hand_out(header_addr, block_size, padding);
 
-            unsafe { ptr::write(header_addr as *mut AllocationHeader, header) };
-        }
+        (free_node_addr + padding) as *mut u8
+    }
 
-        let ptr = (free_node_addr + padding) as *mut u8;
-        SpinLock::unlock(guard);
+    fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        self.visited = 0;
 
-        ptr
-    }
+        // the block isn't live anymore even while it's in the quarantine
+        #[cfg(feature = "heap-walk")]
+        self.unlink(ptr as usize - size_of::<AllocationHeader>() - self.arena.start());
+
+        // the block that actually gets freed is the one leaving the quarantine
+        #[cfg(feature = "quarantine")]
+        let (ptr, layout) = match self.quarantine.push(ptr, layout) {
+            Some(released) => released,
+            None => return,
+        };
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        let guard = self.lock();
-        let mut allocator = guard.get_mut();
         let ptr_addr = ptr as usize;
 
+        unsafe { release_block(ptr, layout.size()) };
+
         // allocation header corresponding to this allocation
-        let alloc_header = unsafe {
-            let alloc_header_addr = ptr_addr - size_of::<AllocationHeader>();
-            ptr::read(alloc_header_addr as *const AllocationHeader)
+        let alloc_header =
+            self.header(ptr_addr - size_of::<AllocationHeader>() - self.arena.start());
+
+        let free_node_addr = ptr_addr - alloc_header.padding();
+
+        match self.coalesce_threshold {
+            Some(threshold) => {
+                self.push_pending(free_node_addr, alloc_header.block_size());
+
+                if self.pending_len > threshold {
+                    self.coalesce_pending();
+                }
+            }
+            None => {
+                // the previously cached block makes room for this one
+                let block = (free_node_addr, alloc_header.block_size());
+                if let Some((addr, block_size)) = self.last_freed.replace(block) {
+                    self.insert_free(addr, block_size);
+                }
+            }
+        }
+
+        self.bounds.dealloc = self.bounds.dealloc.max(self.visited);
+    }
+
+    fn push_pending(&mut self, addr: usize, block_size: usize) {
+        let offset = addr - self.arena.start();
+        let node = FreeNode {
+            block_size,
+            next: self.links().encode(self.pending),
         };
+        self.write_node(offset, node);
+
+        self.pending = Some(offset);
+        self.pending_len += 1;
+    }
+
+    // merges every pending block, and the cached one, back into the list
+    fn coalesce_pending(&mut self) {
+        let links = self.links();
+
+        if let Some((addr, block_size)) = self.last_freed.take() {
+            self.insert_free(addr, block_size);
+        }
+
+        while let Some(offset) = self.pending {
+            let node = self.node(offset);
+            self.pending = links.decode::<FreeNode>(node.next);
+            self.insert_free(self.arena.start() + offset, node.block_size);
+        }
+        self.pending_len = 0;
+    }
+
+    // inserts a free block into the list keeping it sorted by address, coalescing it with its
+    // neighbours when they are adjacent
+    fn insert_free(&mut self, addr: usize, block_size: usize) {
+        let links = self.links();
+        let offset = addr - self.arena.start();
+
+        // find the free nodes right before and after the block, and the one before that
+        let mut before_prev: Option<usize> = None;
+        let mut prev_node: Option<usize> = None;
+        let mut next_node = self.head;
+        while let Some(val) = next_node.filter(|&val| val < offset) {
+            self.visited += 1;
+            before_prev = prev_node;
+            prev_node = Some(val);
+            next_node = links.decode::<FreeNode>(self.node(val).next);
+        }
+
+        // a block right below the tail goes back into it, along with a free block right below
+        if offset + block_size == self.top {
+            self.top = offset;
+
+            if let Some(prev) =
+                prev_node.filter(|&prev| prev + self.node(prev).block_size == offset)
+            {
+                self.top = prev;
+
+                // it was the last node of the list
+                match before_prev {
+                    Some(before_prev) => {
+                        let node = FreeNode {
+                            block_size: self.node(before_prev).block_size,
+                            next: links.encode(None),
+                        };
+                        self.write_node(before_prev, node);
+                    }
+                    None => self.head = None,
+                }
+            }
+            return;
+        }
 
-        // create a new free node
         let mut free_node = FreeNode {
-            block_size: alloc_header.block_size,
-            next: None,
+            block_size,
+            next: links.encode(next_node),
         };
-        let mut free_node_addr = ptr_addr - alloc_header.padding;
 
-        // if there is no free nodes, make this free node the head
-        if allocator.head.is_none() {
-            let free_node_ref = unsafe {
-                ptr::write(free_node_addr as *mut FreeNode, free_node);
+        // coalesce to the next region if possible
+        if let Some(next_val) = next_node.filter(|&val| offset + block_size == val) {
+            let next_val = self.node(next_val);
+            free_node.block_size += next_val.block_size;
+            free_node.next = next_val.next;
+        }
+
+        match prev_node.map(|prev| (prev, self.node(prev))) {
+            // coalesce to the previous region if possible
+            Some((prev, prev_val)) if prev + prev_val.block_size == offset => {
+                let merged = FreeNode {
+                    block_size: prev_val.block_size + free_node.block_size,
+                    next: free_node.next,
+                };
+                self.write_node(prev, merged);
+            }
+            // link the previous node to the block
+            Some((prev, prev_val)) => {
+                self.write_node(offset, free_node);
+
+                let prev_value = FreeNode {
+                    block_size: prev_val.block_size,
+                    next: links.encode(Some(offset)),
+                };
+                self.write_node(prev, prev_value);
+            }
+            // if there is no node before this one, then make this the head of the list
+            None => {
+                self.write_node(offset, free_node);
+                self.head = Some(offset);
+            }
+        }
+    }
 
-                &*(free_node_addr as *const FreeNode)
-            };
+    // whether splitting `remaining` bytes off a block leaves a free block too small to be of use
+    fn leaves_sliver(&self, remaining: usize) -> bool {
+        remaining != 0 && remaining < self.min_remainder
+    }
 
-            allocator.head = Some(free_node_ref);
-            SpinLock::unlock(guard);
-            return;
+    // the free block a search starts at `(offset, size)`, the head of the list or the tail
+    fn first_block(&self) -> Option<(usize, usize)> {
+        match self.head {
+            Some(head) => Some((head, self.node(head).block_size)),
+            None => self.tail(),
         }
+    }
 
-        // if there are free nodes, insert the created node into the list keeping it sorted
-        let mut node = allocator.head;
-        let mut prev_node: Option<&FreeNode> = None;
+    // the free block a search visits after the one at `offset`, the tail follows the list
+    fn next_block(&self, links: &LinkCodec, offset: usize) -> Option<(usize, usize)> {
+        if offset == self.top {
+            return None;
+        }
 
-        let mut update_prev = true;
+        match links.decode::<FreeNode>(self.node(offset).next) {
+            Some(next) => Some((next, self.node(next).block_size)),
+            None => self.tail(),
+        }
+    }
 
-        while let Some(val) = node {
-            if ref_as_usize(val) > free_node_addr {
-                free_node.next = Some(val);
+    // iterates over the free blocks (up to the search limit) and finds the best fit, returns it,
+    // the node before it and the padding the block needs
+    fn find_best(&mut self, size: usize, align: usize) -> (Option<usize>, Option<usize>, usize) {
+        let links = self.links();
 
-                // coalesce to the previous region if possible
-                if let Some(prev_val) = prev_node {
-                    if ref_as_usize(prev_val) + prev_val.block_size == free_node_addr {
-                        update_prev = false;
+        let mut block = self.first_block();
+        let mut prev_node: Option<usize> = None;
 
-                        free_node.block_size += val.block_size;
-                        free_node_addr = ref_as_usize(prev_val);
-                    }
-                }
+        let mut prev_to_best: Option<usize> = None;
+        let mut best_node: Option<usize> = None;
 
-                // coalesce to the next region if possible
-                if free_node_addr + free_node.block_size == ref_as_usize(val) {
-                    free_node.block_size += val.block_size;
-                    free_node.next = val.next;
-                }
+        let mut best_padding: usize = 0;
+
+        // blocks leaving a sliver lose against the others, whatever their size
+        let mut best_fit = (true, usize::MAX);
+
+        let mut searched = 0;
+        while let Some((offset, block_size)) = block {
+            // the search is bounded, the rest of the list isn't looked at
+            if self.search_limit.is_some_and(|limit| searched == limit) {
+                break;
+            }
+            searched += 1;
+
+            let node_addr = self.arena.start() + offset;
+            if let Some((padding, diff)) = fit(node_addr, block_size, size, align) {
+                let fit = (self.leaves_sliver(diff), diff);
 
-                // if there is no node before this one, then make this the head of the list
-                if prev_node.is_none() {
-                    allocator.head = unsafe { Some(&*(free_node_addr as *const FreeNode)) };
+                if fit < best_fit {
+                    prev_to_best = prev_node;
+                    best_node = Some(offset);
+                    best_padding = padding;
+                    best_fit = fit;
                 }
+            }
+
+            prev_node = Some(offset);
+            block = self.next_block(&links, offset);
+        }
+
+        self.visited += searched;
+        (best_node, prev_to_best, best_padding)
+    }
+
+    // iterates over the free blocks (up to the search limit) and finds the first one with enough
+    // space
+    fn find_first(&mut self, size: usize, align: usize) -> (Option<usize>, Option<usize>, usize) {
+        let links = self.links();
+
+        let mut block = self.first_block();
+        let mut prev_node: Option<usize> = None;
 
+        // the first block that fits but leaves a sliver, taken if no other block fits
+        let mut fallback = None;
+
+        let mut searched = 0;
+        while let Some((offset, block_size)) = block {
+            // the search is bounded, the rest of the list isn't looked at
+            if self.search_limit.is_some_and(|limit| searched == limit) {
                 break;
             }
+            searched += 1;
+
+            let node_addr = self.arena.start() + offset;
+            if let Some((padding, diff)) = fit(node_addr, block_size, size, align) {
+                if !self.leaves_sliver(diff) {
+                    self.visited += searched;
+                    return (Some(offset), prev_node, padding);
+                }
 
-            prev_node = node;
-            node = val.next;
+                fallback.get_or_insert((Some(offset), prev_node, padding));
+            }
+
+            prev_node = Some(offset);
+            block = self.next_block(&links, offset);
         }
 
-        // update the previous node to point to this new deallocated free space
-        if update_prev && prev_node.is_some() {
-            let prev_node_value = FreeNode {
-                block_size: prev_node.unwrap().block_size,
-                next: unsafe { Some(&*(free_node_addr as *const FreeNode)) },
-            };
-            unsafe {
-                ptr::write(
-                    prev_node.unwrap() as *const FreeNode as *mut FreeNode,
-                    prev_node_value,
-                )
-            };
+        self.visited += searched;
+        fallback.unwrap_or((None, prev_node, 0))
+    }
+}
+
+impl<R: Relax> SpinLock<FreeListAllocator, R> {
+    /// Hands the allocator the memory it manages at runtime, e.g. the heap between the
+    /// `__heap_start` and `__heap_end` linker symbols.
+    ///
+    /// ```ignore
+    /// static HEAP: SpinLock<FreeListAllocator> =
+    ///     SpinLock::new(FreeListAllocator::empty(PlacementPolicy::FindFirst));
+    ///
+    /// extern "C" {
+    ///     static mut __heap_start: u8;
+    ///     static mut __heap_end: u8;
+    /// }
+    ///
+    /// unsafe {
+    ///     HEAP.init(Region::from_bounds(
+    ///         core::ptr::addr_of_mut!(__heap_start),
+    ///         core::ptr::addr_of_mut!(__heap_end),
+    ///     ))
+    /// };
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// Must be called before any allocation is made, and the region has to uphold the
+    /// requirements of [`Region::from_raw_parts`].
+    pub unsafe fn init(&self, region: Region) {
+        self.lock_with(|allocator| {
+            allocator.arena = aligned_for_nodes(region);
+            allocator.head = None;
+            allocator.pending = None;
+            allocator.pending_len = 0;
+            allocator.last_freed = None;
+            #[cfg(feature = "heap-walk")]
+            {
+                allocator.live = None;
+            }
+            allocator.top = 0;
+        })
+    }
+
+    /// Most nodes a single operation visited so far, see [`SearchBounds`].
+    pub fn search_bounds(&self) -> SearchBounds {
+        self.lock_with(|allocator| allocator.bounds)
+    }
+
+    /// Returns whether `ptr` points into the memory managed by this allocator.
+    pub fn owns(&self, ptr: *mut u8) -> bool {
+        self.lock_with(|allocator| allocator.arena).contains(ptr)
+    }
+}
+
+// `size` rounded down to what headers can describe, the bytes past it are left unused
+const fn whole_grains(size: usize) -> usize {
+    size & !(GRAIN - 1)
+}
+
+// the padding a block of `size` bytes takes at the start of the free block at `addr`, and the
+// bytes of the free block left over after it, `None` if it doesn't fit
+fn fit(addr: usize, block_size: usize, size: usize, align: usize) -> Option<(usize, usize)> {
+    let padding = calc_padding_with_header(addr, align, size_of::<AllocationHeader>())?;
+    let left = block_size.checked_sub(size.checked_add(padding)?)?;
+
+    Some((padding, left))
+}
+
+// the nodes are written at the start of the region, so it has to be aligned for them
+fn aligned_for_nodes(region: Region) -> Region {
+    let start = align_forward(region.start(), align_of::<FreeNode>()).unwrap_or(region.end());
+    let size = region.size().saturating_sub(start - region.start());
+
+    unsafe { Region::from_raw_parts(start as *mut u8, size) }
+}
+
+/// Iterator over the free blocks of a [`FreeListAllocator`], see
+/// [`free_blocks`](FreeListAllocator::free_blocks).
+pub struct FreeBlocks<'a> {
+    allocator: &'a FreeListAllocator,
+    links: LinkCodec,
+    // the next node of the list being walked
+    node: Option<usize>,
+    // the untouched end of the arena, and a free block outside of the lists, `(offset, size)`
+    tail: Option<(usize, usize)>,
+    loose: Option<(usize, usize)>,
+    // the pending list, walked last
+    pending: Option<usize>,
+}
+
+impl Iterator for FreeBlocks<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        if let Some(offset) = self.node {
+            let node = self.allocator.node(offset);
+            self.node = self.links.decode::<FreeNode>(node.next);
+            return Some((offset, node.block_size));
+        }
+
+        if let Some(block) = self.tail.take().or_else(|| self.loose.take()) {
+            return Some(block);
+        }
+
+        self.node = self.pending.take();
+        self.node?;
+        self.next()
+    }
+}
+
+/// Iterator over the live blocks of a [`FreeListAllocator`], see
+/// [`live_blocks`](FreeListAllocator::live_blocks).
+#[cfg(feature = "heap-walk")]
+pub struct LiveBlocks<'a> {
+    allocator: &'a FreeListAllocator,
+    links: LinkCodec,
+    // offset of the next header
+    header: Option<usize>,
+}
+
+#[cfg(feature = "heap-walk")]
+impl Iterator for LiveBlocks<'_> {
+    type Item = (usize, usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize, usize)> {
+        let offset = self.header?;
+        let header = self.allocator.header(offset);
+        self.header = self.links.decode::<AllocationHeader>(header.walk.next);
+
+        Some((
+            offset + size_of::<AllocationHeader>(),
+            header.block_size() - header.padding(),
+            header.walk.tag,
+        ))
+    }
+}
+
+// an allocator without memory, see `empty`
+impl Default for FreeListAllocator {
+    fn default() -> Self {
+        Self::empty(PlacementPolicy::default())
+    }
+}
+
+/// Builds a [`FreeListAllocator`] one option at a time, the whole chain can initialize a
+/// `static`:
+///
+#[cfg_attr(feature = "freelist", doc = "```")]
+#[cfg_attr(not(feature = "freelist"), doc = "```ignore")]
+/// # use rsalloc::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+/// static ARENA: Arena = Arena::new();
+/// static HEAP: SpinLock<FreeListAllocator> = SpinLock::new(
+///     FreeListAllocator::builder()
+///         .region(unsafe { ARENA.region() })
+///         .arena_size(64 * 1024)
+///         .policy(PlacementPolicy::FindBest)
+///         .build()
+///         .with_bounded_search(64),
+/// );
+/// ```
+///
+/// The rest of the options are set on the allocator it builds with its `with_*` methods.
+#[derive(Debug, Clone, Copy)]
+pub struct FreeListBuilder {
+    region: Region,
+    arena_size: Option<usize>,
+    policy: PlacementPolicy,
+}
+
+impl FreeListBuilder {
+    pub const fn new() -> Self {
+        Self {
+            region: Region::empty(),
+            arena_size: None,
+            policy: PlacementPolicy::FindFirst,
+        }
+    }
+
+    /// Sets the memory the allocator manages. Without one the allocator is
+    /// [`empty`](FreeListAllocator::empty) until it's given a region with
+    /// [`init`](SpinLock::<FreeListAllocator>::init).
+    pub const fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    /// Only manages the first `size` bytes of the region, leaving the rest of it alone.
+    pub const fn arena_size(mut self, size: usize) -> Self {
+        self.arena_size = Some(size);
+        self
+    }
+
+    /// Sets how a free block is picked for an allocation, [`FindFirst`](PlacementPolicy::FindFirst) by default.
+    pub const fn policy(mut self, policy: PlacementPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Creates the allocator. Panics if the region is smaller than the arena size.
+    pub const fn build(self) -> FreeListAllocator {
+        let region = match self.arena_size {
+            Some(size) => self.region.truncate(size),
+            None => self.region,
+        };
+
+        FreeListAllocator::new(region, self.policy)
+    }
+}
+
+impl Default for FreeListBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for FreeListAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (free_blocks, free) = self.free_summary();
+
+        f.debug_struct("FreeListAllocator")
+            .field("policy", &self.policy)
+            .field("used", &(self.arena.size() - free))
+            .field("free", &free)
+            .field("free_blocks", &free_blocks)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl SaveState for FreeListAllocator {
+    fn save_state<W: Write>(&self, writer: W) -> io::Result<()> {
+        #[cfg(feature = "heap-walk")]
+        let (live, tag) = (self.live, self.tag);
+        #[cfg(not(feature = "heap-walk"))]
+        let (live, tag) = (None, 0);
+
+        let state = [
+            save_state::encode(self.head),
+            save_state::encode(self.pending),
+            self.pending_len,
+            // the block is kept by its offset, the arena may move
+            save_state::encode(self.last_freed.map(|(addr, _)| addr - self.arena.start())),
+            self.last_freed.map_or(0, |(_, block_size)| block_size),
+            save_state::encode(live),
+            tag,
+            self.top,
+        ];
+        save_arena(writer, self.arena, &state, self.arena.size())
+    }
+
+    unsafe fn restore_state<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        unsafe { self.reset() };
+
+        // safe-linking keys the links to the address of the arena
+        let relocatable = !cfg!(feature = "safe-linking");
+        let [head, pending, pending_len, last_freed, last_freed_size, live, tag, top] =
+            unsafe { restore_arena(reader, self.arena, relocatable)? };
+
+        self.head = save_state::decode(head);
+        self.pending = save_state::decode(pending);
+        self.pending_len = pending_len;
+        self.last_freed = save_state::decode(last_freed)
+            .map(|offset| (self.arena.start() + offset, last_freed_size));
+        #[cfg(feature = "heap-walk")]
+        {
+            self.live = save_state::decode(live);
+            self.tag = tag;
         }
+        #[cfg(not(feature = "heap-walk"))]
+        let _ = (live, tag);
+        self.top = top;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Relax> Metered for SpinLock<FreeListAllocator, R> {
+    fn usage(&self) -> Usage {
+        self.lock_with(|allocator| {
+            let capacity = allocator.arena.size();
 
-        unsafe { ptr::write(free_node_addr as *mut FreeNode, free_node) };
+            let (_, free) = allocator.free_summary();
 
-        SpinLock::unlock(guard);
+            Usage {
+                used: capacity - free,
+                capacity,
+            }
+        })
     }
 }
 
+impl_global_alloc!(FreeListAllocator, {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .lock_with_or_timeout(|allocator| allocator.allocate(layout))
+            .unwrap_or(ptr::null_mut());
+
+        #[cfg(feature = "mte")]
+        let ptr = unsafe { mte::tag(ptr, layout.size()) };
+
+        #[cfg(feature = "valgrind")]
+        let ptr = valgrind::malloc_like(ptr, layout.size());
+
+        unsafe { init_block(ptr, layout.size()) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "mte")]
+        let ptr = unsafe { mte::untag(ptr, layout.size()) };
+
+        #[cfg(feature = "valgrind")]
+        valgrind::free_like(ptr);
+
+        // a lock that can't be taken leaks the block, there's no way to fail a free
+        let _ = self.lock_with_or_timeout(|allocator| allocator.deallocate(ptr, layout));
+    }
+});
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::Arena;
+
+    // lays out free blocks of the given sizes, each followed by a used block of 16 bytes
+    fn free_list(arena: Region, sizes: [usize; 4]) -> FreeListAllocator {
+        let mut allocator = FreeListAllocator::new(arena, PlacementPolicy::FindFirst);
+        let links = allocator.links();
+
+        let mut offset = 0;
+        for (i, &block_size) in sizes.iter().enumerate() {
+            let next = (i + 1 < sizes.len()).then_some(offset + block_size + 16);
+            let node = FreeNode {
+                block_size,
+                next: links.encode(next),
+            };
+            allocator.write_node(offset, node);
+
+            offset += block_size + 16;
+        }
+
+        allocator.head = Some(0);
+        allocator.top = allocator.tail_end();
+        allocator
+    }
 
     #[test]
     fn test_find_first() {
-        let node_3 = FreeNode {
-            block_size: 100,
-            next: None,
-        };
-        let node_2 = FreeNode {
-            block_size: 50,
-            next: Some(&node_3),
-        };
-        let node_1 = FreeNode {
-            block_size: 75,
-            next: Some(&node_2),
-        };
-        let head = FreeNode {
-            block_size: 10,
-            next: Some(&node_1),
-        };
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = free_list(unsafe { ARENA.region() }, [16, 80, 56, 104]);
 
-        let (free_node, prev_node, _) = find_first(&head, 20, 2);
+        let (free_node, prev_node, _) = allocator.find_first(20, 2);
 
-        assert_eq!(free_node.unwrap().block_size, node_1.block_size);
-        assert_eq!(prev_node.unwrap().block_size, head.block_size);
+        assert_eq!(allocator.node(free_node.unwrap()).block_size, 80);
+        assert_eq!(allocator.node(prev_node.unwrap()).block_size, 16);
     }
 
     #[test]
     fn test_find_best() {
-        let node_3 = FreeNode {
-            block_size: 100,
-            next: None,
-        };
-        let node_2 = FreeNode {
-            block_size: 50,
-            next: Some(&node_3),
-        };
-        let node_1 = FreeNode {
-            block_size: 75,
-            next: Some(&node_2),
-        };
-        let head = FreeNode {
-            block_size: 10,
-            next: Some(&node_1),
-        };
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = free_list(unsafe { ARENA.region() }, [16, 80, 56, 104]);
+
+        let (free_node, prev_node, _) = allocator.find_best(20, 2);
+
+        assert_eq!(allocator.node(free_node.unwrap()).block_size, 56);
 
-        let (free_node, prev_node, _) = find_best(&head, 20, 2);
+        assert_eq!(allocator.node(prev_node.unwrap()).block_size, 80);
+    }
+
+    #[test]
+    fn test_bounded_search() {
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = free_list(unsafe { ARENA.region() }, [16, 80, 56, 104]);
+        allocator.search_limit = Some(2);
+
+        // the block that fits is the fourth one, past the limit
+        let (free_node, _, _) = allocator.find_first(88, 8);
+        assert!(free_node.is_none());
+
+        let (free_node, _, _) = allocator.find_best(40, 8);
+        assert_eq!(allocator.node(free_node.unwrap()).block_size, 80);
+        assert_eq!(allocator.visited, 4);
+    }
+
+    #[test]
+    fn test_search_bounds() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst)
+                .with_bounded_search(8),
+        );
+
+        let layout = Layout::new::<[u64; 4]>();
+        let ptrs = [(); 6].map(|_| unsafe { ALLOC.alloc(layout) });
+        assert_eq!(
+            ALLOC.search_bounds(),
+            SearchBounds {
+                alloc: 1,
+                dealloc: 0
+            }
+        );
 
-        assert_eq!(free_node.unwrap().block_size, node_2.block_size);
+        // every other block is freed, leaving two holes in the list in front of the rest of the
+        // arena (the last freed block is held back)
+        for ptr in ptrs.iter().step_by(2) {
+            unsafe { ALLOC.dealloc(*ptr, layout) };
+        }
 
-        assert_eq!(prev_node.unwrap().block_size, node_1.block_size);
+        // the holes are too small, so the search goes past them
+        let large = Layout::new::<[u64; 16]>();
+        assert!(!unsafe { ALLOC.alloc(large) }.is_null());
+        assert_eq!(
+            ALLOC.search_bounds(),
+            SearchBounds {
+                alloc: 3,
+                dealloc: 1
+            }
+        );
     }
 
     #[test]
     fn test_allocation_deallocation_find_first() {
-        let global_alloc_first: SpinLock<FreeListAllocator> =
-            SpinLock::new(FreeListAllocator::new(PlacementPolicy::FindFirst));
+        static ARENA: Arena = Arena::new();
+        let global_alloc_first: SpinLock<FreeListAllocator> = SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst),
+        );
 
         let layout_u32 = Layout::new::<u32>();
         let layout_u64 = Layout::new::<[u64; 34]>();
@@ -395,8 +1443,11 @@ mod test {
     //
     #[test]
     fn test_allocation_deallocation_find_best() {
-        let global_alloc_best: SpinLock<FreeListAllocator> =
-            SpinLock::new(FreeListAllocator::new(PlacementPolicy::FindBest));
+        static ARENA: Arena = Arena::new();
+        let global_alloc_best: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindBest,
+        ));
 
         let layout_u32 = Layout::new::<u32>();
         let layout_u64 = Layout::new::<[u64; 34]>();
@@ -419,4 +1470,751 @@ mod test {
         let ptr = unsafe { global_alloc_best.alloc(layout_u32) };
         assert_eq!(ptr as usize, best_fit_section as usize);
     }
+
+    #[test]
+    fn test_free_blocks() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let free_blocks =
+            || ALLOC.lock_with(|allocator| allocator.free_blocks().collect::<Vec<_>>());
+
+        // the whole arena before the first allocation
+        assert_eq!(free_blocks(), [(0, 4096)]);
+
+        let layout = Layout::new::<[u8; 240]>();
+        let block_size = layout.size() + FreeListAllocator::overhead_per_alloc();
+
+        let ptrs = [(); 4].map(|_| unsafe { ALLOC.alloc(layout) });
+        unsafe { ALLOC.dealloc(ptrs[1], layout) };
+        unsafe { ALLOC.dealloc(ptrs[3], layout) };
+
+        // the list in address order, then the block held back from it
+        assert_eq!(
+            free_blocks(),
+            [
+                (block_size, block_size),
+                (4 * block_size, 4096 - 4 * block_size),
+                (3 * block_size, block_size),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "heap-walk")]
+    fn test_live_blocks() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let small = Layout::new::<[u8; 64]>();
+        let large = Layout::from_size_align(256, 64).unwrap();
+
+        let ptr_1 = unsafe { ALLOC.alloc(small) };
+        ALLOC.lock_with(|allocator| allocator.set_tag(1));
+        let ptr_2 = unsafe { ALLOC.alloc(large) };
+        let ptr_3 = unsafe { ALLOC.alloc(small) };
+
+        let live_blocks =
+            || ALLOC.lock_with(|allocator| allocator.live_blocks().collect::<Vec<_>>());
+        let offset = |ptr: *mut u8| ptr as usize - unsafe { ARENA.region() }.start();
+
+        // the most recent allocation first
+        assert_eq!(
+            live_blocks(),
+            [
+                (offset(ptr_3), 64, 1),
+                (offset(ptr_2), 256, 1),
+                (offset(ptr_1), 64, 0)
+            ]
+        );
+
+        // freed blocks leave the list wherever they are in it
+        unsafe { ALLOC.dealloc(ptr_2, large) };
+        assert_eq!(
+            live_blocks(),
+            [(offset(ptr_3), 64, 1), (offset(ptr_1), 64, 0)]
+        );
+        unsafe { ALLOC.dealloc(ptr_3, small) };
+        assert_eq!(live_blocks(), [(offset(ptr_1), 64, 0)]);
+
+        ALLOC.lock_with(|allocator| unsafe { allocator.reset() });
+        assert_eq!(live_blocks(), []);
+    }
+
+    #[test]
+    fn test_set_policy() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let large = Layout::new::<[u8; 256]>();
+        let medium = Layout::new::<[u8; 128]>();
+        let small = Layout::new::<[u8; 64]>();
+
+        // a large hole at the start and a small one after it, the medium block is freed last so
+        // it's the one held back from the list
+        let layouts = [large, small, small, small, medium, small];
+        let ptrs = layouts.map(|layout| unsafe { ALLOC.alloc(layout) });
+        for i in [0, 2, 4] {
+            unsafe { ALLOC.dealloc(ptrs[i], layouts[i]) };
+        }
+
+        // the next allocation takes the small hole instead of the first one
+        ALLOC.lock_with(|allocator| allocator.set_policy(PlacementPolicy::FindBest));
+        assert_eq!(unsafe { ALLOC.alloc(small) }, ptrs[2]);
+    }
+
+    #[test]
+    fn test_default() {
+        static ARENA: Arena<4096> = Arena::new();
+        let heap =
+            SpinLock::new(FreeListAllocator::default().with_policy(PlacementPolicy::FindBest));
+
+        let layout = Layout::new::<u64>();
+
+        // no memory until it's handed over
+        assert!(unsafe { heap.alloc(layout) }.is_null());
+        unsafe { heap.init(ARENA.region()) };
+        assert!(!unsafe { heap.alloc(layout) }.is_null());
+        assert_eq!(
+            heap.lock_with(|allocator| allocator.policy()),
+            PlacementPolicy::FindBest
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        static ARENA: Arena<4096> = Arena::new();
+        static HEAP: SpinLock<FreeListAllocator> = SpinLock::new(
+            FreeListAllocator::builder()
+                .region(unsafe { ARENA.region() })
+                .arena_size(1024)
+                .policy(PlacementPolicy::FindBest)
+                .build(),
+        );
+
+        assert_eq!(
+            HEAP.lock_with(|allocator| allocator.policy()),
+            PlacementPolicy::FindBest
+        );
+
+        // only the first kilobyte of the arena is used
+        assert!(unsafe { HEAP.alloc(Layout::new::<[u8; 2048]>()) }.is_null());
+        let ptr = unsafe { HEAP.alloc(Layout::new::<[u8; 512]>()) };
+        assert!(HEAP.owns(ptr));
+        assert!((ptr as usize) < unsafe { ARENA.region() }.start() + 1024);
+
+        // no region, no memory
+        let heap = SpinLock::new(FreeListAllocator::builder().build());
+        assert!(unsafe { heap.alloc(Layout::new::<u64>()) }.is_null());
+    }
+
+    #[test]
+    fn test_init_region() {
+        static ARENA: Arena<4096> = Arena::new();
+        static HEAP: SpinLock<FreeListAllocator> =
+            SpinLock::new(FreeListAllocator::empty(PlacementPolicy::FindFirst));
+
+        let layout = Layout::new::<u64>();
+
+        // no memory was given to the allocator yet
+        assert!(unsafe { HEAP.alloc(layout) }.is_null());
+
+        // start the heap at an unaligned address, as linker symbols might
+        let region = unsafe { ARENA.region() };
+        let start = (region.start() + 1) as *mut u8;
+        let end = region.end() as *mut u8;
+        unsafe { HEAP.init(Region::from_bounds(start, end)) };
+
+        let ptr = unsafe { HEAP.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!(start as usize <= ptr as usize && (ptr as usize) < end as usize);
+        assert_eq!(ptr as usize % layout.align(), 0);
+    }
+
+    #[test]
+    fn test_debug() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindBest,
+        ));
+
+        let layout = Layout::new::<[u8; 240]>();
+        let block_size = layout.size() + FreeListAllocator::overhead_per_alloc();
+
+        let ptrs = [(); 3].map(|_| unsafe { ALLOC.alloc(layout) });
+        unsafe { ALLOC.dealloc(ptrs[1], layout) };
+
+        // the freed block is held back for a quick reuse, it's still counted as free
+        assert_eq!(
+            format!("{ALLOC:?}"),
+            format!(
+                "SpinLock {{ value: FreeListAllocator {{ policy: FindBest, used: {}, free: {}, \
+                 free_blocks: 2 }} }}",
+                2 * block_size,
+                4096 - 2 * block_size
+            )
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst)
+                .with_deferred_coalescing(8),
+        );
+
+        let layout = Layout::new::<[u8; 512]>();
+
+        // fill the arena, with a few blocks freed along the way
+        let mut ptrs = Vec::new();
+        loop {
+            let ptr = unsafe { ALLOC.alloc(layout) };
+            if ptr.is_null() {
+                break;
+            }
+            ptrs.push(ptr);
+        }
+        unsafe { ALLOC.dealloc(ptrs[1], layout) };
+        unsafe { ALLOC.dealloc(ptrs[3], layout) };
+
+        ALLOC.lock_with(|allocator| unsafe { allocator.reset() });
+
+        // the arena is one free block again, the untouched tail
+        ALLOC.lock_with(|allocator| {
+            assert_eq!(allocator.pending_len, 0);
+            assert!(allocator.head.is_none());
+            assert_eq!(allocator.free_blocks().collect::<Vec<_>>(), [(0, 4096)]);
+        });
+        assert_eq!(unsafe { ALLOC.alloc(layout) }, ptrs[0]);
+    }
+
+    #[test]
+    fn test_deferred_coalescing() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst)
+                .with_deferred_coalescing(8),
+        );
+
+        let layout = Layout::new::<[u8; 512]>();
+
+        let ptrs = [(); 4].map(|_| unsafe { ALLOC.alloc(layout) });
+        for ptr in ptrs {
+            unsafe { ALLOC.dealloc(ptr, layout) };
+        }
+
+        // the freed blocks are set aside instead of being merged into the list
+        assert_eq!(ALLOC.lock_with(|allocator| allocator.pending_len), 4);
+
+        // only the coalesced blocks leave room for this one
+        let large = Layout::new::<[u8; 3072]>();
+        let ptr = unsafe { ALLOC.alloc(large) };
+        assert!(!ptr.is_null());
+        assert!(ptr < ptrs[1]);
+        assert_eq!(ALLOC.lock_with(|allocator| allocator.pending_len), 0);
+    }
+
+    #[test]
+    fn test_coalesce_threshold() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst)
+                .with_deferred_coalescing(2),
+        );
+
+        let layout = Layout::new::<[u8; 256]>();
+
+        let ptrs = [(); 3].map(|_| unsafe { ALLOC.alloc(layout) });
+        for ptr in ptrs {
+            unsafe { ALLOC.dealloc(ptr, layout) };
+        }
+
+        // the third free went over the threshold, everything was merged back into the tail
+        ALLOC.lock_with(|allocator| {
+            assert_eq!(allocator.pending_len, 0);
+            assert!(allocator.head.is_none());
+            assert_eq!(allocator.free_blocks().collect::<Vec<_>>(), [(0, 4096)]);
+        });
+    }
+
+    #[test]
+    fn test_last_freed_fast_path() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let layout = Layout::new::<[u64; 8]>();
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+
+        // the freed block is cached instead of going back into the list
+        assert!(ALLOC.lock_with(|allocator| allocator.head.is_none()));
+
+        // and is handed out again right away
+        assert_eq!(unsafe { ALLOC.alloc(layout) }, ptr_1);
+        assert!(ALLOC.lock_with(|allocator| allocator.last_freed.is_none()));
+
+        // a block of a different size goes through the list
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        let ptr_3 = unsafe { ALLOC.alloc(Layout::new::<[u64; 4]>()) };
+        assert_ne!(ptr_3, ptr_1);
+
+        // the cached block is coalesced back when the list runs out
+        unsafe { ALLOC.dealloc(ptr_3, Layout::new::<[u64; 4]>()) };
+        unsafe { ALLOC.dealloc(ptr_2, layout) };
+        assert!(!unsafe { ALLOC.alloc(Layout::new::<[u8; 4000]>()) }.is_null());
+    }
+
+    #[test]
+    fn test_lock_released_under_contention() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let layout = Layout::new::<[u64; 16]>();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let ptr = unsafe { ALLOC.alloc(layout) };
+                        if !ptr.is_null() {
+                            unsafe { ALLOC.dealloc(ptr, layout) };
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // every thread let go of the lock, and freed everything it took
+        assert!(ALLOC.try_lock().is_some());
+        let ptr = unsafe { ALLOC.alloc(Layout::new::<[u8; 512]>()) };
+        assert!(!ptr.is_null());
+
+        // the out of memory path releases the lock too
+        assert!(unsafe { ALLOC.alloc(Layout::new::<[u8; 1024]>()) }.is_null());
+        assert!(ALLOC.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_tail() {
+        static ARENA: Arena<4096> = Arena::new();
+        let mut allocator =
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst);
+        let layout = Layout::new::<[u64; 8]>();
+        let block_size = layout.size() + FreeListAllocator::overhead_per_alloc();
+
+        // nothing is written to the arena up front, it's all tail
+        assert!(allocator.head.is_none());
+        assert_eq!(allocator.free_blocks().collect::<Vec<_>>(), [(0, 4096)]);
+
+        let [a, _, c, d] = [(); 4].map(|_| allocator.allocate(layout));
+        assert_eq!(allocator.top, 4 * block_size);
+
+        allocator.deallocate(a, layout);
+        allocator.deallocate(c, layout);
+        allocator.deallocate(d, layout);
+        allocator.coalesce_pending();
+
+        // the last block goes back into the tail along with the free block below it, the
+        // first one stays in the list
+        assert_eq!(allocator.top, 2 * block_size);
+        assert_eq!(
+            allocator.free_blocks().collect::<Vec<_>>(),
+            [(0, block_size), (2 * block_size, 4096 - 2 * block_size)]
+        );
+        assert_eq!(allocator.allocate(layout), a);
+    }
+
+    #[test]
+    fn test_grow() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static ARENA: Arena<6144> = Arena::new();
+        static GROWN: AtomicUsize = AtomicUsize::new(0);
+
+        // hands out the arena 1 KiB at a time, like pages added at the end of the memory
+        fn grow(min_size: usize) -> Option<Region> {
+            let size = min_size.next_multiple_of(1024);
+            let offset = GROWN.fetch_add(size, Ordering::Relaxed);
+            if offset + size > 6144 {
+                return None;
+            }
+
+            let start = (unsafe { ARENA.region() }.start() + offset) as *mut u8;
+            Some(unsafe { Region::from_raw_parts(start, size) })
+        }
+
+        static ALLOC: SpinLock<FreeListAllocator> =
+            SpinLock::new(FreeListAllocator::empty(PlacementPolicy::FindFirst).with_grow_fn(grow));
+
+        let layout = Layout::new::<[u8; 640]>();
+
+        // the first region becomes the arena
+        let first = unsafe { ALLOC.alloc(layout) };
+        let overhead = FreeListAllocator::overhead_per_alloc();
+        assert_eq!(first as usize, unsafe { ARENA.region() }.start() + overhead);
+        assert_eq!(GROWN.load(Ordering::Relaxed), 1024);
+
+        // the rest of the arena is too small, so it's extended with the next region
+        let second = unsafe { ALLOC.alloc(layout) };
+        assert_eq!(second as usize, first as usize + 640 + overhead);
+        assert_eq!(GROWN.load(Ordering::Relaxed), 2048);
+
+        // the free space at the end of the arena and the grown region are coalesced
+        let large = Layout::new::<[u8; 2048]>();
+        let third = unsafe { ALLOC.alloc(large) };
+        assert_eq!(third as usize, second as usize + 640 + overhead);
+        assert!(ALLOC.owns(third));
+
+        // the grow function is out of memory too
+        assert!(unsafe { ALLOC.alloc(large) }.is_null());
+    }
+
+    #[test]
+    // with `mte` the blocks are also rounded up to whole granules
+    #[cfg(not(feature = "mte"))]
+    fn test_overhead_per_alloc() {
+        static ARENA: Arena<1024> = Arena::new();
+        static SMALL_ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let layout = Layout::new::<[u64; 7]>();
+        let block_size = layout.size() + FreeListAllocator::overhead_per_alloc();
+
+        let mut count = 0;
+        while !unsafe { ALLOC.alloc(layout) }.is_null() {
+            count += 1;
+        }
+
+        assert_eq!(count, 1024 / block_size);
+
+        // tiny allocations take up a whole node
+        let layout = Layout::new::<u8>();
+        let block_size = FreeListAllocator::min_block_size();
+        let small = SpinLock::new(FreeListAllocator::new(
+            unsafe { SMALL_ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let mut count = 0;
+        while !unsafe { small.alloc(layout) }.is_null() {
+            count += 1;
+        }
+
+        assert_eq!(count, 1024 / block_size);
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", not(feature = "safe-linking")))]
+    fn test_save_state() {
+        // two page aligned arenas, the state can move from one to the other
+        #[repr(align(4096))]
+        struct Page([u8; 4096]);
+        let mut pages = [Page([0; 4096]), Page([0; 4096])];
+        let [from, to] = pages
+            .each_mut()
+            .map(|page| unsafe { Region::from_raw_parts(page.0.as_mut_ptr(), 4096) });
+
+        let heap = SpinLock::new(FreeListAllocator::new(from, PlacementPolicy::FindFirst));
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptrs = [0x11, 0x22, 0x33].map(|byte| unsafe {
+            let ptr = heap.alloc(layout);
+            ptr.write_bytes(byte, 64);
+            ptr
+        });
+        unsafe { heap.dealloc(ptrs[1], layout) };
+
+        let mut state = Vec::new();
+        heap.lock_with(|heap| heap.save_state(&mut state)).unwrap();
+
+        let restored = SpinLock::new(FreeListAllocator::new(to, PlacementPolicy::FindFirst));
+        unsafe { restored.lock_with(|heap| heap.restore_state(&state[..])) }.unwrap();
+
+        // the blocks are where they were, relative to the arena
+        let moved =
+            |ptr: *mut u8| unsafe { ptr.offset(to.start() as isize - from.start() as isize) };
+        assert_eq!(unsafe { *moved(ptrs[2]) }, 0x33);
+        assert_eq!(
+            heap.lock_with(|heap| heap.free_blocks().collect::<Vec<_>>()),
+            restored.lock_with(|heap| heap.free_blocks().collect::<Vec<_>>())
+        );
+
+        // and the freed one is handed out again
+        assert_eq!(unsafe { restored.alloc(layout) }, moved(ptrs[1]));
+        unsafe { restored.dealloc(moved(ptrs[0]), layout) };
+    }
+
+    // a long session of allocations of a few sizes and random lifetimes under `FindBest`,
+    // returns the sizes of the free blocks at the end
+    fn churn(min_remainder: usize) -> Vec<usize> {
+        let mut memory = vec![0u64; 16 * 1024 / 8];
+        let arena = unsafe { Region::from_raw_parts(memory.as_mut_ptr().cast(), 16 * 1024) };
+        let mut allocator = FreeListAllocator::new(arena, PlacementPolicy::FindBest)
+            .with_min_remainder(min_remainder);
+
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize % bound
+        };
+
+        let mut live = Vec::new();
+        for _ in 0..20_000 {
+            if live.len() < 80 && random(3) != 0 {
+                let size = [24, 40, 56, 120, 248, 128][random(6)];
+                let layout = Layout::from_size_align(size, 8).unwrap();
+
+                let ptr = allocator.allocate(layout);
+                assert!(!ptr.is_null());
+                live.push((ptr, layout));
+            } else if !live.is_empty() {
+                let (ptr, layout) = live.swap_remove(random(live.len()));
+                allocator.deallocate(ptr, layout);
+            }
+        }
+
+        allocator.coalesce_pending();
+        allocator.free_blocks().map(|(_, size)| size).collect()
+    }
+
+    #[test]
+    fn test_min_remainder() {
+        let slivers = |sizes: &[usize]| sizes.iter().filter(|&&size| size < 64).count();
+
+        let before = churn(0);
+        let after = churn(64);
+        // the same session ends with fewer free blocks too small for most of its allocations,
+        // 2 instead of 7 with the default features
+        assert!(slivers(&after) < slivers(&before));
+    }
+
+    #[test]
+    fn test_min_split_size() {
+        static ARENA: Arena<1024> = Arena::new();
+
+        // leaves 32 bytes at the end of the arena
+        let size = 1024 - 32 - FreeListAllocator::overhead_per_alloc();
+        let layout = Layout::from_size_align(size, 8).unwrap();
+
+        // which are split off by default
+        let mut allocator =
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst);
+        let ptr = allocator.allocate(layout);
+        assert_eq!(
+            allocator.free_blocks().collect::<Vec<_>>(),
+            [(1024 - 32, 32)]
+        );
+        allocator.deallocate(ptr, layout);
+
+        // and handed out with the block under a larger threshold
+        let mut allocator =
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst)
+                .with_min_split_size(64);
+        let ptr = allocator.allocate(layout);
+        assert_eq!(allocator.free_blocks().count(), 0);
+
+        // the whole block comes back when it's freed
+        allocator.deallocate(ptr, layout);
+        assert_eq!(allocator.free_blocks().collect::<Vec<_>>(), [(0, 1024)]);
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let (largest, padding) = (whole_grains(MAX_BLOCK_SIZE), whole_grains(MAX_PADDING));
+        let header = AllocationHeader::new(largest, padding);
+        assert_eq!((header.block_size(), header.padding()), (largest, padding));
+
+        // page aligned blocks fit the padding on every target
+        let padding = calc_padding_with_header(1, 4096, size_of::<AllocationHeader>()).unwrap();
+        assert!(padding <= MAX_PADDING);
+    }
+
+    #[test]
+    fn test_large_alignment() {
+        static ARENA: Arena<{ 16 * 1024 }> = Arena::new();
+        let mut allocator =
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst);
+
+        // DMA buffers and vector tables
+        for align in [256, 512, 4096] {
+            let layout = Layout::from_size_align(64, align).unwrap();
+            let ptr = allocator.allocate(layout);
+            assert!(!ptr.is_null() && (ptr as usize).is_multiple_of(align));
+            allocator.deallocate(ptr, layout);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "compact-header")]
+    fn test_compact_header() {
+        static ARENA: Arena<1024> = Arena::new();
+
+        // the bytes past the last whole word are left out
+        let region = unsafe { Region::from_raw_parts(ARENA.region().start() as *mut u8, 1021) };
+        let mut allocator = FreeListAllocator::new(region, PlacementPolicy::FindFirst);
+        assert_eq!(allocator.free_blocks().collect::<Vec<_>>(), [(0, 1016)]);
+
+        let layout = Layout::from_size_align(13, 4).unwrap();
+        let ptr = allocator.allocate(layout);
+        let header =
+            allocator.header(ptr as usize - size_of::<AllocationHeader>() - region.start());
+        assert_eq!(
+            header.block_size(),
+            FreeListAllocator::overhead_per_alloc() + 16
+        );
+
+        allocator.deallocate(ptr, layout);
+        assert_eq!(
+            allocator.free_blocks().map(|(_, size)| size).sum::<usize>(),
+            1016
+        );
+    }
+
+    #[test]
+    fn test_min_align() {
+        static ARENA: Arena<1024> = Arena::new();
+        let layout = Layout::new::<u8>();
+
+        let mut allocator =
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst)
+                .with_min_align(64);
+        for _ in 0..4 {
+            assert!((allocator.allocate(layout) as usize).is_multiple_of(64));
+        }
+
+        // lower than a node's alignment is raised to it
+        let allocator =
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst)
+                .with_min_align(1);
+        assert_eq!(allocator.min_align, FreeListAllocator::MIN_ALIGN);
+    }
+
+    #[test]
+    fn test_huge_layout() {
+        static ARENA: Arena<1024> = Arena::new();
+
+        for policy in [PlacementPolicy::FindFirst, PlacementPolicy::FindBest] {
+            let mut allocator = FreeListAllocator::new(unsafe { ARENA.region() }, policy);
+
+            // larger than any header can describe
+            let layout = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+            assert!(allocator.allocate(layout).is_null());
+
+            let size = (MAX_BLOCK_SIZE / 2).min(isize::MAX as usize / 2);
+            let layout = Layout::from_size_align(size, 64).unwrap();
+            assert!(allocator.allocate(layout).is_null());
+
+            assert!(!allocator.allocate(Layout::new::<u64>()).is_null());
+        }
+    }
+}
+
+// proofs that splitting and coalescing keep the blocks apart and account for every byte, for
+// any two allocations freed in any order, run with `cargo kani`
+#[cfg(kani)]
+mod proofs {
+    use super::*;
+    use crate::Arena;
+
+    const ARENA_SIZE: usize = 256;
+
+    fn any_layout() -> Layout {
+        let size: usize = kani::any();
+        let shift: u32 = kani::any();
+        kani::assume(size > 0 && size <= 64 && shift <= 5);
+
+        Layout::from_size_align(size, 1 << shift).unwrap()
+    }
+
+    // the free blocks are in bounds and apart from each other and the live blocks, returns
+    // the bytes they add up to
+    fn check_free_blocks(allocator: &FreeListAllocator, live: &[(*mut u8, Layout)]) -> usize {
+        let start = allocator.arena.start();
+        let mut total = 0;
+
+        for (offset, size) in allocator.free_blocks() {
+            assert!(offset + size <= ARENA_SIZE);
+
+            for &(ptr, layout) in live {
+                let data = ptr as usize - start;
+                assert!(offset + size <= data || data + layout.size() <= offset);
+            }
+            total += size;
+        }
+
+        total
+    }
+
+    #[kani::proof]
+    #[kani::unwind(6)]
+    fn split_and_coalesce_conserve_the_arena() {
+        static ARENA: Arena<ARENA_SIZE> = Arena::new();
+
+        let policy = if kani::any() {
+            PlacementPolicy::FindFirst
+        } else {
+            PlacementPolicy::FindBest
+        };
+        let mut allocator = FreeListAllocator::new(unsafe { ARENA.region() }, policy);
+
+        let (first, second) = (any_layout(), any_layout());
+        let a = allocator.allocate(first);
+        let b = allocator.allocate(second);
+        kani::assume(!a.is_null() && !b.is_null());
+
+        // both blocks are aligned, in bounds and apart
+        let region = unsafe { ARENA.region() };
+        for (ptr, layout) in [(a, first), (b, second)] {
+            assert!((ptr as usize).is_multiple_of(layout.align()));
+            assert!(region.start() <= ptr as usize && ptr as usize + layout.size() <= region.end());
+        }
+        assert!(
+            a as usize + first.size() <= b as usize || b as usize + second.size() <= a as usize
+        );
+        check_free_blocks(&allocator, &[(a, first), (b, second)]);
+
+        // freed in either order, the blocks merge back into the whole arena
+        if kani::any() {
+            allocator.deallocate(a, first);
+            check_free_blocks(&allocator, &[(b, second)]);
+            allocator.deallocate(b, second);
+        } else {
+            allocator.deallocate(b, second);
+            check_free_blocks(&allocator, &[(a, first)]);
+            allocator.deallocate(a, first);
+        }
+        assert_eq!(check_free_blocks(&allocator, &[]), ARENA_SIZE);
+
+        let whole = ARENA_SIZE - FreeListAllocator::overhead_per_alloc();
+        let layout = Layout::from_size_align(whole, 8).unwrap();
+        assert!(!allocator.allocate(layout).is_null());
+    }
 }
@@ -0,0 +1,139 @@
+/// Declares a `static` [`Arena`](crate::Arena) of the given size.
+///
+/// An optional `link_section` places the storage in a specific linker section, which is how
+/// embedded targets put the heap in a particular memory region (CCM RAM, external SDRAM, ...).
+///
+/// ```
+/// rsalloc::static_arena!(HEAP, 4096, link_section = ".data.heap");
+///
+/// let region = unsafe { HEAP.region() };
+/// assert_eq!(region.size(), 4096);
+/// ```
+#[macro_export]
+macro_rules! static_arena {
+    ($(#[$attr:meta])* $vis:vis $name:ident, $size:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::Arena<{ $size }> = $crate::Arena::new();
+    };
+    ($(#[$attr:meta])* $vis:vis $name:ident, $size:expr, link_section = $section:literal) => {
+        $(#[$attr])*
+        #[link_section = $section]
+        $vis static $name: $crate::Arena<{ $size }> = $crate::Arena::new();
+    };
+}
+
+/// Declares the `#[global_allocator]` together with the arena backing it.
+///
+#[cfg_attr(feature = "freelist", doc = "```")]
+#[cfg_attr(not(feature = "freelist"), doc = "```ignore")]
+/// rsalloc::global_allocator!(FreeList, policy = FindBest, size = 1_048_576);
+///
+/// let numbers = vec![1, 2, 3];
+/// assert_eq!(numbers.iter().sum::<i32>(), 6);
+/// ```
+///
+/// The other allocators are declared with `Linear`, `BumpDown`, `Stack` and
+/// `Pool, chunk_size = ...`, each followed by the arena `size`.
+#[macro_export]
+macro_rules! global_allocator {
+    (FreeList, policy = $policy:ident, size = $size:expr) => {
+        $crate::global_allocator!(
+            @declare $crate::FreeListAllocator,
+            $size,
+            $crate::FreeListAllocator::new(
+                unsafe { ARENA.region() },
+                $crate::PlacementPolicy::$policy
+            )
+        );
+    };
+    (Linear, size = $size:expr) => {
+        $crate::global_allocator!(
+            @declare $crate::ArenaAllocator,
+            $size,
+            $crate::ArenaAllocator::new(unsafe { ARENA.region() })
+        );
+    };
+    (BumpDown, size = $size:expr) => {
+        $crate::global_allocator!(
+            @declare $crate::BumpDownAllocator,
+            $size,
+            $crate::BumpDownAllocator::new(unsafe { ARENA.region() })
+        );
+    };
+    (Stack, size = $size:expr) => {
+        $crate::global_allocator!(
+            @declare $crate::StackAllocator,
+            $size,
+            $crate::StackAllocator::new(unsafe { ARENA.region() })
+        );
+    };
+    (Pool, chunk_size = $chunk_size:expr, size = $size:expr) => {
+        $crate::global_allocator!(
+            @declare $crate::PoolAllocator,
+            $size,
+            $crate::PoolAllocator::new(unsafe { ARENA.region() }, $chunk_size)
+        );
+    };
+    (@declare $ty:ty, $size:expr, $allocator:expr) => {
+        const _: () = {
+            // out of reach of anything but `GLOBAL`, the one allocator its region is handed to
+            static ARENA: $crate::Arena<{ $size }> = $crate::Arena::new();
+
+            #[global_allocator]
+            static GLOBAL: $crate::SpinLock<$ty> = $crate::SpinLock::new($allocator);
+        };
+    };
+}
+
+// implements `GlobalAlloc` for an allocator behind a `SpinLock` and behind a `SingleThreaded`,
+// the methods only reach the allocator through `lock_with`, which both of them provide
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+macro_rules! impl_global_alloc {
+    ($allocator:ty, { $($methods:tt)* }) => {
+        unsafe impl<R: Relax> GlobalAlloc for SpinLock<$allocator, R> {
+            $($methods)*
+        }
+
+        unsafe impl GlobalAlloc for SingleThreaded<$allocator> {
+            $($methods)*
+        }
+    };
+}
+
+#[cfg(all(test, feature = "stack"))]
+mod tests {
+    use crate::stack::StackAllocator;
+    use crate::SpinLock;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    static_arena!(ARENA, 1024);
+
+    #[cfg(target_os = "linux")]
+    static_arena!(SECTION_ARENA, 1024, link_section = ".data.rsalloc_test");
+
+    #[test]
+    fn test_static_arena() {
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        assert_eq!(unsafe { ARENA.region() }.size(), 1024);
+
+        let ptr = unsafe { ALLOC.alloc(Layout::new::<u64>()) };
+        assert!(!ptr.is_null());
+        assert!(
+            unsafe { ARENA.region() }.start() <= ptr as usize
+                && (ptr as usize) < unsafe { ARENA.region() }.end()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_static_arena_link_section() {
+        assert_eq!(unsafe { SECTION_ARENA.region() }.size(), 1024);
+    }
+}
@@ -0,0 +1,201 @@
+use super::pool::PoolAllocator;
+use super::utils::{init_block, release_block};
+use super::SpinLock;
+use core::alloc::Layout;
+use core::mem;
+use core::ptr;
+
+// a stack of up to `M` free chunks
+struct Magazine<const M: usize> {
+    rounds: [*mut u8; M],
+    len: usize,
+}
+
+impl<const M: usize> Magazine<M> {
+    const fn new() -> Self {
+        Self {
+            rounds: [ptr::null_mut(); M],
+            len: 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == M
+    }
+}
+
+/// A cache of free chunks in front of a shared pool, following the magazine layer of Bonwick's
+/// slab allocator.
+///
+/// Each consumer (a thread, a CPU, a subsystem) owns a cache, and most allocations and frees
+/// only push and pop chunks of its magazines without taking the pool's lock. The pool is only
+/// locked to refill an empty magazine or to flush a full one, `M` chunks at a time.
+///
+/// ```ignore
+/// static POOL: SpinLock<PoolAllocator> =
+///     SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64));
+///
+/// let mut cache = MagazineCache::<16>::new(&POOL, Layout::new::<Node>());
+/// let node = cache.alloc();
+/// unsafe { cache.dealloc(node) };
+/// ```
+///
+/// Chunks still cached when the cache is dropped are returned to the pool.
+pub struct MagazineCache<'p, const M: usize> {
+    pool: &'p SpinLock<PoolAllocator>,
+    layout: Layout,
+    // the magazine chunks are taken from and freed to
+    loaded: Magazine<M>,
+    // the previously loaded magazine, kept so alternating allocations and frees at the edge of
+    // a magazine don't reach the pool every time
+    previous: Magazine<M>,
+}
+
+// SAFETY: the cached chunks belong to the cache alone, and the pool is shared behind its lock
+unsafe impl<const M: usize> Send for MagazineCache<'_, M> {}
+unsafe impl<const M: usize> Sync for MagazineCache<'_, M> {}
+
+impl<'p, const M: usize> MagazineCache<'p, M> {
+    /// Creates an empty cache handing out chunks of `pool` for blocks of `layout`.
+    pub const fn new(pool: &'p SpinLock<PoolAllocator>, layout: Layout) -> Self {
+        assert!(M > 0, "magazines need room for at least one chunk");
+
+        Self {
+            pool,
+            layout,
+            loaded: Magazine::new(),
+            previous: Magazine::new(),
+        }
+    }
+
+    /// Hands out a chunk, null if the magazines are empty and so is the pool.
+    pub fn alloc(&mut self) -> *mut u8 {
+        if self.loaded.len == 0 {
+            if self.previous.len > 0 {
+                mem::swap(&mut self.loaded, &mut self.previous);
+            } else {
+                self.loaded.len = self.pool.alloc_into(self.layout, &mut self.loaded.rounds);
+            }
+        }
+
+        if self.loaded.len == 0 {
+            return ptr::null_mut();
+        }
+
+        self.loaded.len -= 1;
+        unsafe { init_block(self.loaded.rounds[self.loaded.len], self.layout.size()) }
+    }
+
+    /// Takes back a chunk handed out by [`alloc`](Self::alloc).
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by a cache of the same pool, and not be freed already.
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8) {
+        if self.loaded.is_full() {
+            if self.previous.len > 0 {
+                self.flush_previous();
+            }
+            mem::swap(&mut self.loaded, &mut self.previous);
+        }
+
+        unsafe { release_block(ptr, self.layout.size()) };
+
+        self.loaded.rounds[self.loaded.len] = ptr;
+        self.loaded.len += 1;
+    }
+
+    /// Number of chunks held by the cache.
+    pub fn cached(&self) -> usize {
+        self.loaded.len + self.previous.len
+    }
+
+    fn flush_previous(&mut self) {
+        let rounds = &self.previous.rounds[..self.previous.len];
+        unsafe { self.pool.dealloc_batch(rounds, self.layout) };
+        self.previous.len = 0;
+    }
+}
+
+impl<const M: usize> Drop for MagazineCache<'_, M> {
+    fn drop(&mut self) {
+        self.flush_previous();
+        mem::swap(&mut self.loaded, &mut self.previous);
+        self.flush_previous();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arena;
+
+    #[test]
+    fn test_refill_and_flush() {
+        static ARENA: Arena<1024> = Arena::new();
+        static POOL: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64));
+
+        let layout = Layout::new::<[u8; 64]>();
+        // chunks left in the pool, counted by taking all of them and giving them back
+        let free_in_pool = || {
+            let mut chunks = [ptr::null_mut(); 16];
+            let count = POOL.alloc_into(layout, &mut chunks);
+            unsafe { POOL.dealloc_batch(&chunks[..count], layout) };
+            count
+        };
+
+        let mut cache = MagazineCache::<4>::new(&POOL, layout);
+
+        // the first allocation loads a whole magazine from the pool
+        let first = cache.alloc();
+        assert_eq!(free_in_pool(), 12);
+        assert_eq!(cache.cached(), 3);
+
+        let mut chunks = [(); 3].map(|_| cache.alloc());
+        assert_eq!(free_in_pool(), 12);
+
+        // the loaded magazine is empty, so the next one is loaded
+        let fifth = cache.alloc();
+        assert_eq!(free_in_pool(), 8);
+
+        // frees fill the magazines up without reaching the pool
+        unsafe {
+            cache.dealloc(first);
+            cache.dealloc(fifth);
+            for chunk in chunks {
+                cache.dealloc(chunk);
+            }
+        }
+        assert_eq!(cache.cached(), 8);
+
+        // freed chunks are handed out again
+        chunks = [(); 3].map(|_| cache.alloc());
+        assert!(chunks.iter().all(|&chunk| !chunk.is_null()));
+        assert_eq!(free_in_pool(), 8);
+
+        unsafe {
+            for chunk in chunks {
+                cache.dealloc(chunk);
+            }
+        }
+        drop(cache);
+
+        // dropping the cache returned every chunk to the pool
+        assert_eq!(free_in_pool(), 16);
+    }
+
+    #[test]
+    fn test_pool_exhausted() {
+        static ARENA: Arena<256> = Arena::new();
+        static POOL: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64));
+
+        let mut cache = MagazineCache::<8>::new(&POOL, Layout::new::<[u8; 64]>());
+
+        // the magazine is only partially loaded
+        let chunks = [(); 4].map(|_| cache.alloc());
+        assert!(chunks.iter().all(|&chunk| !chunk.is_null()));
+        assert!(cache.alloc().is_null());
+    }
+}
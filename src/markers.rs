@@ -0,0 +1,173 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ops::{Deref, DerefMut};
+
+/// Gives a value the `Send` it doesn't have, e.g. an allocator of your own holding raw pointers
+/// into memory it owns, so it can go behind a [`SpinLock`](crate::SpinLock) in a `static`.
+///
+/// ```ignore
+/// static HEAP: SpinLock<AssertSend<MyAllocator>> =
+///     SpinLock::new(unsafe { AssertSend::new(MyAllocator::new()) });
+/// ```
+#[derive(Debug, Default)]
+pub struct AssertSend<T>(T);
+
+// SAFETY: the creator of the value promised it can move to another thread
+unsafe impl<T> Send for AssertSend<T> {}
+
+impl<T> AssertSend<T> {
+    /// # Safety
+    ///
+    /// The value must be fine to use from another thread than the one it was created on: the
+    /// pointers in it lead to memory no other thread accesses behind its back, and nothing in it
+    /// is tied to the thread (thread locals, `Rc`s shared with values left behind).
+    pub const unsafe fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/// Gives a value the `Sync` it doesn't have, for values whose shared methods synchronize on
+/// their own in a way the compiler can't see, e.g. an allocator built on a hardware semaphore.
+///
+/// Unlike [`SingleThreaded`](crate::SingleThreaded), which serializes the calls itself, the
+/// value is shared as it is.
+#[derive(Debug, Default)]
+pub struct AssertSync<T>(T);
+
+// SAFETY: the creator of the value promised it can be shared between threads
+unsafe impl<T> Sync for AssertSync<T> {}
+
+impl<T> AssertSync<T> {
+    /// # Safety
+    ///
+    /// Every method reachable through `&T` must be safe to call from several threads at once,
+    /// and the value must be fine to use from another thread than the one it was created on.
+    pub const unsafe fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+macro_rules! impl_marker {
+    ($marker:ident) => {
+        impl<T> Deref for $marker<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $marker<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+
+        unsafe impl<T: GlobalAlloc> GlobalAlloc for $marker<T> {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                unsafe { self.0.alloc(layout) }
+            }
+
+            unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+                unsafe { self.0.alloc_zeroed(layout) }
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                unsafe { self.0.dealloc(ptr, layout) }
+            }
+
+            unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+                unsafe { self.0.realloc(ptr, layout, new_size) }
+            }
+        }
+    };
+}
+
+impl_marker!(AssertSend);
+impl_marker!(AssertSync);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SpinLock;
+    use core::ptr;
+
+    fn send_sync<T: Send + Sync>() {}
+
+    // the allocators are `Send` and `Sync` behind their locks, so they fit in a `static` of any
+    // wrapper that is itself `Sync` when what it holds is
+    #[test]
+    fn test_allocators_are_send_sync() {
+        send_sync::<AssertSync<AssertSend<*mut u8>>>();
+
+        #[cfg(feature = "linear")]
+        send_sync::<SpinLock<crate::ArenaAllocator>>();
+        #[cfg(feature = "linear")]
+        send_sync::<crate::ArenaBox<'static, u32>>();
+        #[cfg(feature = "stack")]
+        send_sync::<SpinLock<crate::StackAllocator>>();
+        #[cfg(feature = "pool")]
+        send_sync::<SpinLock<crate::PoolAllocator>>();
+        #[cfg(feature = "pool")]
+        send_sync::<crate::MagazineCache<'static, 8>>();
+        #[cfg(feature = "freelist")]
+        send_sync::<SpinLock<crate::FreeListAllocator>>();
+        #[cfg(feature = "freelist")]
+        send_sync::<crate::ShardedAllocator<2>>();
+        #[cfg(feature = "freelist")]
+        send_sync::<crate::PerCpu<SpinLock<crate::FreeListAllocator>, 2>>();
+    }
+
+    // an allocator holding a raw pointer, which isn't `Send` on its own
+    struct Bump {
+        next: *mut u8,
+        end: *mut u8,
+    }
+
+    unsafe impl GlobalAlloc for SpinLock<AssertSend<Bump>> {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.lock_with(|bump| {
+                let start = bump
+                    .next
+                    .wrapping_add(bump.next.align_offset(layout.align()));
+                if start.wrapping_add(layout.size()) > bump.end {
+                    return ptr::null_mut();
+                }
+
+                bump.next = start.wrapping_add(layout.size());
+                start
+            })
+        }
+
+        unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+    }
+
+    #[test]
+    fn test_assert_send() {
+        static ARENA: crate::Arena<128> = crate::Arena::new();
+        static HEAP: SpinLock<AssertSend<Bump>> = SpinLock::new(unsafe {
+            AssertSend::new(Bump {
+                next: ptr::null_mut(),
+                end: ptr::null_mut(),
+            })
+        });
+
+        HEAP.lock_with(|bump| {
+            let region = unsafe { ARENA.region() };
+            bump.next = region.start() as *mut u8;
+            bump.end = region.end() as *mut u8;
+        });
+
+        let ptr = std::thread::spawn(|| unsafe { HEAP.alloc(Layout::new::<u64>()) } as usize)
+            .join()
+            .unwrap();
+        assert_eq!(ptr, unsafe { ARENA.region() }.start());
+    }
+}
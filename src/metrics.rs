@@ -0,0 +1,199 @@
+//! A registry of allocator instances and their usage.
+//!
+//! Each allocator registers itself under a name once, and [`snapshot`] then reports the usage
+//! of all of them at once, e.g. a frame arena, a pool and the general heap:
+//!
+//! ```ignore
+//! static FRAME: SpinLock<ArenaAllocator> = ...;
+//! static HEAP: SpinLock<FreeListAllocator> = ...;
+//!
+//! metrics::register("frame", &FRAME);
+//! metrics::register("heap", &HEAP);
+//!
+//! for (name, usage) in metrics::snapshot().iter() {
+//!     log!("{name}: {} of {} bytes", usage.used, usage.capacity);
+//! }
+//! ```
+
+use super::RwSpinLock;
+
+/// Number of allocators that can be registered.
+pub const MAX_ALLOCATORS: usize = 16;
+
+/// Memory use of an allocator, in bytes of its arena.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Usage {
+    /// Bytes taken up by live blocks, their headers and padding, or lost to fragmentation the
+    /// allocator can't reuse.
+    pub used: usize,
+    /// Size of the arena.
+    pub capacity: usize,
+}
+
+/// An allocator that can report its [`Usage`].
+pub trait Metered {
+    fn usage(&self) -> Usage;
+}
+
+type Entry = (&'static str, &'static (dyn Metered + Sync));
+
+// snapshots only read the registry, so they don't wait for each other
+static REGISTRY: RwSpinLock<[Option<Entry>; MAX_ALLOCATORS]> =
+    RwSpinLock::new([None; MAX_ALLOCATORS]);
+
+/// Adds `allocator` to the registry under `name`. Returns `false` if [`MAX_ALLOCATORS`] are
+/// registered already.
+pub fn register(name: &'static str, allocator: &'static (dyn Metered + Sync)) -> bool {
+    REGISTRY.write_with(
+        |entries| match entries.iter_mut().find(|entry| entry.is_none()) {
+            Some(free) => {
+                *free = Some((name, allocator));
+                true
+            }
+            None => false,
+        },
+    )
+}
+
+/// Removes the allocator registered under `name`, returning whether there was one.
+pub fn unregister(name: &str) -> bool {
+    REGISTRY.write_with(|entries| {
+        match entries
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|(entry_name, _)| entry_name == name))
+        {
+            Some(entry) => {
+                *entry = None;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Usage of every allocator in the registry.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    entries: [Option<(&'static str, Usage)>; MAX_ALLOCATORS],
+}
+
+impl Snapshot {
+    /// The registered allocators in the order they were registered in.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Usage)> + '_ {
+        self.entries.iter().flatten().copied()
+    }
+
+    /// Usage of the allocator registered under `name`.
+    pub fn get(&self, name: &str) -> Option<Usage> {
+        self.iter()
+            .find(|&(entry_name, _)| entry_name == name)
+            .map(|(_, usage)| usage)
+    }
+
+    /// Usage summed over all allocators.
+    pub fn total(&self) -> Usage {
+        self.iter()
+            .fold(Usage::default(), |total, (_, usage)| Usage {
+                used: total.used + usage.used,
+                capacity: total.capacity + usage.capacity,
+            })
+    }
+}
+
+// a map from the allocators' names to their usage
+#[cfg(feature = "serde")]
+impl serde::Serialize for Snapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+/// Reports the usage of every registered allocator.
+pub fn snapshot() -> Snapshot {
+    // the allocators are asked after the registry is unlocked, they take their own locks
+    let entries = REGISTRY.read_with(|entries| *entries);
+
+    Snapshot {
+        entries: entries.map(|entry| entry.map(|(name, allocator)| (name, allocator.usage()))),
+    }
+}
+
+#[cfg(all(test, feature = "linear", feature = "pool", feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{
+        Arena, ArenaAllocator, FreeListAllocator, PlacementPolicy, PoolAllocator, SpinLock,
+    };
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_snapshot() {
+        static FRAME_ARENA: Arena<1024> = Arena::new();
+        static FRAME: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { FRAME_ARENA.region() }));
+        static POOL_ARENA: Arena<1024> = Arena::new();
+        static POOL: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { POOL_ARENA.region() }, 64));
+        static HEAP_ARENA: Arena<1024> = Arena::new();
+        static HEAP: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { HEAP_ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        assert!(register("test_snapshot::frame", &FRAME));
+        assert!(register("test_snapshot::pool", &POOL));
+        assert!(register("test_snapshot::heap", &HEAP));
+
+        let layout = Layout::from_size_align(48, 8).unwrap();
+        let (chunk, block) = unsafe {
+            FRAME.alloc(layout);
+            (POOL.alloc(layout), HEAP.alloc(layout))
+        };
+
+        let snapshot = snapshot();
+        let usage = |name| snapshot.get(name).unwrap();
+        assert_eq!(usage("test_snapshot::frame").used, 48);
+        assert_eq!(usage("test_snapshot::pool").used, 64);
+        assert_eq!(
+            usage("test_snapshot::heap").used,
+            FreeListAllocator::overhead_per_alloc() + 48
+        );
+        assert_eq!(usage("test_snapshot::heap").capacity, 1024);
+
+        unsafe {
+            POOL.dealloc(chunk, layout);
+            HEAP.dealloc(block, layout);
+        }
+
+        let snapshot = super::snapshot();
+        assert_eq!(snapshot.get("test_snapshot::pool").unwrap().used, 0);
+        assert_eq!(snapshot.get("test_snapshot::heap").unwrap().used, 0);
+
+        assert!(unregister("test_snapshot::frame"));
+        assert!(!unregister("test_snapshot::frame"));
+        assert_eq!(super::snapshot().get("test_snapshot::frame"), None);
+
+        unregister("test_snapshot::pool");
+        unregister("test_snapshot::heap");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serialize() {
+        static ARENA: Arena<1024> = Arena::new();
+        static FRAME: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
+        register("test_serialize::frame", &FRAME);
+        unsafe { FRAME.alloc(Layout::new::<[u8; 100]>()) };
+
+        let json = serde_json::to_value(snapshot()).unwrap();
+        assert_eq!(
+            json["test_serialize::frame"],
+            serde_json::json!({ "used": 100, "capacity": 1024 })
+        );
+
+        unregister("test_serialize::frame");
+    }
+}
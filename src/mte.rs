@@ -0,0 +1,129 @@
+//! Memory tagging of the blocks handed out by the pool and free list allocators, with the
+//! Memory Tagging Extension of aarch64.
+//!
+//! Every allocated block gets a random 4 bit tag, set both on its memory and in the top byte of
+//! the pointer handed out. Freed blocks go back to tag 0, which is never handed out, so the
+//! hardware faults on a use after free, and most likely on an overflow into a neighbour (unless
+//! it happens to share the tag, a 1 in 15 chance).
+//!
+//! The arena has to be tag checked memory and checks have to be enabled, e.g. on Linux:
+//!
+//! ```ignore
+//! unsafe {
+//!     libc::prctl(
+//!         libc::PR_SET_TAGGED_ADDR_CTRL,
+//!         libc::PR_TAGGED_ADDR_ENABLE | libc::PR_MTE_TCF_SYNC | (0xfffe << libc::PR_MTE_TAG_SHIFT),
+//!         0, 0, 0,
+//!     );
+//!     let arena = libc::mmap(ptr::null_mut(), size, PROT_READ | PROT_WRITE | PROT_MTE, ...);
+//! }
+//! ```
+//!
+//! The batches of [`PoolAllocator::alloc_into`](crate::PoolAllocator::alloc_into) are tagged a
+//! chunk at a time like single allocations. A [`MagazineCache`](crate::MagazineCache) keeps the
+//! tag of a chunk while it's cached, so a use after free only faults once the chunk went back to
+//! the pool. On other architectures the feature does nothing.
+
+// only the pool and free list allocators tag their blocks
+#![cfg_attr(not(any(feature = "pool", feature = "freelist")), allow(dead_code))]
+
+/// Memory is tagged in granules of 16 bytes, the blocks are aligned and sized to match.
+pub(crate) const GRANULE: usize = 16;
+
+// the tag bits of a pointer
+#[cfg(target_arch = "aarch64")]
+const TAG_MASK: usize = 0xF << 56;
+
+/// Tags the `size` bytes at `ptr` (rounded up to whole granules) with a random non-zero tag,
+/// returns `ptr` carrying the tag.
+///
+/// # Safety
+///
+/// `ptr` must be aligned to a granule, and the memory up to the end of the last granule must
+/// belong to the block.
+#[inline]
+pub(crate) unsafe fn tag(ptr: *mut u8, size: usize) -> *mut u8 {
+    if ptr.is_null() {
+        return ptr;
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let tagged = random_tag(ptr);
+        set_tags(tagged, size);
+        tagged
+    }
+
+    #[cfg(not(target_arch = "aarch64"))]
+    {
+        let _ = size;
+        ptr
+    }
+}
+
+/// Sets the `size` bytes of the block `ptr` points to back to tag 0, returns the pointer
+/// without its tag.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [`tag`] for a block of `size` bytes.
+#[inline]
+pub(crate) unsafe fn untag(ptr: *mut u8, size: usize) -> *mut u8 {
+    let ptr = strip(ptr);
+
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        set_tags(ptr, size)
+    };
+    #[cfg(not(target_arch = "aarch64"))]
+    let _ = size;
+
+    ptr
+}
+
+/// The address `ptr` points to, without its tag.
+#[inline]
+pub(crate) fn strip(ptr: *mut u8) -> *mut u8 {
+    #[cfg(target_arch = "aarch64")]
+    return (ptr as usize & !TAG_MASK) as *mut u8;
+
+    #[cfg(not(target_arch = "aarch64"))]
+    ptr
+}
+
+// `ptr` with a random tag other than 0
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "mte")]
+unsafe fn random_tag(ptr: *mut u8) -> *mut u8 {
+    let tagged: *mut u8;
+    unsafe {
+        core::arch::asm!(
+            "irg {tagged}, {ptr}, {exclude}",
+            tagged = lateout(reg) tagged,
+            ptr = in(reg) ptr,
+            // the tags to leave out, one bit each
+            exclude = in(reg) 1usize,
+            options(nomem, nostack, preserves_flags),
+        )
+    };
+    tagged
+}
+
+// sets the tag of every granule of the block to the one `ptr` carries
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "mte")]
+unsafe fn set_tags(ptr: *mut u8, size: usize) {
+    let mut granule = ptr;
+    let end = ptr.wrapping_add(size);
+
+    while granule < end {
+        unsafe {
+            core::arch::asm!(
+                "stg {granule}, [{granule}]",
+                granule = in(reg) granule,
+                options(nostack, preserves_flags),
+            )
+        };
+        granule = granule.wrapping_add(GRANULE);
+    }
+}
@@ -0,0 +1,185 @@
+use super::linked_list::FreeListAllocator;
+use super::utils::zeroed;
+#[cfg(not(loom))]
+use super::Region;
+use super::{RangeTable, SpinLock};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// Manages several disjoint regions of memory, e.g. fast SRAM and external RAM.
+///
+/// Allocations are served by the first region (in the given order) that has enough memory,
+/// deallocations are routed to the region that owns the pointer.
+#[derive(Debug)]
+pub struct MultiRegionAllocator<const N: usize> {
+    regions: [SpinLock<FreeListAllocator>; N],
+    ranges: Option<RangeTable<N>>,
+}
+
+impl<const N: usize> MultiRegionAllocator<N> {
+    /// Creates the allocator, `regions` are ordered from highest to lowest priority.
+    pub const fn new(regions: [SpinLock<FreeListAllocator>; N]) -> Self {
+        Self {
+            regions,
+            ranges: None,
+        }
+    }
+
+    /// Finds the region a freed block belongs to in a [`RangeTable`] of the allocators' memory,
+    /// instead of asking every allocator in turn. The table is built from the regions the
+    /// allocators have at this point, so it's no use for ones created
+    /// [`empty`](FreeListAllocator::empty) or growing later on.
+    #[cfg(not(loom))]
+    pub const fn with_range_table(mut self) -> Self {
+        let mut ranges = [Region::empty(); N];
+
+        let mut i = 0;
+        while i < N {
+            ranges[i] = self.regions[i].get_mut().arena();
+            i += 1;
+        }
+
+        self.ranges = Some(RangeTable::new(ranges));
+        self
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for MultiRegionAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        for region in self.regions.iter() {
+            let ptr = unsafe { region.alloc(layout) };
+
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+
+        // every region is out of memory
+        ptr::null_mut()
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let region = match &self.ranges {
+            Some(ranges) => {
+                let index = ranges.find(ptr);
+
+                // the allocators got memory the table doesn't know about
+                debug_assert_eq!(
+                    index,
+                    self.regions.iter().position(|region| region.owns(ptr)),
+                    "range table out of date"
+                );
+                index.map(|index| &self.regions[index])
+            }
+            None => self.regions.iter().find(|region| region.owns(ptr)),
+        };
+
+        // pointers that don't belong to any region are ignored
+        if let Some(region) = region {
+            unsafe { region.dealloc(ptr, layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linked_list::PlacementPolicy;
+    use crate::Arena;
+
+    #[test]
+    fn test_priority_order() {
+        static FAST: Arena<1024> = Arena::new();
+        static SLOW: Arena<4096> = Arena::new();
+        static ALLOC: MultiRegionAllocator<2> = MultiRegionAllocator::new([
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { FAST.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { SLOW.region() },
+                PlacementPolicy::FindFirst,
+            )),
+        ]);
+
+        let layout = Layout::new::<[u64; 64]>();
+
+        // the first allocation fits in the fast region
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        assert!(
+            unsafe { FAST.region() }.start() <= ptr_1 as usize
+                && (ptr_1 as usize) < unsafe { FAST.region() }.end()
+        );
+
+        // the fast region is full, so the slow one is used
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        assert!(
+            unsafe { SLOW.region() }.start() <= ptr_2 as usize
+                && (ptr_2 as usize) < unsafe { SLOW.region() }.end()
+        );
+
+        // freeing goes back to the owning region, which is preferred again
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        let ptr_3 = unsafe { ALLOC.alloc(layout) };
+        assert_eq!(ptr_1, ptr_3);
+
+        unsafe { ALLOC.dealloc(ptr_2, layout) };
+        unsafe { ALLOC.dealloc(ptr_3, layout) };
+    }
+
+    #[test]
+    fn test_ranges() {
+        static FIRST: Arena<1024> = Arena::new();
+        static SECOND: Arena<1024> = Arena::new();
+        static THIRD: Arena<1024> = Arena::new();
+        static ALLOC: MultiRegionAllocator<3> = MultiRegionAllocator::new([
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { FIRST.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { SECOND.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { THIRD.region() },
+                PlacementPolicy::FindFirst,
+            )),
+        ])
+        .with_range_table();
+
+        // fills every region, then frees the blocks through the table
+        let layout = Layout::new::<[u64; 96]>();
+        let ptrs: Vec<_> = (0..3).map(|_| unsafe { ALLOC.alloc(layout) }).collect();
+        assert!(unsafe { THIRD.region() }.contains(ptrs[2]));
+
+        for &ptr in ptrs.iter().rev() {
+            unsafe { ALLOC.dealloc(ptr, layout) };
+        }
+        assert_eq!(unsafe { ALLOC.alloc(layout) }, ptrs[0]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "range table out of date")]
+    fn test_range_table_out_of_date() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: MultiRegionAllocator<2> = MultiRegionAllocator::new([
+            SpinLock::new(FreeListAllocator::empty(PlacementPolicy::FindFirst)),
+            SpinLock::new(FreeListAllocator::empty(PlacementPolicy::FindFirst)),
+        ])
+        .with_range_table();
+
+        // the memory comes after the table was built
+        unsafe { ALLOC.regions[1].init(ARENA.region()) };
+
+        let layout = Layout::new::<u64>();
+        let ptr = unsafe { ALLOC.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe { ALLOC.dealloc(ptr, layout) };
+    }
+}
@@ -0,0 +1,129 @@
+use core::fmt;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const INCOMPLETE: u8 = 0;
+const RUNNING: u8 = 1;
+const COMPLETE: u8 = 2;
+
+/// A one-time initialization, e.g. for an allocator that sets its memory up on first use.
+///
+/// The first caller runs the initialization while the others spin, and every caller returns
+/// once it's done, seeing everything it wrote. Unlike `std::sync::Once` it works without `std`,
+/// and an initialization that panics is run again by the next caller.
+pub struct Once {
+    state: AtomicU8,
+}
+
+// puts the state back if the initialization unwinds, so the next caller doesn't spin forever
+struct Unwinding<'a>(&'a AtomicU8);
+
+impl Drop for Unwinding<'_> {
+    fn drop(&mut self) {
+        self.0.store(INCOMPLETE, Ordering::Release);
+    }
+}
+
+impl Once {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(INCOMPLETE),
+        }
+    }
+
+    /// Runs `f` unless it ran already, waiting for a concurrent call to finish running it.
+    #[inline]
+    pub fn call_once(&self, f: impl FnOnce()) {
+        if !self.is_completed() {
+            self.call_once_slow(f);
+        }
+    }
+
+    #[cold]
+    fn call_once_slow(&self, f: impl FnOnce()) {
+        loop {
+            match self.state.compare_exchange_weak(
+                INCOMPLETE,
+                RUNNING,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    let unwinding = Unwinding(&self.state);
+                    f();
+                    core::mem::forget(unwinding);
+
+                    self.state.store(COMPLETE, Ordering::Release);
+                    return;
+                }
+                Err(COMPLETE) => return,
+                // another caller is running it, or the weak exchange failed spuriously
+                Err(_) => spin_loop(),
+            }
+        }
+    }
+
+    /// Whether the initialization ran, everything it wrote is visible if it did.
+    #[inline]
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
+
+impl Default for Once {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Once {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Once")
+            .field("completed", &self.is_completed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_call_once() {
+        static ONCE: Once = Once::new();
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        static VALUE: AtomicUsize = AtomicUsize::new(0);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    ONCE.call_once(|| {
+                        CALLS.fetch_add(1, Ordering::Relaxed);
+                        VALUE.store(42, Ordering::Relaxed);
+                    });
+
+                    // whoever ran it, the write is visible once `call_once` returns
+                    assert_eq!(VALUE.load(Ordering::Relaxed), 42);
+                });
+            }
+        });
+
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        assert!(ONCE.is_completed());
+        assert_eq!(format!("{ONCE:?}"), "Once { completed: true }");
+    }
+
+    #[test]
+    fn test_panicking_init_runs_again() {
+        let once = Once::new();
+
+        let panicked = std::panic::catch_unwind(|| once.call_once(|| panic!("init failed")));
+        assert!(panicked.is_err());
+        assert!(!once.is_completed());
+
+        let mut ran = false;
+        once.call_once(|| ran = true);
+        assert!(ran && once.is_completed());
+    }
+}
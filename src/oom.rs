@@ -0,0 +1,61 @@
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+static HANDLER: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Registers a callback that is given the failing layout before [`oom`] panics, e.g. to log it
+/// over a serial port.
+pub fn set_oom_handler(handler: fn(Layout)) {
+    HANDLER.store(handler as *mut (), Ordering::Release);
+}
+
+/// Reports an allocation failure to the registered callback and panics.
+///
+/// Meant to be used as the `#[alloc_error_handler]`, see [`oom_handler!`](crate::oom_handler).
+pub fn oom(layout: Layout) -> ! {
+    let handler = HANDLER.load(Ordering::Acquire);
+
+    if !handler.is_null() {
+        // SAFETY: the only non-null values stored are `fn(Layout)` pointers
+        let handler: fn(Layout) = unsafe { core::mem::transmute(handler) };
+        handler(layout);
+    }
+
+    panic!(
+        "memory allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align()
+    );
+}
+
+/// Installs [`oom`] as the `#[alloc_error_handler]`.
+///
+/// The attribute is still unstable, so the crate using the macro has to enable
+/// `#![feature(alloc_error_handler)]`.
+#[macro_export]
+macro_rules! oom_handler {
+    () => {
+        #[alloc_error_handler]
+        fn rsalloc_alloc_error_handler(layout: core::alloc::Layout) -> ! {
+            $crate::oom::oom(layout)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicUsize;
+
+    static FAILED_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_oom_calls_handler() {
+        set_oom_handler(|layout| FAILED_SIZE.store(layout.size(), Ordering::Relaxed));
+
+        let result = std::panic::catch_unwind(|| oom(Layout::new::<[u8; 48]>()));
+
+        assert!(result.is_err());
+        assert_eq!(FAILED_SIZE.load(Ordering::Relaxed), 48);
+    }
+}
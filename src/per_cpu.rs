@@ -0,0 +1,185 @@
+use super::stats::Counters;
+#[cfg(feature = "stats")]
+use super::AllocStats;
+use super::{RangeTable, Region};
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// One allocator per CPU, for kernels where every CPU should allocate from memory of its own
+/// without contending with the others.
+///
+/// `cpu_id` returns the index of the CPU the caller runs on, e.g. read from a per-CPU register,
+/// and every allocator is given the region it hands out blocks from:
+///
+/// ```ignore
+/// static HEAP: PerCpu<SpinLock<FreeListAllocator>, 2> = PerCpu::new(
+///     [SpinLock::new(...), SpinLock::new(...)],
+///     [unsafe { PARTS.region(0) }, unsafe { PARTS.region(1) }],
+///     cpu_id,
+/// );
+/// ```
+///
+/// Blocks freed on another CPU than the one they were allocated on are queued for their home CPU,
+/// which frees them the next time it allocates (or calls [`reclaim`](PerCpu::reclaim)). Blocks
+/// too small to be queued are freed on the home CPU's allocator right away.
+pub struct PerCpu<A, const N: usize> {
+    allocators: [A; N],
+    regions: RangeTable<N>,
+    cpu_id: fn() -> usize,
+    // blocks freed on other CPUs, waiting for their home CPU
+    remote_frees: [AtomicPtr<RemoteFree>; N],
+    counters: Counters,
+}
+
+// written over a block that waits in a remote free queue
+struct RemoteFree {
+    next: *mut RemoteFree,
+    layout: Layout,
+}
+
+impl<A, const N: usize> PerCpu<A, N> {
+    pub const fn new(allocators: [A; N], regions: [Region; N], cpu_id: fn() -> usize) -> Self {
+        Self {
+            allocators,
+            regions: RangeTable::new(regions),
+            cpu_id,
+            remote_frees: [const { AtomicPtr::new(ptr::null_mut()) }; N],
+            counters: Counters::new(),
+        }
+    }
+
+    /// The allocations and frees over all the CPUs, remote frees counted when they're queued.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> AllocStats {
+        self.counters.snapshot()
+    }
+
+    // the CPU whose region holds `ptr`
+    fn home(&self, ptr: *mut u8) -> Option<usize> {
+        self.regions.find(ptr)
+    }
+}
+
+impl<A: GlobalAlloc, const N: usize> PerCpu<A, N> {
+    /// Frees the blocks other CPUs queued for the current one.
+    pub fn reclaim(&self) {
+        let cpu = (self.cpu_id)();
+
+        // most of the time there's nothing to do, so the queue isn't written to then
+        if self.remote_frees[cpu].load(Ordering::Relaxed).is_null() {
+            return;
+        }
+
+        // only the home CPU takes blocks off its queue, and it takes all of them at once
+        let mut node = self.remote_frees[cpu].swap(ptr::null_mut(), Ordering::Acquire);
+        while !node.is_null() {
+            let RemoteFree { next, layout } = unsafe { node.read() };
+            unsafe { self.allocators[cpu].dealloc(node.cast(), layout) };
+            node = next;
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc, const N: usize> GlobalAlloc for PerCpu<A, N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.reclaim();
+        let ptr = unsafe { self.allocators[(self.cpu_id)()].alloc(layout) };
+        self.counters.allocated(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.reclaim();
+        let ptr = unsafe { self.allocators[(self.cpu_id)()].alloc_zeroed(layout) };
+        self.counters.allocated(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // pointers that don't belong to any CPU are ignored
+        let Some(home) = self.home(ptr) else {
+            return;
+        };
+        self.counters.freed(layout.size());
+
+        let fits_node = layout.size() >= size_of::<RemoteFree>()
+            && (ptr as usize).is_multiple_of(align_of::<RemoteFree>());
+        if home == (self.cpu_id)() || !fits_node {
+            unsafe { self.allocators[home].dealloc(ptr, layout) };
+            return;
+        }
+
+        let node = ptr.cast::<RemoteFree>();
+        let queue = &self.remote_frees[home];
+        let mut head = queue.load(Ordering::Relaxed);
+        loop {
+            unsafe { node.write(RemoteFree { next: head, layout }) };
+
+            match queue.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+    use core::sync::atomic::AtomicUsize;
+
+    static CPU: AtomicUsize = AtomicUsize::new(0);
+
+    static FIRST: Arena<1024> = Arena::new();
+    static SECOND: Arena<1024> = Arena::new();
+    static HEAP: PerCpu<SpinLock<FreeListAllocator>, 2> = PerCpu::new(
+        [
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { FIRST.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { SECOND.region() },
+                PlacementPolicy::FindFirst,
+            )),
+        ],
+        [unsafe { FIRST.region() }, unsafe { SECOND.region() }],
+        || CPU.load(Ordering::Relaxed),
+    );
+
+    #[test]
+    fn test_remote_free() {
+        let layout = Layout::new::<[u64; 8]>();
+        let tiny = Layout::new::<u8>();
+
+        CPU.store(0, Ordering::Relaxed);
+        let (block, tiny_block) = unsafe { (HEAP.alloc(layout), HEAP.alloc(tiny)) };
+        assert_eq!(HEAP.home(block), Some(0));
+
+        // freed on the second CPU, the block waits in the queue of the first one
+        CPU.store(1, Ordering::Relaxed);
+        unsafe { HEAP.dealloc(block, layout) };
+        assert_eq!(HEAP.remote_frees[0].load(Ordering::Relaxed), block.cast());
+        assert_eq!(HEAP.home(unsafe { HEAP.alloc(layout) }), Some(1));
+
+        // blocks too small for the queue are freed right away
+        unsafe { HEAP.dealloc(tiny_block, tiny) };
+        assert_eq!(HEAP.remote_frees[0].load(Ordering::Relaxed), block.cast());
+
+        // back on the first CPU, the block is freed and handed out again
+        CPU.store(0, Ordering::Relaxed);
+        assert_eq!(unsafe { HEAP.alloc(layout) }, block);
+        assert!(HEAP.remote_frees[0].load(Ordering::Relaxed).is_null());
+
+        // the queued free was counted right away
+        #[cfg(feature = "stats")]
+        {
+            let stats = HEAP.stats();
+            assert_eq!((stats.allocs, stats.deallocs), (4, 2));
+            assert_eq!(stats.live_bytes, 2 * layout.size());
+        }
+    }
+}
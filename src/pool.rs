@@ -1,154 +1,671 @@
-use super::{Arena, SpinLock, ARENA_SIZE};
-use core::alloc::GlobalAlloc;
+use super::link::LinkCodec;
+#[cfg(feature = "metrics")]
+use super::metrics::{Metered, Usage};
+#[cfg(feature = "mte")]
+use super::mte;
+#[cfg(feature = "quarantine")]
+use super::quarantine::Quarantine;
+#[cfg(feature = "std")]
+use super::save_state::{self, restore_arena, save_arena, SaveState};
+use super::utils::{align_forward, init_block, release_block, unpoison, zeroed};
+#[cfg(feature = "valgrind")]
+use super::valgrind;
+use super::{Region, Relax, SingleThreaded, SpinLock};
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::mem::{align_of, size_of};
 use core::ptr;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
 
-pub struct PoolAllocator<'a> {
-    arena: Arena,
+/// Number of chunks the debug builds of a [`PoolAllocator`] keep track of, frees of the chunks
+/// past them aren't checked.
+pub const DEBUG_TRACKED_CHUNKS: usize = 4096;
+
+pub struct PoolAllocator {
+    arena: Region,
     chunk_size: usize,
-    head: Option<&'a PoolFreeNode<'a>>,
-    initialized: bool,
+    chunk_align: usize,
+    // offset of the first free chunk in the arena
+    head: Option<usize>,
+    secret: usize,
+    #[cfg(feature = "quarantine")]
+    quarantine: Quarantine,
+    shuffle_seed: Option<u64>,
+    // chunks handed out from the untouched part of the arena so far
+    carved: usize,
+    rejected_frees: usize,
+    // a bit per chunk, set while it's handed out
+    #[cfg(debug_assertions)]
+    allocated: [u64; DEBUG_TRACKED_CHUNKS / 64],
 }
 
-struct PoolFreeNode<'a> {
-    next: Option<&'a PoolFreeNode<'a>>,
+#[derive(Clone, Copy)]
+struct PoolFreeNode {
+    // encoded by the allocator's `LinkCodec`
+    next: usize,
 }
 
 #[allow(dead_code)]
-impl PoolAllocator<'_> {
-    pub const fn new(chunk_size: usize) -> Self {
-        Self {
-            arena: Arena::new(),
+impl PoolAllocator {
+    /// Creates a pool handing out chunks of `chunk_size` bytes. A free chunk holds a link of the
+    /// free list, so the size has to be at least a word, which fails the build when the pool is
+    /// a `static`:
+    ///
+    #[cfg_attr(feature = "pool", doc = "```compile_fail")]
+    #[cfg_attr(not(feature = "pool"), doc = "```ignore")]
+    /// # use rsalloc::{Arena, PoolAllocator, SpinLock};
+    /// static ARENA: Arena<4096> = Arena::new();
+    /// static POOL: SpinLock<PoolAllocator> =
+    ///     SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 2));
+    /// ```
+    pub const fn new(arena: Region, chunk_size: usize) -> Self {
+        assert!(
+            chunk_size >= size_of::<PoolFreeNode>(),
+            "chunk size must fit a free list node"
+        );
+
+        let pool = Self {
+            arena,
             chunk_size,
+            chunk_align: align_of::<PoolFreeNode>(),
             head: None,
-            initialized: false,
+            secret: 0,
+            #[cfg(feature = "quarantine")]
+            quarantine: Quarantine::new(0),
+            shuffle_seed: None,
+            carved: 0,
+            rejected_frees: 0,
+            #[cfg(debug_assertions)]
+            allocated: [0; DEBUG_TRACKED_CHUNKS / 64],
+        };
+
+        // chunks are tagged a whole granule at a time
+        #[cfg(feature = "mte")]
+        let pool = pool.with_chunk_align(mte::GRANULE);
+
+        pool
+    }
+
+    /// Shuffles the order chunks are first handed out in with the given seed, so the addresses
+    /// handed out are unpredictable. Take the seed from an entropy source to make the most of it.
+    pub const fn with_shuffle_seed(mut self, seed: u64) -> Self {
+        self.shuffle_seed = Some(seed);
+        self
+    }
+
+    /// Aligns every chunk to `align`, e.g. for DMA descriptors or cache-line aligned objects.
+    /// The first chunk is moved up to the next aligned address of the arena and the chunk size
+    /// is rounded up to a multiple of `align`.
+    pub const fn with_chunk_align(mut self, align: usize) -> Self {
+        assert!(
+            align.is_power_of_two(),
+            "chunk alignment must be a power of two"
+        );
+
+        if align > self.chunk_align {
+            self.chunk_align = align;
+            self.chunk_size = self.chunk_size.next_multiple_of(align);
         }
+        self
     }
 
-    fn init(&mut self) {
-        self.initialized = true;
+    /// Bytes of metadata every allocation costs, chunks don't carry any.
+    pub const fn overhead_per_alloc() -> usize {
+        0
+    }
 
-        let chunk_count: usize = ARENA_SIZE / self.chunk_size;
+    /// Smallest number of bytes an allocation takes up in the arena, a whole chunk.
+    pub const fn min_block_size(&self) -> usize {
+        self.chunk_size
+    }
 
-        let mut prev_node: Option<&PoolFreeNode> = None;
+    /// Sets the secret the free list links are encoded with when `safe-linking` is enabled.
+    pub const fn with_secret(mut self, secret: usize) -> Self {
+        self.secret = secret;
+        self
+    }
 
-        for i in 0..chunk_count {
-            let offset = i * self.chunk_size;
+    /// Holds freed blocks back for `delay` frees (at most `QUARANTINE_CAPACITY`) before they
+    /// can be reused, poisoning them in the meantime.
+    #[cfg(feature = "quarantine")]
+    pub const fn with_quarantine(mut self, delay: usize) -> Self {
+        self.quarantine = Quarantine::new(delay);
+        self
+    }
 
-            // allocate the node in chunk `i`
-            let node = PoolFreeNode { next: None };
+    fn links(&self) -> LinkCodec {
+        LinkCodec::new(&self.arena, self.secret)
+    }
 
-            // save the current header onto the arena
-            let node_pointer = (self.arena.start() + offset) as *mut PoolFreeNode;
-            let node_reference = unsafe {
-                ptr::write(node_pointer, node);
+    // the free node of the chunk at `offset` in the arena
+    fn node(&self, offset: usize) -> PoolFreeNode {
+        unsafe { ptr::read((self.arena.start() + offset) as *const PoolFreeNode) }
+    }
 
-                // get a reference to the written node
-                &*node_pointer as &PoolFreeNode
-            };
+    // address of the first chunk, the start of the arena is only guaranteed to be byte aligned
+    fn first_chunk(&self) -> usize {
+        // no chunk fits if aligning the start overflows
+        align_forward(self.arena.start(), self.chunk_align).unwrap_or(usize::MAX)
+    }
 
-            // if there is a previous node, make the node point to the new node
-            if let Some(val) = prev_node {
-                let node = PoolFreeNode {
-                    next: Some(node_reference),
-                };
+    fn chunk_count(&self) -> usize {
+        self.arena.end().saturating_sub(self.first_chunk()) / self.chunk_size
+    }
 
-                // write the new value
-                unsafe {
-                    ptr::write(val as *const PoolFreeNode as *mut PoolFreeNode, node);
-                }
-            }
+    // whether `count` chunks can still be handed out, counting the untouched ones as well
+    fn has_free_chunks(&self, count: usize) -> bool {
+        let links = self.links();
 
-            // make the new node the previous node
-            prev_node = Some(node_reference);
+        let mut free = self.chunk_count() - self.carved;
+        let mut node = self.head;
+        while let Some(offset) = node.filter(|_| free < count) {
+            free += 1;
+            node = links.decode::<PoolFreeNode>(self.node(offset).next);
         }
 
-        // the head is the first allocated node
-        self.head = unsafe { Some(&*(self.arena.start() as *const PoolFreeNode)) };
+        free >= count
     }
-}
 
-unsafe impl GlobalAlloc for SpinLock<PoolAllocator<'_>> {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        let guard = self.lock();
+    // chunks handed out, the ones in the quarantine are still counted as used
+    fn chunks_in_use(&self) -> usize {
+        let links = self.links();
+
+        let mut in_use = self.carved;
+        let mut node = self.head;
+        while let Some(offset) = node {
+            in_use -= 1;
+            node = links.decode::<PoolFreeNode>(self.node(offset).next);
+        }
+
+        in_use
+    }
 
-        let mut allocator = guard.get_mut();
+    // takes the next chunk that was never handed out, chunks are only touched once they're
+    // carved so the arena isn't written to (or paged in) all at once
+    fn carve(&mut self) -> *mut u8 {
+        let chunk_count = self.chunk_count();
 
-        if layout.size() > allocator.chunk_size {
-            panic!("data doesn't fit in chunk");
+        if self.carved == chunk_count {
+            return ptr::null_mut();
         }
 
-        if !allocator.initialized {
-            allocator.init();
+        let chunk = match self.shuffle_seed {
+            Some(seed) => shuffled_index(self.carved, chunk_count, seed),
+            None => self.carved,
+        };
+        self.carved += 1;
+
+        (self.first_chunk() + chunk * self.chunk_size) as *mut u8
+    }
+
+    /// Releases every chunk handed out so far, e.g. to clear the objects of a frame at its end.
+    /// Chunks are carved from the arena afresh, in the same order as the first time, so it takes
+    /// constant time unless `zeroize-on-free`, `asan` or `valgrind` have to touch the arena.
+    ///
+    /// # Safety
+    ///
+    /// None of the released chunks may be used afterwards.
+    pub unsafe fn reset(&mut self) {
+        let first_chunk = self.first_chunk() as *mut u8;
+        unsafe { release_block(first_chunk, self.chunk_count() * self.chunk_size) };
+
+        self.head = None;
+        self.carved = 0;
+        #[cfg(feature = "quarantine")]
+        self.quarantine.clear();
+        #[cfg(debug_assertions)]
+        {
+            self.allocated = [0; DEBUG_TRACKED_CHUNKS / 64];
         }
+    }
 
-        if let Some(head) = allocator.head {
-            let ptr_addr = head as *const PoolFreeNode as usize;
+    // marks the chunk at `addr` as handed out or free, returns whether it was handed out before,
+    // which the chunks that aren't tracked always are
+    #[cfg(debug_assertions)]
+    fn set_allocated(&mut self, addr: usize, allocated: bool) -> bool {
+        let chunk = (addr - self.first_chunk()) / self.chunk_size;
 
-            allocator.head = head.next;
+        let Some(word) = self.allocated.get_mut(chunk / 64) else {
+            return true;
+        };
+        let bit = 1 << (chunk % 64);
+        let was_allocated = *word & bit != 0;
 
-            SpinLock::unlock(guard);
-            ptr_addr as *mut u8
+        if allocated {
+            *word |= bit;
         } else {
-            SpinLock::unlock(guard);
-            ptr::null_mut()
+            *word &= !bit;
         }
+        was_allocated
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: core::alloc::Layout) {
-        let guard = self.lock();
+    // whether a chunk holds a block of `layout`, the chunks after the first are only aligned as
+    // far as their size allows
+    fn fits(&self, layout: Layout) -> bool {
+        let align = self.chunk_align.min(1 << self.chunk_size.trailing_zeros());
+
+        layout.size() <= self.chunk_size && layout.align() <= align
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        // data doesn't fit in a chunk, or needs more alignment than every chunk has
+        if !self.fits(layout) {
+            return ptr::null_mut();
+        }
+
+        // freed chunks are reused first
+        let ptr = match self.head {
+            Some(head) => {
+                self.head = self.links().decode::<PoolFreeNode>(self.node(head).next);
+                (self.arena.start() + head) as *mut u8
+            }
+            None => self.carve(),
+        };
 
-        let mut allocator = guard.get_mut();
+        #[cfg(debug_assertions)]
+        if !ptr.is_null() {
+            self.set_allocated(ptr as usize, true);
+        }
 
-        // ignore deallocation if not initialized
-        if !allocator.initialized {
+        ptr
+    }
+
+    fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        // ignore deallocation if nothing was handed out yet
+        if self.carved == 0 {
             return;
         }
+
+        let addr = ptr as usize;
+        let first_chunk = self.first_chunk();
+
         // memory out of bounds
-        if !(allocator.arena.start() <= (ptr as usize) && (ptr as usize) < allocator.arena.end()) {
+        if !(first_chunk <= addr && addr < first_chunk + self.chunk_count() * self.chunk_size) {
             return;
         }
 
-        let node = PoolFreeNode {
-            next: allocator.head,
+        // a pointer into the middle of a chunk would corrupt the free list
+        if !(addr - first_chunk).is_multiple_of(self.chunk_size) {
+            self.rejected_frees += 1;
+            return;
+        }
+
+        // debug builds catch the frees that would put a chunk on the list twice
+        #[cfg(debug_assertions)]
+        if !self.set_allocated(addr, false) {
+            self.rejected_frees += 1;
+            return;
+        }
+
+        // the block that actually gets freed is the one leaving the quarantine
+        #[cfg(feature = "quarantine")]
+        let (ptr, layout) = match self.quarantine.push(ptr, layout) {
+            Some(released) => released,
+            None => return,
         };
-        let node_pointer = ptr as *mut PoolFreeNode;
 
-        // write the node to the arena and get a reference to it
-        let node_reference = unsafe {
-            ptr::write(node_pointer, node);
-            &*node_pointer as &PoolFreeNode
+        unsafe { release_block(ptr, layout.size()) };
+
+        let offset = ptr as usize - self.arena.start();
+        let node = PoolFreeNode {
+            next: self.links().encode(self.head),
         };
+        unsafe {
+            unpoison(ptr, size_of::<PoolFreeNode>());
+            ptr::write(ptr as *mut PoolFreeNode, node);
+        }
 
-        allocator.head = Some(node_reference);
+        self.head = Some(offset);
+    }
+}
 
-        SpinLock::unlock(guard);
+// maps `index` to its position in a permutation of `0..count` chosen by `seed`, using a small
+// feistel network over the next power of four and cycle walking back into range
+fn shuffled_index(index: usize, count: usize, seed: u64) -> usize {
+    let bits = (usize::BITS - count.leading_zeros())
+        .max(2)
+        .next_multiple_of(2);
+    let half = bits / 2;
+    let mask = (1 << half) - 1;
+
+    let mut value = index;
+    loop {
+        let (mut left, mut right) = (value >> half, value & mask);
+
+        for round in 0..4 {
+            let hash = splitmix64(seed ^ ((right as u64) << 8) ^ round) as usize;
+            (left, right) = (right, left ^ (hash & mask));
+        }
+
+        value = (left << half) | right;
+
+        if value < count {
+            return value;
+        }
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+impl<R: Relax> SpinLock<PoolAllocator, R> {
+    /// Number of frees ignored so far, of pointers into the middle of a chunk and, in debug
+    /// builds, of chunks that weren't allocated.
+    pub fn rejected_frees(&self) -> usize {
+        self.lock_with(|allocator| allocator.rejected_frees)
+    }
+
+    /// Allocates `N` chunks at once, taking the lock a single time. Either the whole batch is
+    /// handed out or, if there aren't enough free chunks or `layout` doesn't fit in one, none
+    /// of it.
+    pub fn alloc_batch<const N: usize>(&self, layout: Layout) -> Option<[*mut u8; N]> {
+        let batch = self.lock_with(|allocator| {
+            // make sure the whole batch is available before taking anything
+            if !allocator.fits(layout) || !allocator.has_free_chunks(N) {
+                return None;
+            }
+
+            Some([(); N].map(|_| allocator.allocate(layout)))
+        })?;
+
+        Some(batch.map(|ptr| unsafe { hand_out(ptr, layout) }))
+    }
+
+    /// Allocates as many chunks as are free, up to the length of `chunks`, taking the lock a
+    /// single time. Returns how many were allocated, they're at the start of `chunks`, none if
+    /// `layout` doesn't fit in a chunk.
+    pub fn alloc_into(&self, layout: Layout, chunks: &mut [*mut u8]) -> usize {
+        let count = self.lock_with(|allocator| {
+            let mut count = 0;
+            while count < chunks.len() {
+                let ptr = allocator.allocate(layout);
+                if ptr.is_null() {
+                    break;
+                }
+
+                chunks[count] = ptr;
+                count += 1;
+            }
+            count
+        });
+
+        for chunk in &mut chunks[..count] {
+            *chunk = unsafe { hand_out(*chunk, layout) };
+        }
+        count
+    }
+
+    /// Returns whether `ptr` points into the memory managed by this pool.
+    pub fn owns(&self, ptr: *mut u8) -> bool {
+        self.lock_with(|allocator| allocator.arena).contains(ptr)
+    }
+
+    // whether a chunk holds a block of `layout`
+    #[cfg(feature = "freelist")]
+    pub(crate) fn fits(&self, layout: Layout) -> bool {
+        self.lock_with(|allocator| allocator.fits(layout))
+    }
+
+    /// Frees every chunk in `ptrs`, taking the lock a single time.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`GlobalAlloc::dealloc`] for each of the pointers.
+    pub unsafe fn dealloc_batch(&self, ptrs: &[*mut u8], layout: Layout) {
+        self.lock_with(|allocator| {
+            for &ptr in ptrs {
+                allocator.deallocate(unsafe { take_back(ptr, layout) }, layout);
+            }
+        })
     }
 }
 
+impl fmt::Debug for PoolAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let in_use = self.chunks_in_use();
+
+        f.debug_struct("PoolAllocator")
+            .field("chunk_size", &self.chunk_size)
+            .field("in_use", &in_use)
+            .field("free", &(self.chunk_count() - in_use))
+            .field("rejected_frees", &self.rejected_frees)
+            .finish()
+    }
+}
+
+#[cfg(feature = "std")]
+impl SaveState for PoolAllocator {
+    fn save_state<W: Write>(&self, writer: W) -> io::Result<()> {
+        let state = [save_state::encode(self.head), self.carved];
+        save_arena(writer, self.arena, &state, self.arena.size())
+    }
+
+    unsafe fn restore_state<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        unsafe { self.reset() };
+
+        // safe-linking keys the links to the address of the arena
+        let relocatable = !cfg!(feature = "safe-linking");
+        let [head, carved] = unsafe { restore_arena(reader, self.arena, relocatable)? };
+        self.head = save_state::decode(head);
+        self.carved = carved;
+
+        // the chunks handed out are the carved ones that aren't on the list
+        #[cfg(debug_assertions)]
+        {
+            for index in 0..self.carved {
+                let chunk = match self.shuffle_seed {
+                    Some(seed) => shuffled_index(index, self.chunk_count(), seed),
+                    None => index,
+                };
+                self.set_allocated(self.first_chunk() + chunk * self.chunk_size, true);
+            }
+
+            let links = self.links();
+            let mut node = self.head;
+            while let Some(offset) = node {
+                self.set_allocated(self.arena.start() + offset, false);
+                node = links.decode::<PoolFreeNode>(self.node(offset).next);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Relax> Metered for SpinLock<PoolAllocator, R> {
+    fn usage(&self) -> Usage {
+        self.lock_with(|allocator| Usage {
+            used: allocator.chunks_in_use() * allocator.chunk_size,
+            capacity: allocator.arena.size(),
+        })
+    }
+}
+
+// tags, annotates and initializes a chunk on its way out of the pool, `ptr` may be null
+unsafe fn hand_out(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    #[cfg(feature = "mte")]
+    let ptr = unsafe { mte::tag(ptr, layout.size()) };
+
+    #[cfg(feature = "valgrind")]
+    let ptr = valgrind::malloc_like(ptr, layout.size());
+
+    unsafe { init_block(ptr, layout.size()) }
+}
+
+// undoes `hand_out` for a chunk coming back, returns the pointer the pool knows it by
+unsafe fn take_back(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    #[cfg(feature = "mte")]
+    let ptr = unsafe { mte::untag(ptr, layout.size()) };
+    #[cfg(not(feature = "mte"))]
+    let _ = layout;
+
+    #[cfg(feature = "valgrind")]
+    valgrind::free_like(ptr);
+
+    ptr
+}
+
+impl_global_alloc!(PoolAllocator, {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .lock_with_or_timeout(|allocator| allocator.allocate(layout))
+            .unwrap_or(ptr::null_mut());
+
+        unsafe { hand_out(ptr, layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let ptr = unsafe { take_back(ptr, layout) };
+
+        // a lock that can't be taken leaks the block, there's no way to fail a free
+        let _ = self.lock_with_or_timeout(|allocator| allocator.deallocate(ptr, layout));
+    }
+});
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Arena;
     use core::alloc::Layout;
 
-    static GLOBAL_ALLOC: SpinLock<PoolAllocator> = SpinLock::new(PoolAllocator::new(1024));
+    static ARENA: Arena = Arena::new();
+    static GLOBAL_ALLOC: SpinLock<PoolAllocator> =
+        SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 1024));
 
     #[test]
-    fn test_init() {
-        let mut pool = PoolAllocator::new(1024);
+    fn test_lazy_init() {
+        static ARENA: Arena = Arena::new();
+        let mut pool = PoolAllocator::new(unsafe { ARENA.region() }, 1024);
 
-        pool.init();
+        let region = unsafe { ARENA.region() };
+        unsafe { ptr::write_bytes(region.start() as *mut u8, 0xAA, region.size()) };
 
-        assert_eq!(pool.initialized, true);
+        let layout = Layout::new::<[u8; 1024]>();
+        let first = pool.allocate(layout);
 
-        let mut chunk_count = 0;
-        while let Some(head) = pool.head {
-            pool.head = head.next;
+        // only the chunk handed out was touched
+        assert_eq!(first as usize, region.start());
+        let rest = unsafe { core::slice::from_raw_parts(first.add(1024), region.size() - 1024) };
+        assert!(rest.iter().all(|&byte| byte == 0xAA));
+
+        let mut chunk_count = 1;
+        while !pool.allocate(layout).is_null() {
             chunk_count += 1;
         }
 
         // size of the allocator is ARENA_SIZE = 128 * 1024
         // so we expect 128 chunks
         assert_eq!(chunk_count, 128);
+        assert!(!pool.has_free_chunks(1));
+    }
+
+    #[test]
+    fn test_shuffled_init() {
+        static ARENA: Arena = Arena::new();
+        let mut pool =
+            PoolAllocator::new(unsafe { ARENA.region() }, 1024).with_shuffle_seed(0xDEADBEEF);
+
+        let layout = Layout::new::<[u8; 1024]>();
+
+        let mut seen = [false; 128];
+        let mut in_order = true;
+        let mut chunk_count = 0;
+
+        loop {
+            let ptr = pool.allocate(layout);
+            if ptr.is_null() {
+                break;
+            }
+            let chunk = (ptr as usize - unsafe { ARENA.region() }.start()) / 1024;
+
+            assert!(!seen[chunk]);
+            seen[chunk] = true;
+            in_order &= chunk == chunk_count;
+
+            chunk_count += 1;
+        }
+
+        // every chunk is handed out exactly once, just not in address order
+        assert_eq!(chunk_count, 128);
+        assert!(!in_order);
+    }
+
+    #[test]
+    fn test_reset() {
+        static ARENA: Arena<4096> = Arena::new();
+        let mut pool =
+            PoolAllocator::new(unsafe { ARENA.region() }, 64).with_shuffle_seed(0xDEADBEEF);
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let first: Vec<_> = (0..8).map(|_| pool.allocate(layout)).collect();
+        pool.deallocate(first[2], layout);
+        while !pool.allocate(layout).is_null() {}
+
+        unsafe { pool.reset() };
+
+        // every chunk is free again, handed out in the same order as the first time
+        assert!(pool.has_free_chunks(4096 / 64));
+        let again: Vec<_> = (0..8).map(|_| pool.allocate(layout)).collect();
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn test_free_inside_chunk() {
+        static ARENA: Arena<4096> = Arena::new();
+        let mut pool = PoolAllocator::new(unsafe { ARENA.region() }, 64);
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptr = pool.allocate(layout);
+        pool.deallocate(unsafe { ptr.add(8) }, layout);
+
+        // the free is ignored
+        assert!(pool.head.is_none());
+        assert_eq!(pool.rejected_frees, 1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_double_free() {
+        static ARENA: Arena<4096> = Arena::new();
+        let mut pool = PoolAllocator::new(unsafe { ARENA.region() }, 64);
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptr = pool.allocate(layout);
+        pool.deallocate(ptr, layout);
+        pool.deallocate(ptr, layout);
+
+        // the chunk is on the free list once
+        assert_eq!(pool.rejected_frees, 1);
+        assert_eq!(pool.allocate(layout), ptr);
+        assert_ne!(pool.allocate(layout), ptr);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_free_never_allocated() {
+        static ARENA: Arena<4096> = Arena::new();
+        let mut pool = PoolAllocator::new(unsafe { ARENA.region() }, 64);
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptr = pool.allocate(layout);
+        pool.deallocate(unsafe { ptr.add(64) }, layout);
+
+        assert_eq!(pool.rejected_frees, 1);
+        assert!(pool.head.is_none());
     }
 
     #[test]
@@ -171,12 +688,261 @@ mod tests {
 
         let guard = GLOBAL_ALLOC.lock();
 
-        let head = guard.get().head.unwrap();
-        assert_eq!(ptr_2 as usize, head as *const PoolFreeNode as usize);
+        let allocator = guard.get();
+        let head = allocator.head.unwrap();
+        assert_eq!(ptr_2 as usize, allocator.arena.start() + head);
 
-        let head_next = head.next.unwrap();
-        assert_eq!(ptr_1 as usize, head_next as *const PoolFreeNode as usize);
+        let head_next = allocator
+            .links()
+            .decode::<PoolFreeNode>(allocator.node(head).next)
+            .unwrap();
+        assert_eq!(ptr_1 as usize, allocator.arena.start() + head_next);
 
         SpinLock::unlock(guard);
     }
+
+    #[test]
+    #[cfg(feature = "zeroize-on-free")]
+    fn test_zeroize_on_free() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64));
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptr = unsafe { ALLOC.alloc(layout) };
+        unsafe { ptr::write_bytes(ptr, 0xAA, layout.size()) };
+        unsafe { ALLOC.dealloc(ptr, layout) };
+
+        // everything past the free node written on top of the chunk was scrubbed
+        let node_size = core::mem::size_of::<PoolFreeNode>();
+        unsafe { crate::utils::unpoison(ptr, layout.size()) };
+        let block = unsafe { core::slice::from_raw_parts(ptr.add(node_size), 64 - node_size) };
+        assert!(block.iter().all(|&byte| byte == 0x00));
+    }
+
+    #[test]
+    #[cfg(feature = "safe-linking")]
+    fn test_safe_linking_detects_corruption() {
+        // the corruption aborts, so it's run in a child process of the test binary
+        if std::env::var_os("RSALLOC_CORRUPT_POOL").is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "pool::tests::test_safe_linking_detects_corruption",
+                    "--nocapture",
+                ])
+                .env("RSALLOC_CORRUPT_POOL", "1")
+                .output()
+                .unwrap();
+
+            assert!(!output.status.success());
+            assert!(String::from_utf8_lossy(&output.stderr).contains("corrupted free list link"));
+            return;
+        }
+
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64).with_secret(0xC0FFEE));
+
+        let layout = Layout::new::<u64>();
+
+        let ptr = unsafe { ALLOC.alloc(layout) };
+        unsafe { ALLOC.dealloc(ptr, layout) };
+
+        // a use after free overwrites the link with an attacker controlled address
+        unsafe { ptr::write(ptr as *mut usize, 0x1000) };
+
+        unsafe { ALLOC.alloc(layout) };
+        unreachable!("the corrupted link wasn't caught");
+    }
+
+    #[test]
+    #[cfg(feature = "quarantine")]
+    fn test_quarantine() {
+        use crate::quarantine::POISON_BYTE;
+
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64).with_quarantine(2));
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+
+        // the freed chunk is poisoned and not handed out again, `asan` would report reading it
+        unsafe { crate::utils::unpoison(ptr_1, layout.size()) };
+        let block = unsafe { core::slice::from_raw_parts(ptr_1, layout.size()) };
+        assert!(block.iter().all(|&byte| byte == POISON_BYTE));
+        assert_ne!(unsafe { ALLOC.alloc(layout) }, ptr_1);
+
+        // two more frees release it
+        unsafe { ALLOC.dealloc(ptr_2, layout) };
+        let ptr_3 = unsafe { ALLOC.alloc(layout) };
+        unsafe { ALLOC.dealloc(ptr_3, layout) };
+        assert_eq!(unsafe { ALLOC.alloc(layout) }, ptr_1);
+    }
+
+    #[test]
+    fn test_lock_released_under_contention() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64));
+
+        let layout = Layout::new::<[u64; 8]>();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let mut outside = [0u64; 8];
+                    for _ in 0..1000 {
+                        // more chunks are held than there are in the pool
+                        let ptrs = [(); 8].map(|_| unsafe { ALLOC.alloc(layout) });
+                        unsafe { ALLOC.dealloc(outside.as_mut_ptr() as *mut u8, layout) };
+                        for ptr in ptrs.into_iter().filter(|ptr| !ptr.is_null()) {
+                            unsafe { ALLOC.dealloc(ptr, layout) };
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // an oversized request fails without taking the lock down
+        let oversized = Layout::new::<[u64; 16]>();
+        assert!(unsafe { ALLOC.alloc(oversized) }.is_null());
+
+        // a panic while holding the lock releases it as well
+        let panicking = std::thread::spawn(move || {
+            ALLOC.lock_with(|_| panic!("panicking with the lock held"));
+        });
+        assert!(panicking.join().is_err());
+
+        assert!(ALLOC.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_batch() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64));
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let single = unsafe { ALLOC.alloc(layout) };
+
+        // only 63 chunks are left, the batch fails without taking any of them
+        assert!(ALLOC.alloc_batch::<64>(layout).is_none());
+
+        let batch = ALLOC.alloc_batch::<63>(layout).unwrap();
+        assert!(batch.iter().all(|ptr| !ptr.is_null() && *ptr != single));
+        assert!(unsafe { ALLOC.alloc(layout) }.is_null());
+
+        unsafe { ALLOC.dealloc_batch(&batch, layout) };
+        unsafe { ALLOC.dealloc(single, layout) };
+
+        assert!(ALLOC.alloc_batch::<64>(layout).is_some());
+    }
+
+    #[test]
+    fn test_batch_oversized() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 64));
+
+        // every chunk is free, but none holds the block
+        let layout = Layout::new::<[u8; 65]>();
+        assert!(ALLOC.alloc_batch::<4>(layout).is_none());
+
+        let mut chunks = [ptr::null_mut(); 4];
+        assert_eq!(ALLOC.alloc_into(layout, &mut chunks), 0);
+        assert!(chunks.iter().all(|ptr| ptr.is_null()));
+    }
+
+    #[test]
+    fn test_chunk_align() {
+        static ARENA: Arena<4096> = Arena::new();
+
+        // a region starting at an odd address
+        let region = unsafe { ARENA.region() };
+        let region = unsafe { Region::from_raw_parts((region.start() + 1) as *mut u8, 4095) };
+        let mut pool = PoolAllocator::new(region, 48).with_chunk_align(64);
+
+        let layout = Layout::new::<[u8; 48]>();
+
+        let mut chunk_count = 0;
+        loop {
+            let ptr = pool.allocate(layout);
+            if ptr.is_null() {
+                break;
+            }
+            assert!((ptr as usize).is_multiple_of(64));
+            assert!(ptr as usize + 64 <= region.end());
+            chunk_count += 1;
+        }
+
+        // the first 63 bytes are skipped to reach an aligned address
+        assert_eq!(chunk_count, 63);
+    }
+
+    #[test]
+    // `mte` aligns every chunk to a granule
+    #[cfg(not(feature = "mte"))]
+    fn test_over_aligned_layout() {
+        static ARENA: Arena<4096> = Arena::new();
+
+        // every other chunk of 24 bytes is only 8 byte aligned
+        let mut pool = PoolAllocator::new(unsafe { ARENA.region() }, 24);
+        assert!(pool
+            .allocate(Layout::from_size_align(16, 16).unwrap())
+            .is_null());
+
+        for _ in 0..4 {
+            let ptr = pool.allocate(Layout::from_size_align(16, 8).unwrap());
+            assert!((ptr as usize).is_multiple_of(8));
+        }
+
+        // aligning the chunks to 16 rounds their size up to 32
+        let mut pool = PoolAllocator::new(unsafe { ARENA.region() }, 24).with_chunk_align(16);
+        for _ in 0..4 {
+            let ptr = pool.allocate(Layout::from_size_align(16, 16).unwrap());
+            assert!(!ptr.is_null() && (ptr as usize).is_multiple_of(16));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_save_state() {
+        static ARENA: Arena<4096> = Arena::new();
+        let mut pool =
+            PoolAllocator::new(unsafe { ARENA.region() }, 64).with_shuffle_seed(0xDEADBEEF);
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptrs: Vec<_> = (0..4).map(|_| pool.allocate(layout)).collect();
+        pool.deallocate(ptrs[1], layout);
+
+        let mut state = Vec::new();
+        pool.save_state(&mut state).unwrap();
+
+        unsafe { pool.reset() };
+        while !pool.allocate(layout).is_null() {}
+
+        unsafe { pool.restore_state(&state[..]) }.unwrap();
+
+        // the freed chunk comes first, then the ones that were never handed out
+        assert_eq!(pool.allocate(layout), ptrs[1]);
+        assert!(!ptrs.contains(&pool.allocate(layout)));
+
+        // the debug builds know which chunks are handed out
+        for &ptr in &ptrs {
+            pool.deallocate(ptr, layout);
+        }
+        assert_eq!(pool.chunks_in_use(), 1);
+    }
 }
@@ -0,0 +1,50 @@
+//! The types most programs need, in one import:
+//!
+#![cfg_attr(feature = "freelist", doc = "```")]
+#![cfg_attr(not(feature = "freelist"), doc = "```ignore")]
+//! use rsalloc::prelude::*;
+//!
+//! static ARENA: Arena<4096> = Arena::new();
+//! static HEAP: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+//!     unsafe { ARENA.region() },
+//!     PlacementPolicy::FindFirst,
+//! ));
+//!
+//! let ptr = unsafe { HEAP.alloc(Layout::new::<u64>()) };
+//! assert!(!ptr.is_null());
+//! ```
+//!
+//! It brings in the allocators enabled by the features, the arenas and locks they sit behind,
+//! the error and stats types, and the extension traits allocating typed values.
+
+pub use crate::{AllocAligned, AllocError, AllocStats, AllocUninit, AllocWith, AllocZeroedSlice};
+pub use crate::{AllocatorHandle, RwSpinLock, SingleThreaded, SpinLock, SpinTimeout};
+pub use crate::{Arena, Region};
+pub use core::alloc::{GlobalAlloc, Layout};
+
+#[cfg(feature = "metrics")]
+pub use crate::metrics::Usage;
+#[cfg(feature = "cortex-m")]
+pub use crate::InterruptFree;
+#[cfg(feature = "lock-stats")]
+pub use crate::LockStats;
+#[cfg(feature = "pool")]
+pub use crate::PoolAllocator;
+#[cfg(any(feature = "linear", feature = "stack"))]
+pub use crate::Resettable;
+#[cfg(all(
+    feature = "std",
+    any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )
+))]
+pub use crate::SaveState;
+#[cfg(feature = "linear")]
+pub use crate::{ArenaAllocator, BumpDownAllocator};
+#[cfg(feature = "freelist")]
+pub use crate::{FreeListAllocator, FreeListBuilder, PlacementPolicy, SearchBounds};
+#[cfg(feature = "stack")]
+pub use crate::{StackAllocator, StackFreeError};
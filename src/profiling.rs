@@ -0,0 +1,452 @@
+//! Heap profiling of allocator instances.
+//!
+//! [`HeapProfiler`] keeps dhat-style totals for the allocator it wraps: blocks and bytes
+//! allocated over the whole run, what's live right now, the peak, and the most requested sizes.
+//!
+//! [`ChromeTrace`] wraps an allocator and writes the lifetime of every block as a Chrome trace
+//! (the JSON array format), which `chrome://tracing`, Perfetto and Speedscope open. Blocks show up
+//! as async slices grouped under the allocator's name, next to a counter of the bytes it has
+//! handed out, so the memory behaviour of a frame can be looked at next to the frame itself.
+//!
+//! ```ignore
+//! static ARENA: Arena = Arena::new();
+//!
+//! let frame = ChromeTrace::new(
+//!     "frame",
+//!     SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() })),
+//!     File::create("frame.json")?,
+//! );
+//! ```
+
+use super::SpinLock;
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use std::io::{Cursor, Write};
+use std::time::Instant;
+
+/// Number of distinct block sizes [`HeapStats`] keeps count of.
+pub const HOT_SIZES: usize = 16;
+
+// longest event written, with a name of up to 64 bytes
+const MAX_EVENT_SIZE: usize = 512;
+
+/// Wraps an allocator, counting its blocks and bytes.
+///
+/// ```ignore
+/// static PROFILER: HeapProfiler<SpinLock<PoolAllocator>> = HeapProfiler::new(...);
+///
+/// println!("{}", PROFILER.stats());
+/// ```
+pub struct HeapProfiler<A> {
+    inner: A,
+    stats: SpinLock<HeapStats>,
+}
+
+/// Totals kept by a [`HeapProfiler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HeapStats {
+    /// Blocks allocated since the profiler was created.
+    pub total_blocks: usize,
+    /// Bytes allocated since the profiler was created.
+    pub total_bytes: usize,
+    pub live_blocks: usize,
+    pub live_bytes: usize,
+    /// Most bytes live at once.
+    pub peak_bytes: usize,
+    /// Blocks live when `peak_bytes` was reached.
+    pub peak_blocks: usize,
+    // (size, count), the heavy hitters among the requested sizes
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "hottest_sizes", serialize_with = "serialize_sizes")
+    )]
+    sizes: [(usize, usize); HOT_SIZES],
+}
+
+impl<A> HeapProfiler<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            stats: SpinLock::new(HeapStats::new()),
+        }
+    }
+
+    /// The totals up to now.
+    pub fn stats(&self) -> HeapStats {
+        self.stats.lock_with(|stats| *stats)
+    }
+
+    fn allocated(&self, size: usize) {
+        self.stats.lock_with(|stats| stats.allocated(size));
+    }
+
+    fn freed(&self, size: usize) {
+        self.stats.lock_with(|stats| stats.freed(size));
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for HeapProfiler<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+
+        if !ptr.is_null() {
+            self.allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+
+        if !ptr.is_null() {
+            self.allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+
+        self.freed(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+
+        // counted like dhat does, as a free of the old block and an allocation of the new one
+        if !new_ptr.is_null() {
+            self.freed(layout.size());
+            self.allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+impl HeapStats {
+    const fn new() -> Self {
+        Self {
+            total_blocks: 0,
+            total_bytes: 0,
+            live_blocks: 0,
+            live_bytes: 0,
+            peak_bytes: 0,
+            peak_blocks: 0,
+            sizes: [(0, 0); HOT_SIZES],
+        }
+    }
+
+    /// The most requested block sizes as `(size, count)`, most requested first.
+    ///
+    /// Only [`HOT_SIZES`] sizes are tracked. Once that many were seen, a new size takes the place
+    /// of the least requested one and inherits its count, so the counts are upper bounds, but a
+    /// size requested more often than `total_blocks / HOT_SIZES` times is always listed.
+    pub fn hottest_sizes(&self) -> impl Iterator<Item = (usize, usize)> {
+        let mut sizes = self.sizes;
+        sizes.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        sizes.into_iter().filter(|&(_, count)| count > 0)
+    }
+
+    fn allocated(&mut self, size: usize) {
+        self.total_blocks += 1;
+        self.total_bytes += size;
+        self.live_blocks += 1;
+        self.live_bytes += size;
+
+        if self.live_bytes > self.peak_bytes {
+            self.peak_bytes = self.live_bytes;
+            self.peak_blocks = self.live_blocks;
+        }
+
+        // space-saving: count the size if it's tracked, otherwise replace the least counted one
+        let slot = match self
+            .sizes
+            .iter()
+            .position(|&(s, count)| s == size && count > 0)
+        {
+            Some(slot) => slot,
+            None => {
+                let slot = (0..HOT_SIZES).min_by_key(|&i| self.sizes[i].1).unwrap_or(0);
+                self.sizes[slot].0 = size;
+                slot
+            }
+        };
+        self.sizes[slot].1 += 1;
+    }
+
+    fn freed(&mut self, size: usize) {
+        self.live_blocks -= 1;
+        self.live_bytes -= size;
+    }
+}
+
+// most requested first, as with `hottest_sizes`
+#[cfg(feature = "serde")]
+fn serialize_sizes<S: serde::Serializer>(
+    sizes: &[(usize, usize); HOT_SIZES],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let stats = HeapStats {
+        sizes: *sizes,
+        ..HeapStats::new()
+    };
+    serializer.collect_seq(stats.hottest_sizes())
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Total:     {} bytes in {} blocks",
+            self.total_bytes, self.total_blocks
+        )?;
+        writeln!(
+            f,
+            "At t-gmax: {} bytes in {} blocks",
+            self.peak_bytes, self.peak_blocks
+        )?;
+        writeln!(
+            f,
+            "At t-end:  {} bytes in {} blocks",
+            self.live_bytes, self.live_blocks
+        )?;
+
+        write!(f, "Hottest sizes:")?;
+        for (size, count) in self.hottest_sizes() {
+            write!(f, "\n  {size} bytes: {count} blocks")?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps an allocator, writing the lifetime of every block to `writer` as a Chrome trace.
+///
+/// Failing to write doesn't affect the allocations, the event is dropped. When profiling the
+/// global allocator the writer must not allocate itself (a `File` doesn't, a `BufWriter` does).
+pub struct ChromeTrace<A, W> {
+    name: &'static str,
+    inner: A,
+    state: SpinLock<TraceState<W>>,
+}
+
+struct TraceState<W> {
+    writer: W,
+    // time of the first event, timestamps are relative to it
+    start: Option<Instant>,
+    live_bytes: usize,
+}
+
+enum Phase {
+    Begin,
+    End,
+}
+
+impl<A, W: Write> ChromeTrace<A, W> {
+    /// The blocks of `inner` are grouped under `name`, which is written as is, so it mustn't
+    /// contain quotes or backslashes.
+    pub const fn new(name: &'static str, inner: A, writer: W) -> Self {
+        Self {
+            name,
+            inner,
+            state: SpinLock::new(TraceState {
+                writer,
+                start: None,
+                live_bytes: 0,
+            }),
+        }
+    }
+
+    /// Closes the JSON array. Viewers also open traces that weren't finished, e.g. because the
+    /// program crashed, so this is optional.
+    pub fn finish(&self) {
+        self.state.lock_with(|state| {
+            if state.start.is_some() {
+                let _ = state.writer.write_all(b"\n]\n");
+                let _ = state.writer.flush();
+            }
+        });
+    }
+
+    fn record(&self, phase: Phase, ptr: *mut u8, layout: Layout) {
+        self.state.lock_with(|state| {
+            let now = Instant::now();
+            let separator = if state.start.is_none() { "[\n" } else { ",\n" };
+            let ts = now.duration_since(*state.start.get_or_insert(now)).as_micros();
+
+            let ph = match phase {
+                Phase::Begin => {
+                    state.live_bytes += layout.size();
+                    "b"
+                }
+                Phase::End => {
+                    state.live_bytes -= layout.size();
+                    "e"
+                }
+            };
+
+            // the block as an async slice keyed by its address, then the allocator's counter
+            let mut buf = Cursor::new([0u8; MAX_EVENT_SIZE]);
+            let written = write!(
+                buf,
+                "{separator}{{\"name\":\"{size} bytes\",\"cat\":\"{name}\",\"ph\":\"{ph}\",\"id\":\"{ptr:p}\",\
+                 \"ts\":{ts},\"pid\":1,\"tid\":1,\"args\":{{\"size\":{size},\"align\":{align}}}}},\n\
+                 {{\"name\":\"{name}\",\"ph\":\"C\",\"ts\":{ts},\"pid\":1,\"tid\":1,\
+                 \"args\":{{\"bytes\":{live}}}}}",
+                name = self.name,
+                size = layout.size(),
+                align = layout.align(),
+                live = state.live_bytes,
+            );
+
+            // a single write, so a partial event never ends up in the trace
+            if written.is_ok() {
+                let len = buf.position() as usize;
+                let _ = state.writer.write_all(&buf.get_ref()[..len]);
+                let _ = state.writer.flush();
+            }
+        });
+    }
+}
+
+unsafe impl<A: GlobalAlloc, W: Write> GlobalAlloc for ChromeTrace<A, W> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+
+        if !ptr.is_null() {
+            self.record(Phase::Begin, ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+
+        if !ptr.is_null() {
+            self.record(Phase::Begin, ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // recorded first, once the block is freed its address may be handed out again
+        self.record(Phase::End, ptr, layout);
+
+        unsafe { self.inner.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+
+        // the old block ends and a new one begins, even when it was resized in place
+        if !new_ptr.is_null() {
+            self.record(Phase::End, ptr, layout);
+            // SAFETY: the caller guarantees the new size makes a valid layout
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            self.record(Phase::Begin, new_ptr, new_layout);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(all(test, feature = "linear"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, ArenaAllocator};
+
+    #[test]
+    fn test_heap_profiler() {
+        static ARENA: Arena<4096> = Arena::new();
+
+        let profiler = HeapProfiler::new(SpinLock::new(ArenaAllocator::new(unsafe {
+            ARENA.region()
+        })));
+
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let large = Layout::from_size_align(256, 8).unwrap();
+        unsafe {
+            let blocks = [(); 3].map(|_| profiler.alloc(small));
+            let large_block = profiler.alloc(large);
+            profiler.dealloc(large_block, large);
+
+            let grown = profiler.realloc(blocks[0], small, 32);
+            profiler.dealloc(grown, Layout::from_size_align(32, 8).unwrap());
+        }
+
+        let stats = profiler.stats();
+        assert_eq!(stats.total_blocks, 5);
+        assert_eq!(stats.total_bytes, 3 * 16 + 256 + 32);
+        assert_eq!((stats.peak_bytes, stats.peak_blocks), (3 * 16 + 256, 4));
+        assert_eq!((stats.live_bytes, stats.live_blocks), (2 * 16, 2));
+
+        let hottest: Vec<_> = stats.hottest_sizes().collect();
+        assert_eq!(hottest, [(16, 3), (32, 1), (256, 1)]);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serialize_heap_stats() {
+        let mut stats = HeapStats::new();
+        stats.allocated(8);
+        stats.allocated(8);
+        stats.allocated(32);
+        stats.freed(8);
+
+        let json = serde_json::to_value(stats).unwrap();
+        assert_eq!(json["total_bytes"], 48);
+        assert_eq!(json["live_blocks"], 2);
+        assert_eq!(json["peak_bytes"], 48);
+        assert_eq!(json["hottest_sizes"], serde_json::json!([[8, 2], [32, 1]]));
+    }
+
+    #[test]
+    fn test_hottest_sizes_overflow() {
+        let mut stats = HeapStats::new();
+
+        // a frequent size interleaved with more distinct sizes than are tracked
+        for size in 1..=4 * HOT_SIZES {
+            stats.allocated(1000);
+            stats.allocated(size);
+        }
+
+        let (size, count) = stats.hottest_sizes().next().unwrap();
+        assert_eq!(size, 1000);
+        assert!(count >= 4 * HOT_SIZES);
+    }
+
+    #[test]
+    fn test_chrome_trace() {
+        static ARENA: Arena<4096> = Arena::new();
+
+        let profiler = ChromeTrace::new(
+            "frame",
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() })),
+            Vec::new(),
+        );
+
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        unsafe {
+            let a = profiler.alloc(layout);
+            let b = profiler.alloc(layout);
+            profiler.dealloc(a, layout);
+            profiler.dealloc(b, layout);
+        }
+        profiler.finish();
+
+        let trace = profiler.state.lock_with(|state| state.writer.clone());
+        let trace = String::from_utf8(trace).unwrap();
+
+        assert!(trace.starts_with("[\n{"));
+        assert!(trace.ends_with("}\n]\n"));
+        assert_eq!(trace.matches("\"ph\":\"b\"").count(), 2);
+        assert_eq!(trace.matches("\"ph\":\"e\"").count(), 2);
+        assert_eq!(trace.matches("\"cat\":\"frame\"").count(), 4);
+
+        // the counter follows the bytes handed out
+        let counters: Vec<_> = trace
+            .lines()
+            .filter_map(|line| line.split("\"bytes\":").nth(1))
+            .collect();
+        assert_eq!(counters, ["24}},", "48}},", "24}},", "0}}"]);
+    }
+}
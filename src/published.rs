@@ -0,0 +1,179 @@
+use super::SeqLock;
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Wrapper publishing allocation counters through a [`SeqLock`], so they can be sampled at any
+/// rate without taking the allocator's lock or slowing down allocations.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static GLOBAL: Published<SpinLock<FreeListAllocator>> = Published::new(...);
+///
+/// // on a monitoring thread
+/// loop {
+///     report(GLOBAL.stats());
+///     thread::sleep(Duration::from_millis(1));
+/// }
+/// ```
+pub struct Published<A> {
+    inner: A,
+    stats: SeqLock<AllocStats>,
+}
+
+/// Counters published by a [`Published`] allocator, or counted by the lock-free ones with the
+/// `stats` feature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AllocStats {
+    /// Successful allocations, reallocations included.
+    pub allocs: usize,
+    /// Frees, reallocations included.
+    pub deallocs: usize,
+    /// Allocations that returned null.
+    pub failed: usize,
+    pub live_blocks: usize,
+    pub live_bytes: usize,
+    /// Most bytes live at once.
+    pub peak_bytes: usize,
+}
+
+impl<A> Published<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            stats: SeqLock::new(AllocStats {
+                allocs: 0,
+                deallocs: 0,
+                failed: 0,
+                live_blocks: 0,
+                live_bytes: 0,
+                peak_bytes: 0,
+            }),
+        }
+    }
+
+    /// A consistent sample of the counters.
+    pub fn stats(&self) -> AllocStats {
+        self.stats.read()
+    }
+
+    fn allocated(&self, ptr: *mut u8, size: usize) {
+        self.stats.write(|stats| {
+            if ptr.is_null() {
+                stats.failed += 1;
+                return;
+            }
+
+            stats.allocs += 1;
+            stats.live_blocks += 1;
+            stats.live_bytes += size;
+            stats.peak_bytes = stats.peak_bytes.max(stats.live_bytes);
+        });
+    }
+
+    fn freed(&self, size: usize) {
+        self.stats.write(|stats| {
+            stats.deallocs += 1;
+            stats.live_blocks -= 1;
+            stats.live_bytes -= size;
+        });
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Published<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+
+        self.allocated(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+
+        self.allocated(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+
+        self.freed(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+
+        // a failed realloc leaves the block as it was
+        if !new_ptr.is_null() {
+            self.freed(layout.size());
+        }
+        self.allocated(new_ptr, new_size);
+        new_ptr
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_published_stats() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: Published<SpinLock<FreeListAllocator>> = Published::new(SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst),
+        ));
+
+        let layout = Layout::new::<[u64; 8]>();
+        unsafe {
+            let a = ALLOC.alloc(layout);
+            let b = ALLOC.alloc(layout);
+            ALLOC.dealloc(a, layout);
+            let b = ALLOC.realloc(b, layout, 128);
+            assert!(ALLOC.alloc(Layout::new::<[u8; 2048]>()).is_null());
+            ALLOC.dealloc(b, Layout::from_size_align(128, 8).unwrap());
+        }
+
+        assert_eq!(
+            ALLOC.stats(),
+            AllocStats {
+                allocs: 3,
+                deallocs: 3,
+                failed: 1,
+                live_blocks: 0,
+                live_bytes: 0,
+                peak_bytes: 128,
+            }
+        );
+    }
+
+    #[test]
+    fn test_consistent_samples() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: Published<SpinLock<FreeListAllocator>> = Published::new(SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst),
+        ));
+        static DONE: AtomicBool = AtomicBool::new(false);
+
+        let sampler = std::thread::spawn(|| {
+            while !DONE.load(Ordering::Relaxed) {
+                // every block has the same size, so a torn sample would break the ratio
+                let stats = ALLOC.stats();
+                assert_eq!(stats.live_bytes, stats.live_blocks * 32);
+                assert_eq!(stats.live_blocks, stats.allocs - stats.deallocs);
+            }
+        });
+
+        let layout = Layout::new::<[u64; 4]>();
+        for _ in 0..10_000 {
+            unsafe {
+                let ptr = ALLOC.alloc(layout);
+                ALLOC.dealloc(ptr, layout);
+            }
+        }
+
+        DONE.store(true, Ordering::Relaxed);
+        sampler.join().unwrap();
+    }
+}
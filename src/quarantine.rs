@@ -0,0 +1,80 @@
+use super::utils::{poison, unpoison};
+use core::alloc::Layout;
+
+/// Maximum number of frees a block can be held back for.
+pub const QUARANTINE_CAPACITY: usize = 16;
+
+/// Byte pattern written over blocks while they're in quarantine.
+pub const POISON_BYTE: u8 = 0xDE;
+
+/// Delays the reuse of freed blocks by a number of frees.
+///
+/// Freed blocks are poisoned and kept in a ring buffer, so a use after free reads the poison
+/// pattern instead of silently corrupting a newer allocation.
+pub struct Quarantine {
+    blocks: [Option<(usize, Layout)>; QUARANTINE_CAPACITY],
+    delay: usize,
+    next: usize,
+}
+
+impl Quarantine {
+    pub const fn new(delay: usize) -> Self {
+        Self {
+            blocks: [None; QUARANTINE_CAPACITY],
+            delay: if delay < QUARANTINE_CAPACITY {
+                delay
+            } else {
+                QUARANTINE_CAPACITY
+            },
+            next: 0,
+        }
+    }
+
+    /// Forgets every block in quarantine, when they're released all at once.
+    pub fn clear(&mut self) {
+        self.blocks = [None; QUARANTINE_CAPACITY];
+        self.next = 0;
+    }
+
+    /// Puts a freed block in quarantine, returns the block that is released to be actually freed.
+    pub fn push(&mut self, ptr: *mut u8, layout: Layout) -> Option<(*mut u8, Layout)> {
+        if self.delay == 0 {
+            return Some((ptr, layout));
+        }
+
+        unsafe {
+            // memcheck already considers the block freed
+            unpoison(ptr, layout.size());
+            core::ptr::write_bytes(ptr, POISON_BYTE, layout.size());
+            poison(ptr, layout.size());
+        }
+
+        let released = self.blocks[self.next].replace((ptr as usize, layout));
+        self.next = (self.next + 1) % self.delay;
+
+        released.map(|(addr, layout)| (addr as *mut u8, layout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_releases_in_order() {
+        let mut blocks = [[0u8; 8]; 3];
+        let layout = Layout::new::<[u8; 8]>();
+        let mut quarantine = Quarantine::new(2);
+
+        assert!(quarantine.push(blocks[0].as_mut_ptr(), layout).is_none());
+        assert!(quarantine.push(blocks[1].as_mut_ptr(), layout).is_none());
+
+        // the third free releases the first block
+        let (released, _) = quarantine.push(blocks[2].as_mut_ptr(), layout).unwrap();
+        assert_eq!(released, blocks[0].as_mut_ptr());
+
+        // the blocks live on the stack, `asan` has to let the test read and return over them
+        unsafe { crate::utils::unpoison(blocks.as_ptr().cast(), size_of_val(&blocks)) };
+        assert!(blocks[1].iter().all(|&byte| byte == POISON_BYTE));
+    }
+}
@@ -0,0 +1,126 @@
+use super::Region;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+// states of the sorted index
+const UNSORTED: u8 = 0;
+const SORTING: u8 = 1;
+const SORTED: u8 = 2;
+
+/// The regions of the allocators behind a combinator, sorted by address so the one holding a
+/// pointer is found with a binary search instead of asking every allocator in turn.
+///
+/// The addresses of statics aren't known at compile time, so the regions are sorted on the first
+/// lookup, lock-free: lookups racing with it scan the regions instead. The regions can't grow or
+/// move once the table is built.
+///
+/// ```ignore
+/// static HEAP: MultiRegionAllocator<2> = MultiRegionAllocator::new([...])
+///     .with_range_table();
+/// ```
+#[derive(Debug)]
+pub struct RangeTable<const N: usize> {
+    regions: [Region; N],
+    // indices of the regions in address order, written once by the first lookup
+    order: UnsafeCell<[usize; N]>,
+    state: AtomicU8,
+}
+
+// SAFETY: `order` is only written by the lookup that moved the state to `SORTING`, and only
+// read once it's `SORTED`
+unsafe impl<const N: usize> Sync for RangeTable<N> {}
+
+impl<const N: usize> RangeTable<N> {
+    pub const fn new(regions: [Region; N]) -> Self {
+        Self {
+            regions,
+            order: UnsafeCell::new([0; N]),
+            state: AtomicU8::new(UNSORTED),
+        }
+    }
+
+    /// The index of the region holding `ptr`, in the order the regions were given.
+    pub fn find(&self, ptr: *mut u8) -> Option<usize> {
+        match self.state.load(Ordering::Acquire) {
+            SORTED => self.search(ptr),
+            UNSORTED if self.sort() => self.search(ptr),
+            _ => self.scan(ptr),
+        }
+    }
+
+    // sorts the index unless another lookup is at it, returns whether it's sorted
+    #[cold]
+    fn sort(&self) -> bool {
+        if self
+            .state
+            .compare_exchange(UNSORTED, SORTING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return self.state.load(Ordering::Acquire) == SORTED;
+        }
+
+        // SAFETY: no other lookup reads or writes the index until it's marked as sorted
+        let order = unsafe { &mut *self.order.get() };
+        for (index, slot) in order.iter_mut().enumerate() {
+            *slot = index;
+        }
+        order.sort_unstable_by_key(|&index| self.regions[index].start());
+
+        debug_assert!(
+            order
+                .windows(2)
+                .all(|pair| self.regions[pair[0]].end() <= self.regions[pair[1]].start()),
+            "regions overlap"
+        );
+
+        self.state.store(SORTED, Ordering::Release);
+        true
+    }
+
+    fn search(&self, ptr: *mut u8) -> Option<usize> {
+        // SAFETY: the index is sorted, nothing writes it anymore
+        let order = unsafe { &*self.order.get() };
+
+        // the last region starting at or before `ptr` is the only one that can hold it
+        let after = order.partition_point(|&index| self.regions[index].start() <= ptr as usize);
+        let index = order[after.checked_sub(1)?];
+
+        self.regions[index].contains(ptr).then_some(index)
+    }
+
+    fn scan(&self, ptr: *mut u8) -> Option<usize> {
+        self.regions.iter().position(|region| region.contains(ptr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arena;
+
+    #[test]
+    fn test_find() {
+        static FIRST: Arena<256> = Arena::new();
+        static SECOND: Arena<512> = Arena::new();
+        static THIRD: Arena<128> = Arena::new();
+        static TABLE: RangeTable<3> = RangeTable::new([
+            unsafe { FIRST.region() },
+            unsafe { SECOND.region() },
+            unsafe { THIRD.region() },
+        ]);
+
+        let at = |region: Region, offset: usize| (region.start() + offset) as *mut u8;
+        let mut outside = 0u8;
+
+        // whichever order the arenas landed in, every pointer maps back to its region
+        for _ in 0..2 {
+            assert_eq!(TABLE.find(at(unsafe { FIRST.region() }, 0)), Some(0));
+            assert_eq!(TABLE.find(at(unsafe { SECOND.region() }, 511)), Some(1));
+            assert_eq!(TABLE.find(at(unsafe { THIRD.region() }, 64)), Some(2));
+
+            assert_eq!(TABLE.find(&mut outside), None);
+            assert_eq!(TABLE.find(core::ptr::null_mut()), None);
+            assert_eq!(TABLE.state.load(Ordering::Relaxed), SORTED);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use super::linear_arena::ArenaAllocator;
+use super::scoped::Resettable;
+use super::{Relax, Spin, SpinLock};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A pool of `N` arenas handed out one per request, like the per-request pools of Apache.
+///
+/// A handler checks an arena out, allocates from it freely while it serves the request, and
+/// everything is released at once when the arena goes back to the pool:
+///
+/// ```ignore
+/// static POOL: RequestArenaPool<4> = RequestArenaPool::new([
+///     SpinLock::new(ArenaAllocator::new(unsafe { PARTS.region(0) })),
+///     ...
+/// ]);
+///
+/// fn handle(request: &Request) -> Option<Response> {
+///     let arena = POOL.checkout()?;
+///     let headers = arena.alloc_with(|| parse_headers(request))?;
+///     ...
+/// } // the arena is reset and returned to the pool here
+/// ```
+pub struct RequestArenaPool<const N: usize, R: Relax = Spin> {
+    arenas: [SpinLock<ArenaAllocator, R>; N],
+    // set while the arena at the same index is checked out
+    checked_out: [AtomicBool; N],
+}
+
+impl<const N: usize, R: Relax> RequestArenaPool<N, R> {
+    pub const fn new(arenas: [SpinLock<ArenaAllocator, R>; N]) -> Self {
+        Self {
+            arenas,
+            checked_out: [const { AtomicBool::new(false) }; N],
+        }
+    }
+
+    /// Checks out an arena for a request, `None` if all of them are in use.
+    pub fn checkout(&self) -> Option<PooledArena<'_, N, R>> {
+        let index = self.checked_out.iter().position(|checked_out| {
+            checked_out
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+
+        Some(PooledArena { pool: self, index })
+    }
+
+    /// Number of arenas that can be checked out right now.
+    pub fn available(&self) -> usize {
+        self.checked_out
+            .iter()
+            .filter(|checked_out| !checked_out.load(Ordering::Relaxed))
+            .count()
+    }
+}
+
+/// An arena checked out of a [`RequestArenaPool`], allocate from it through the
+/// [`SpinLock`] it derefs to.
+///
+/// Dropping it releases everything allocated from it and returns it to the pool.
+pub struct PooledArena<'p, const N: usize, R: Relax = Spin> {
+    pool: &'p RequestArenaPool<N, R>,
+    index: usize,
+}
+
+impl<const N: usize, R: Relax> Deref for PooledArena<'_, N, R> {
+    type Target = SpinLock<ArenaAllocator, R>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool.arenas[self.index]
+    }
+}
+
+impl<const N: usize, R: Relax> Drop for PooledArena<'_, N, R> {
+    fn drop(&mut self) {
+        // the request is over, nothing allocated for it can be used anymore
+        self.lock_with(|arena| unsafe { arena.rewind(0) });
+
+        self.pool.checked_out[self.index].store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arena;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    static FIRST: Arena<1024> = Arena::new();
+    static SECOND: Arena<1024> = Arena::new();
+    static POOL: RequestArenaPool<2> = RequestArenaPool::new([
+        SpinLock::new(ArenaAllocator::new(unsafe { FIRST.region() })),
+        SpinLock::new(ArenaAllocator::new(unsafe { SECOND.region() })),
+    ]);
+
+    #[test]
+    fn test_checkout_and_return() {
+        let layout = Layout::new::<[u64; 16]>();
+
+        let first = POOL.checkout().unwrap();
+        let second = POOL.checkout().unwrap();
+        assert!(POOL.checkout().is_none());
+        assert_eq!(POOL.available(), 0);
+
+        let block = unsafe { first.alloc(layout) };
+        assert_eq!(block as usize, unsafe { FIRST.region() }.start());
+        assert_eq!(
+            unsafe { first.alloc(layout) } as usize,
+            block as usize + 128
+        );
+        drop(first);
+        assert_eq!(POOL.available(), 1);
+
+        // the returned arena was reset for the next request
+        let next = POOL.checkout().unwrap();
+        assert_eq!(unsafe { next.alloc(layout) }, block);
+
+        drop(next);
+        drop(second);
+        assert_eq!(POOL.available(), 2);
+    }
+
+    #[test]
+    fn test_concurrent_requests() {
+        static THIRD: Arena<4096> = Arena::new();
+        static FOURTH: Arena<4096> = Arena::new();
+        static SHARED: RequestArenaPool<2> = RequestArenaPool::new([
+            SpinLock::new(ArenaAllocator::new(unsafe { THIRD.region() })),
+            SpinLock::new(ArenaAllocator::new(unsafe { FOURTH.region() })),
+        ]);
+
+        let layout = Layout::new::<[u8; 256]>();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        let Some(arena) = SHARED.checkout() else {
+                            continue;
+                        };
+
+                        // every request has the whole arena to itself
+                        for _ in 0..16 {
+                            assert!(!unsafe { arena.alloc(layout) }.is_null());
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(SHARED.available(), 2);
+    }
+}
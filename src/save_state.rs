@@ -0,0 +1,206 @@
+//! Saving the state of an allocator, the contents of its arena and its own bookkeeping, to
+//! restore it later, e.g. the save states of an emulator or a game keeping everything in one
+//! arena.
+//!
+//! ```ignore
+//! HEAP.lock_with(|heap| heap.save_state(File::create("level.state")?))?;
+//!
+//! // later on, every block handed out since is given up
+//! HEAP.lock_with(|heap| unsafe { heap.restore_state(File::open("level.state")?) })?;
+//! ```
+//!
+//! The allocators keep track of their blocks by their offset in the arena, so a state can also
+//! be restored into an arena at another address, as long as it moved by a multiple of
+//! [`RELOCATION_ALIGN`] (the blocks keep their alignment) and the free list links aren't keyed
+//! to the address with `safe-linking`. The pointers the program stored in the arena aren't
+//! fixed up though, data that has to survive a move refers to other blocks by handles (offsets
+//! from the start of the arena) instead.
+
+use super::utils::unpoison;
+use super::Region;
+use std::io::{self, Read, Write};
+
+/// A restored arena may only have moved by a multiple of this, so the blocks in it keep their
+/// alignment.
+pub const RELOCATION_ALIGN: usize = 4096;
+
+const MAGIC: [u8; 8] = *b"rsalloc\0";
+
+/// An allocator that can be saved along with the contents of its arena, and restored later.
+pub trait SaveState {
+    /// Writes the state of the allocator and the bytes of its arena in use to `writer`.
+    fn save_state<W: Write>(&self, writer: W) -> io::Result<()>;
+
+    /// Reads back a state written by [`save_state`](SaveState::save_state), overwriting the arena.
+    ///
+    /// The state is checked to come from an arena of the same size, that didn't move or moved
+    /// by a multiple of [`RELOCATION_ALIGN`]. If reading fails half way, the arena is left in
+    /// an unspecified state and the allocator has to be reset before it's used again. Blocks
+    /// in quarantine at the time of the save stay allocated.
+    ///
+    /// # Safety
+    ///
+    /// The state must have been saved from an allocator of the same type and configuration,
+    /// and none of the blocks handed out before the restore may be used afterwards.
+    unsafe fn restore_state<R: Read>(&mut self, reader: R) -> io::Result<()>;
+}
+
+/// Writes the header, the `state` words of the allocator and the first `len` bytes of `arena`.
+pub(crate) fn save_arena<W: Write>(
+    mut writer: W,
+    arena: Region,
+    state: &[usize],
+    len: usize,
+) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+
+    for word in [arena.start(), arena.size(), len, state.len()]
+        .iter()
+        .chain(state)
+    {
+        writer.write_all(&(*word as u64).to_le_bytes())?;
+    }
+
+    // SAFETY: the bytes up to `len` are in the arena, unused ones are saved as they are
+    let bytes = unsafe {
+        unpoison(arena.start() as *const u8, len);
+        core::slice::from_raw_parts(arena.start() as *const u8, len)
+    };
+    writer.write_all(bytes)?;
+    writer.flush()
+}
+
+/// Reads a state written by [`save_arena`] into `arena`, returns the state words of the
+/// allocator. The arena may only have moved if the allocator is `relocatable`.
+///
+/// # Safety
+///
+/// Nothing may be using the arena.
+pub(crate) unsafe fn restore_arena<R: Read, const N: usize>(
+    mut reader: R,
+    arena: Region,
+    relocatable: bool,
+) -> io::Result<[usize; N]> {
+    let mut magic = [0u8; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("not a saved allocator state"));
+    }
+
+    let [start, size, len, count] = read_words(&mut reader)?;
+
+    if size != arena.size() || len > size {
+        return Err(invalid("saved from an arena of another size"));
+    }
+    if start != arena.start()
+        && !(relocatable
+            && start
+                .abs_diff(arena.start())
+                .is_multiple_of(RELOCATION_ALIGN))
+    {
+        return Err(invalid("saved from an arena at an incompatible address"));
+    }
+    if count != N {
+        return Err(invalid("saved from another kind of allocator"));
+    }
+
+    let state = read_words(&mut reader)?;
+
+    // SAFETY: the bytes up to `len` are in the arena, which nothing uses
+    let bytes = unsafe {
+        unpoison(arena.start() as *const u8, len);
+        core::slice::from_raw_parts_mut(arena.start() as *mut u8, len)
+    };
+    reader.read_exact(bytes)?;
+
+    Ok(state)
+}
+
+fn read_words<R: Read, const N: usize>(reader: &mut R) -> io::Result<[usize; N]> {
+    let mut words = [0; N];
+
+    for word in &mut words {
+        let mut bytes = [0u8; 8];
+        reader.read_exact(&mut bytes)?;
+
+        *word =
+            usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| invalid("word overflows"))?;
+    }
+
+    Ok(words)
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// `Option<usize>` as a single word, 0 for `None`.
+#[cfg_attr(
+    not(any(feature = "stack", feature = "pool", feature = "freelist")),
+    allow(dead_code)
+)]
+pub(crate) fn encode(value: Option<usize>) -> usize {
+    value.map_or(0, |value| value + 1)
+}
+
+#[cfg_attr(
+    not(any(feature = "stack", feature = "pool", feature = "freelist")),
+    allow(dead_code)
+)]
+pub(crate) fn decode(word: usize) -> Option<usize> {
+    word.checked_sub(1)
+}
+
+#[cfg(test)]
+#[cfg(feature = "linear")]
+mod tests {
+    use super::*;
+    use crate::{Arena, ArenaAllocator, Resettable};
+    use core::alloc::Layout;
+
+    #[test]
+    fn test_rejects_other_arenas() {
+        static ARENA: Arena<2048> = Arena::new();
+        let start = unsafe { ARENA.region() }.start() as *mut u8;
+        let region = |offset, size| unsafe { Region::from_raw_parts(start.add(offset), size) };
+        let mut allocator = ArenaAllocator::new(region(0, 1024));
+
+        let ptr = allocator.alloc(Layout::new::<u32>()).cast::<u32>();
+        unsafe { ptr.write(42) };
+
+        let mut state = Vec::new();
+        allocator.save_state(&mut state).unwrap();
+
+        let restore = |allocator: &mut ArenaAllocator, state: &[u8]| unsafe {
+            allocator.restore_state(state).unwrap_err().kind()
+        };
+
+        // another size
+        let mut other = ArenaAllocator::new(region(0, 2048));
+        assert_eq!(restore(&mut other, &state), io::ErrorKind::InvalidData);
+
+        // moved by less than a page
+        let mut moved = ArenaAllocator::new(region(8, 1024));
+        assert_eq!(restore(&mut moved, &state), io::ErrorKind::InvalidData);
+
+        // not a state at all
+        assert_eq!(
+            restore(&mut allocator, b"garbage!"),
+            io::ErrorKind::InvalidData
+        );
+
+        // cut short
+        let len = state.len() - 1;
+        assert_eq!(
+            restore(&mut allocator, &state[..len]),
+            io::ErrorKind::UnexpectedEof
+        );
+
+        unsafe { allocator.restore_state(&state[..]) }.unwrap();
+        assert_eq!(unsafe { ptr.read() }, 42);
+        assert_eq!(
+            allocator.alloc(Layout::new::<u32>()),
+            ptr.wrapping_add(1).cast()
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use super::utils::{init_block, zeroed};
+use super::Region;
+use core::alloc::Layout;
+
+/// An allocator that can be rewound to an earlier state, releasing everything allocated since.
+pub trait Resettable {
+    /// A position of the allocator to rewind to.
+    type Checkpoint: Copy;
+
+    fn checkpoint(&self) -> Self::Checkpoint;
+
+    /// Releases every block allocated after `checkpoint` was taken.
+    ///
+    /// # Safety
+    ///
+    /// `checkpoint` must have been taken from this allocator, the allocator may not have been
+    /// rewound past it since, and none of the released blocks may be used afterwards.
+    unsafe fn rewind(&mut self, checkpoint: Self::Checkpoint);
+
+    /// Allocates a block, null if the allocator is out of memory.
+    fn alloc(&mut self, layout: Layout) -> *mut u8;
+}
+
+/// A saved state of an [`ArenaAllocator`](crate::ArenaAllocator) or a
+/// [`StackAllocator`](crate::StackAllocator), handed back to `restore` to roll back everything
+/// allocated since, e.g. when a parser gives up on an alternative it tried.
+///
+/// In debug builds the snapshot carries a checksum of the allocator's arena and offsets, so
+/// restoring it on another allocator panics, as does restoring it after the allocator was
+/// rewound past it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    prev_offset: usize,
+    curr_offset: usize,
+    #[cfg(debug_assertions)]
+    checksum: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(_arena: Region, prev_offset: usize, curr_offset: usize) -> Self {
+        Self {
+            prev_offset,
+            curr_offset,
+            #[cfg(debug_assertions)]
+            checksum: checksum(_arena, prev_offset, curr_offset),
+        }
+    }
+
+    // the offsets to restore, checked against the allocator's arena and current offset
+    pub(crate) fn offsets(self, _arena: Region, _curr_offset: usize) -> (usize, usize) {
+        #[cfg(debug_assertions)]
+        {
+            assert_eq!(
+                self.checksum,
+                checksum(_arena, self.prev_offset, self.curr_offset),
+                "snapshot restored on another allocator"
+            );
+            assert!(
+                self.curr_offset <= _curr_offset,
+                "allocator was rewound past the snapshot"
+            );
+        }
+
+        (self.prev_offset, self.curr_offset)
+    }
+}
+
+// FNV-1a over the words describing the snapshot
+#[cfg(debug_assertions)]
+fn checksum(arena: Region, prev_offset: usize, curr_offset: usize) -> u64 {
+    [arena.start(), arena.size(), prev_offset, curr_offset]
+        .into_iter()
+        .fold(0xCBF2_9CE4_8422_2325, |hash, word| {
+            (hash ^ word as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+/// Scratch allocations for the duration of a scope: every block allocated through it is
+/// released when it's dropped, rewinding the allocator to where it was when the scope was
+/// created.
+///
+/// ```ignore
+/// fn build_mesh(allocator: &mut StackAllocator) {
+///     let mut scratch = ScopedArena::new(allocator);
+///     let vertices = scratch.alloc(layout);
+///     ...
+/// } // `vertices` is released here
+/// ```
+///
+/// The scope borrows the allocator, so nothing else can allocate from it (and be released by
+/// mistake) while it's alive. An allocator behind a [`SpinLock`](crate::SpinLock) is borrowed
+/// through its guard, holding the lock for the whole scope.
+pub struct ScopedArena<'a, A: Resettable> {
+    allocator: &'a mut A,
+    checkpoint: A::Checkpoint,
+}
+
+impl<'a, A: Resettable> ScopedArena<'a, A> {
+    pub fn new(allocator: &'a mut A) -> Self {
+        Self {
+            checkpoint: allocator.checkpoint(),
+            allocator,
+        }
+    }
+
+    /// Allocates a block that lives until the scope ends, null if the allocator is out of
+    /// memory.
+    pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.allocator.alloc(layout);
+
+        unsafe { init_block(ptr, layout.size()) }
+    }
+
+    /// Same as [`ScopedArena::alloc`] with the block zeroed.
+    pub fn alloc_zeroed(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc(layout);
+
+        unsafe { zeroed(ptr, layout.size()) }
+    }
+
+    /// A nested scope, its blocks are released when it's dropped while this scope's stay.
+    pub fn scope(&mut self) -> ScopedArena<'_, A> {
+        ScopedArena::new(self.allocator)
+    }
+}
+
+impl<A: Resettable> Drop for ScopedArena<'_, A> {
+    fn drop(&mut self) {
+        // the blocks can't outlive the scope as far as the allocator is concerned
+        unsafe { self.allocator.rewind(self.checkpoint) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arena;
+
+    #[test]
+    #[cfg(feature = "linear")]
+    fn test_scoped_linear() {
+        use crate::ArenaAllocator;
+
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = ArenaAllocator::new(unsafe { ARENA.region() });
+
+        let layout = Layout::new::<[u64; 8]>();
+        let kept = Resettable::alloc(&mut allocator, layout);
+
+        let scratch = {
+            let mut scope = ScopedArena::new(&mut allocator);
+            let scratch = scope.alloc(layout);
+            assert_eq!(scratch as usize, kept as usize + 64);
+
+            {
+                let mut inner = scope.scope();
+                inner.alloc(Layout::new::<[u8; 512]>());
+            }
+            assert_eq!(scope.alloc(layout) as usize, scratch as usize + 64);
+
+            scratch
+        };
+
+        // the scratch memory is handed out again
+        assert_eq!(Resettable::alloc(&mut allocator, layout), scratch);
+    }
+
+    #[test]
+    #[cfg(feature = "stack")]
+    fn test_scoped_stack() {
+        use crate::{SpinLock, StackAllocator};
+        use core::alloc::GlobalAlloc;
+
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let layout = Layout::new::<[u64; 8]>();
+        let kept = unsafe { ALLOC.alloc(layout) };
+
+        let mut guard = ALLOC.lock();
+        let scratch = {
+            let mut scope = ScopedArena::new(guard.get_mut());
+            let zeroed = scope.alloc_zeroed(layout);
+            assert_eq!(unsafe { *(zeroed as *const [u64; 8]) }, [0; 8]);
+            scope.alloc(layout);
+
+            zeroed
+        };
+        SpinLock::unlock(guard);
+
+        // the stack is back to the block allocated before the scope, which can be freed in order
+        let next = unsafe { ALLOC.alloc(layout) };
+        assert_eq!(next, scratch);
+        unsafe {
+            ALLOC.dealloc(next, layout);
+            ALLOC.dealloc(kept, layout);
+        }
+        assert_eq!(ALLOC.rejected_frees(), 0);
+    }
+}
@@ -0,0 +1,143 @@
+use super::linked_list::FreeListAllocator;
+use super::utils::zeroed;
+use super::{PoolAllocator, RangeTable, Region, SpinLock};
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Serves small allocations from pools of fixed size chunks, one per size class, and everything
+/// else from a free list allocator.
+///
+/// Chunks carry no header, so a 16 byte node takes up 16 bytes instead of twice as much behind a
+/// free list header. Every pool has a region of its own, the size class of a freed block is
+/// recovered from the region it's in.
+///
+/// ```ignore
+/// static PARTS: Split<3> =
+///     unsafe { ARENA.split([("16", 8 * 1024), ("64", 8 * 1024), ("heap", 48 * 1024)]) };
+///
+/// static HEAP: SegregatedAllocator<2> = SegregatedAllocator::new(
+///     [
+///         SpinLock::new(PoolAllocator::new(unsafe { PARTS.region(0) }, 16)),
+///         SpinLock::new(PoolAllocator::new(unsafe { PARTS.region(1) }, 64)),
+///     ],
+///     SpinLock::new(FreeListAllocator::new(
+///         unsafe { PARTS.region(2) },
+///         PlacementPolicy::FindFirst,
+///     )),
+/// );
+/// ```
+///
+/// An allocation goes to the first class (in the given order, smallest first) whose chunks it
+/// fits in, and to the free list once that class is exhausted.
+#[derive(Debug)]
+pub struct SegregatedAllocator<const N: usize> {
+    classes: [SpinLock<PoolAllocator>; N],
+    fallback: SpinLock<FreeListAllocator>,
+    ranges: Option<RangeTable<N>>,
+}
+
+impl<const N: usize> SegregatedAllocator<N> {
+    /// Creates the allocator, `classes` ordered from the smallest chunk size to the largest.
+    pub const fn new(
+        classes: [SpinLock<PoolAllocator>; N],
+        fallback: SpinLock<FreeListAllocator>,
+    ) -> Self {
+        Self {
+            classes,
+            fallback,
+            ranges: None,
+        }
+    }
+
+    /// Finds the class a freed block belongs to in a [`RangeTable`] of `ranges`, the memory of
+    /// each pool in the same order, instead of asking the pools in turn.
+    pub const fn with_ranges(mut self, ranges: [Region; N]) -> Self {
+        self.ranges = Some(RangeTable::new(ranges));
+        self
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for SegregatedAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(class) = self.classes.iter().find(|class| class.fits(layout)) {
+            let ptr = unsafe { class.alloc(layout) };
+
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+
+        unsafe { self.fallback.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let class = match &self.ranges {
+            Some(ranges) => ranges.find(ptr).map(|index| &self.classes[index]),
+            None => self.classes.iter().find(|class| class.owns(ptr)),
+        };
+
+        match class {
+            Some(class) => unsafe { class.dealloc(ptr, layout) },
+            None => unsafe { self.fallback.dealloc(ptr, layout) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linked_list::PlacementPolicy;
+    use crate::Arena;
+
+    #[test]
+    fn test_size_classes() {
+        static SMALL: Arena<256> = Arena::new();
+        static LARGE: Arena<1024> = Arena::new();
+        static HEAP: Arena<1024> = Arena::new();
+        static ALLOC: SegregatedAllocator<2> = SegregatedAllocator::new(
+            [
+                SpinLock::new(PoolAllocator::new(unsafe { SMALL.region() }, 16)),
+                SpinLock::new(PoolAllocator::new(unsafe { LARGE.region() }, 64)),
+            ],
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { HEAP.region() },
+                PlacementPolicy::FindFirst,
+            )),
+        );
+
+        let node = Layout::new::<[u64; 2]>();
+
+        // the nodes are packed back to back, without a header between them
+        let first = unsafe { ALLOC.alloc(node) };
+        let second = unsafe { ALLOC.alloc(node) };
+        assert!(unsafe { SMALL.region() }.contains(first));
+        assert_eq!(second as usize - first as usize, 16);
+
+        // blocks go to the smallest class they fit in, or to the free list
+        let medium = Layout::new::<[u64; 5]>();
+        let large = Layout::new::<[u64; 16]>();
+        let (medium_ptr, large_ptr) = unsafe { (ALLOC.alloc(medium), ALLOC.alloc(large)) };
+        assert!(unsafe { LARGE.region() }.contains(medium_ptr));
+        assert!(unsafe { HEAP.region() }.contains(large_ptr));
+
+        // once a class is exhausted, the free list takes over
+        let rest: Vec<_> = (0..14).map(|_| unsafe { ALLOC.alloc(node) }).collect();
+        assert!(rest
+            .iter()
+            .all(|&ptr| unsafe { SMALL.region() }.contains(ptr)));
+        let overflow = unsafe { ALLOC.alloc(node) };
+        assert!(unsafe { HEAP.region() }.contains(overflow));
+
+        // and every block is freed where it came from
+        unsafe {
+            ALLOC.dealloc(second, node);
+            ALLOC.dealloc(overflow, node);
+            ALLOC.dealloc(large_ptr, large);
+        }
+        assert_eq!(unsafe { ALLOC.alloc(node) }, second);
+        assert_eq!(unsafe { ALLOC.alloc(large) }, large_ptr);
+    }
+}
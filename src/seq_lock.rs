@@ -0,0 +1,73 @@
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+/// A sequence lock, readers never block the writer.
+///
+/// Writers bump a sequence number before and after updating the value, readers copy the value
+/// and retry if the sequence number changed in the meantime (or was odd, a write in progress).
+/// This suits small `Copy` values written often and sampled from elsewhere, e.g. counters read
+/// by a monitoring thread.
+pub struct SeqLock<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: writers are serialized by the sequence number, readers only keep copies that were
+// validated against it
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a consistent copy of the value, retrying while a write is in progress.
+    pub fn read(&self) -> T {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 1 {
+                spin_loop();
+                continue;
+            }
+
+            // the copy may be torn by a concurrent write, it's only used if the sequence number
+            // shows there was none
+            let value = unsafe { ptr::read_volatile(self.value.get()) };
+            fence(Ordering::Acquire);
+
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return value;
+            }
+        }
+    }
+
+    /// Updates the value, waiting for other writers to be done. Keep `f` short, readers spin
+    /// while it runs.
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let seq = loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+
+            if seq & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break seq;
+            }
+            spin_loop();
+        };
+        // the odd sequence number is visible before any of the writes to the value
+        fence(Ordering::Release);
+
+        let result = f(unsafe { &mut *self.value.get() });
+
+        self.seq.store(seq + 2, Ordering::Release);
+        result
+    }
+}
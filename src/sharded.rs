@@ -0,0 +1,233 @@
+use super::linked_list::FreeListAllocator;
+use super::stats::Counters;
+use super::utils::zeroed;
+#[cfg(feature = "stats")]
+use super::AllocStats;
+use super::{RangeTable, Region, SpinLock};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// Spreads allocations over `N` free list allocators, each with its own lock and its own part
+/// of the memory, so threads running on different cores rarely wait for each other.
+///
+/// Every thread allocates from its own shard, and only takes memory from the other shards once
+/// its own is exhausted. Blocks are freed to the shard that owns them, whichever thread frees
+/// them.
+///
+/// ```ignore
+/// static ARENA: Arena = Arena::new();
+/// static PARTS: Split<2> =
+///     unsafe { ARENA.split([("shard 0", 64 * 1024), ("shard 1", 64 * 1024)]) };
+///
+/// static HEAP: ShardedAllocator<2> = ShardedAllocator::new([
+///     SpinLock::new(FreeListAllocator::new(
+///         unsafe { PARTS.region(0) },
+///         PlacementPolicy::FindFirst,
+///     )),
+///     SpinLock::new(FreeListAllocator::new(
+///         unsafe { PARTS.region(1) },
+///         PlacementPolicy::FindFirst,
+///     )),
+/// ]);
+/// ```
+#[derive(Debug)]
+pub struct ShardedAllocator<const N: usize> {
+    shards: [SpinLock<FreeListAllocator>; N],
+    shard_of: Option<fn() -> usize>,
+    ranges: Option<RangeTable<N>>,
+    counters: Counters,
+}
+
+impl<const N: usize> ShardedAllocator<N> {
+    /// Creates the allocator from its shards, there has to be at least one.
+    pub const fn new(shards: [SpinLock<FreeListAllocator>; N]) -> Self {
+        assert!(N > 0, "a sharded allocator needs at least one shard");
+
+        Self {
+            shards,
+            shard_of: None,
+            ranges: None,
+            counters: Counters::new(),
+        }
+    }
+
+    /// Sets the function picking the shard of the calling thread (modulo `N`), e.g. reading the
+    /// ID of the current core on bare metal.
+    ///
+    /// Under `std` every thread is given the next shard by default, without `std` all of them
+    /// share the first one until a function is set.
+    pub const fn with_shard_fn(mut self, shard_of: fn() -> usize) -> Self {
+        self.shard_of = Some(shard_of);
+        self
+    }
+
+    /// Finds the shard a freed block belongs to in a [`RangeTable`] of `ranges`, the memory of
+    /// each shard in the same order, instead of asking the shards in turn.
+    pub const fn with_ranges(mut self, ranges: [Region; N]) -> Self {
+        self.ranges = Some(RangeTable::new(ranges));
+        self
+    }
+
+    /// The allocations and frees over all the shards, counted without taking their locks.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> AllocStats {
+        self.counters.snapshot()
+    }
+
+    fn current_shard(&self) -> usize {
+        if let Some(shard_of) = self.shard_of {
+            return shard_of() % N;
+        }
+
+        #[cfg(feature = "std")]
+        return thread_shard() % N;
+
+        #[cfg(not(feature = "std"))]
+        0
+    }
+
+    // the shards starting with the calling thread's own one
+    fn shards_from_current(&self) -> impl Iterator<Item = &SpinLock<FreeListAllocator>> {
+        let current = self.current_shard();
+        self.shards[current..].iter().chain(&self.shards[..current])
+    }
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    static SHARD: core::cell::Cell<usize> = const { core::cell::Cell::new(usize::MAX) };
+}
+
+// the threads are handed out shards round robin, the first time they allocate
+#[cfg(feature = "std")]
+fn thread_shard() -> usize {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+    SHARD.with(|shard| {
+        if shard.get() == usize::MAX {
+            shard.set(NEXT_SHARD.fetch_add(1, Ordering::Relaxed));
+        }
+        shard.get()
+    })
+}
+
+unsafe impl<const N: usize> GlobalAlloc for ShardedAllocator<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        for shard in self.shards_from_current() {
+            let ptr = unsafe { shard.alloc(layout) };
+
+            if !ptr.is_null() {
+                self.counters.allocated(ptr, layout.size());
+                return ptr;
+            }
+        }
+
+        // every shard is out of memory
+        self.counters.allocated(ptr::null_mut(), layout.size());
+        ptr::null_mut()
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let shard = match &self.ranges {
+            Some(ranges) => ranges.find(ptr).map(|index| &self.shards[index]),
+            // blocks are usually freed by the thread that allocated them, so its shard is tried
+            // first
+            None => self.shards_from_current().find(|shard| shard.owns(ptr)),
+        };
+
+        if let Some(shard) = shard {
+            unsafe { shard.dealloc(ptr, layout) };
+            self.counters.freed(layout.size());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linked_list::PlacementPolicy;
+    use crate::{Arena, Region};
+
+    fn owns(region: Region, ptr: *mut u8) -> bool {
+        region.start() <= ptr as usize && (ptr as usize) < region.end()
+    }
+
+    #[test]
+    fn test_steal_when_exhausted() {
+        static FIRST: Arena<1024> = Arena::new();
+        static SECOND: Arena<1024> = Arena::new();
+        static ALLOC: ShardedAllocator<2> = ShardedAllocator::new([
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { FIRST.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { SECOND.region() },
+                PlacementPolicy::FindFirst,
+            )),
+        ])
+        .with_shard_fn(|| 2);
+
+        let layout = Layout::new::<[u64; 64]>();
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        assert!(owns(unsafe { FIRST.region() }, ptr_1));
+
+        // the own shard is full, so the memory comes from the other one
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        assert!(owns(unsafe { SECOND.region() }, ptr_2));
+
+        // freed blocks go back to their shard, the own one is used again
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        unsafe { ALLOC.dealloc(ptr_2, layout) };
+        assert_eq!(unsafe { ALLOC.alloc(layout) }, ptr_1);
+
+        #[cfg(feature = "stats")]
+        {
+            let stats = ALLOC.stats();
+            assert_eq!((stats.allocs, stats.deallocs, stats.failed), (3, 2, 0));
+            assert_eq!(stats.live_blocks, 1);
+            assert_eq!(stats.peak_bytes, 2 * layout.size());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_shard_per_thread() {
+        static FIRST: Arena<4096> = Arena::new();
+        static SECOND: Arena<4096> = Arena::new();
+        static ALLOC: ShardedAllocator<2> = ShardedAllocator::new([
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { FIRST.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { SECOND.region() },
+                PlacementPolicy::FindFirst,
+            )),
+        ]);
+
+        let layout = Layout::new::<[u64; 8]>();
+
+        let main = unsafe { ALLOC.alloc(layout) } as usize;
+        let other = std::thread::spawn(move || unsafe { ALLOC.alloc(layout) } as usize)
+            .join()
+            .unwrap();
+
+        // the threads are given consecutive shards
+        assert_ne!(
+            owns(unsafe { FIRST.region() }, main as *mut u8),
+            owns(unsafe { FIRST.region() }, other as *mut u8)
+        );
+
+        // any thread may free a block
+        unsafe { ALLOC.dealloc(other as *mut u8, layout) };
+        unsafe { ALLOC.dealloc(main as *mut u8, layout) };
+    }
+}
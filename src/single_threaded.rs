@@ -0,0 +1,128 @@
+use super::SpinTimeout;
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+
+/// Gives an allocator the `Sync` a `static` needs without any locking, for single core targets
+/// where nothing can preempt an allocation (no threads, no allocating interrupt handlers) and a
+/// [`SpinLock`](crate::SpinLock) would be pure overhead.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static GLOBAL: SingleThreaded<FreeListAllocator> = unsafe {
+///     SingleThreaded::new(FreeListAllocator::new(ARENA.region(), PlacementPolicy::FindFirst))
+/// };
+/// ```
+///
+/// Every allocator implements [`GlobalAlloc`](core::alloc::GlobalAlloc) behind it the same way
+/// it does behind a `SpinLock`.
+pub struct SingleThreaded<T> {
+    value: UnsafeCell<T>,
+    // set while the value is borrowed, to catch an allocation preempting another one
+    borrowed: Cell<bool>,
+}
+
+// SAFETY: the creator of the value promised it's only ever accessed from a single context
+unsafe impl<T> Sync for SingleThreaded<T> {}
+
+// clears the borrow flag once `lock_with` is done, even if `f` unwinds
+struct Borrow<'a>(&'a Cell<bool>);
+
+impl Drop for Borrow<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+impl<T> SingleThreaded<T> {
+    /// # Safety
+    ///
+    /// The value must only be accessed from one thread of execution: the target has a single
+    /// core, there are no other threads, and interrupt handlers don't allocate.
+    pub const unsafe fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            borrowed: Cell::new(false),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the value, named after
+    /// [`SpinLock::lock_with`](crate::SpinLock::lock_with) so the two are interchangeable.
+    ///
+    /// Panics in debug builds if the value is accessed again while `f` runs, i.e. the promise
+    /// made in [`SingleThreaded::new`] was broken.
+    pub fn lock_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let borrowed = self.borrowed.replace(true);
+        debug_assert!(!borrowed, "single threaded value accessed concurrently");
+        let _borrow = Borrow(&self.borrowed);
+
+        // SAFETY: there is a single context of execution, and `f` can't reach the value again
+        f(unsafe { &mut *self.value.get() })
+    }
+
+    /// [`lock_with`](Self::lock_with), which never times out, named after
+    /// [`SpinLock::lock_with_or_timeout`](crate::SpinLock::lock_with_or_timeout).
+    pub fn lock_with_or_timeout<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, SpinTimeout> {
+        Ok(self.lock_with(f))
+    }
+}
+
+// the value isn't printed while it's borrowed, e.g. from a handler preempting an allocation
+impl<T: fmt::Debug> fmt::Debug for SingleThreaded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("SingleThreaded");
+        if self.borrowed.get() {
+            debug.field("value", &format_args!("<borrowed>"));
+        } else {
+            self.lock_with(|value| debug.field("value", value));
+        }
+        debug.finish()
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy};
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_single_threaded_allocator() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SingleThreaded<FreeListAllocator> = unsafe {
+            SingleThreaded::new(FreeListAllocator::new(
+                ARENA.region(),
+                PlacementPolicy::FindFirst,
+            ))
+        };
+
+        let layout = Layout::new::<[u64; 4]>();
+        let ptr = unsafe { ALLOC.alloc(layout) };
+        assert!(!ptr.is_null());
+
+        unsafe { ALLOC.dealloc(ptr, layout) };
+        assert_eq!(unsafe { ALLOC.alloc(layout) }, ptr);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "accessed concurrently")]
+    fn test_reentrancy_detected() {
+        let value = unsafe { SingleThreaded::new(0) };
+
+        value.lock_with(|_| value.lock_with(|_| ()));
+    }
+
+    #[test]
+    fn test_borrow_released_on_unwind() {
+        let value = unsafe { SingleThreaded::new(0) };
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            value.lock_with(|_| panic!("allocation failed"))
+        }));
+        assert!(panicked.is_err());
+
+        // the value can be borrowed again
+        assert_eq!(value.lock_with(|value| *value + 1), 1);
+        assert_eq!(format!("{value:?}"), "SingleThreaded { value: 0 }");
+    }
+}
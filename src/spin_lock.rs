@@ -1,58 +1,645 @@
-use core::cell::UnsafeCell;
-use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(loom))]
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-pub struct SpinLock<T> {
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::NonNull;
+
+/// How a [`SpinLock`] waits while another thread holds it.
+///
+/// `relax` is called every time the lock is found taken, with the number of times it was called
+/// before for the same acquisition. Returning `false` gives up on the lock.
+pub trait Relax {
+    fn relax(spins: usize) -> bool;
+
+    /// Called after the lock is released, to wake up threads waiting for it.
+    fn wake() {}
+}
+
+/// Spins with a hint to the CPU, the default.
+pub struct Spin;
+
+impl Relax for Spin {
+    #[inline(always)]
+    fn relax(_spins: usize) -> bool {
+        spin_loop();
+        true
+    }
+}
+
+/// Lets the OS schedule another thread, for user space code where the lock holder may have been
+/// preempted.
+#[cfg(feature = "std")]
+pub struct Yield;
+
+#[cfg(feature = "std")]
+impl Relax for Yield {
+    fn relax(_spins: usize) -> bool {
+        std::thread::yield_now();
+        true
+    }
+}
+
+/// Sleeps the core with `WFE` until the holder signals the release with `SEV`, to save power on
+/// bare metal ARM. Spins with a hint on other architectures.
+pub struct Wfe;
+
+impl Relax for Wfe {
+    #[inline(always)]
+    fn relax(_spins: usize) -> bool {
+        #[cfg(all(any(target_arch = "arm", target_arch = "aarch64"), not(loom)))]
+        unsafe {
+            core::arch::asm!("wfe", options(nomem, nostack, preserves_flags))
+        };
+        #[cfg(not(all(any(target_arch = "arm", target_arch = "aarch64"), not(loom))))]
+        spin_loop();
+        true
+    }
+
+    #[inline(always)]
+    fn wake() {
+        #[cfg(all(any(target_arch = "arm", target_arch = "aarch64"), not(loom)))]
+        unsafe {
+            core::arch::asm!("sev", options(nomem, nostack, preserves_flags))
+        };
+    }
+}
+
+/// Spins with a hint up to `N` times, then gives up, for latency critical threads that would
+/// rather fail than wait on a lock for long.
+pub struct Bounded<const N: usize>;
+
+impl<const N: usize> Relax for Bounded<N> {
+    #[inline(always)]
+    fn relax(spins: usize) -> bool {
+        spin_loop();
+        spins + 1 < N
+    }
+}
+
+/// How often a [`SpinLock`] was taken, and how often it had to be waited for.
+#[cfg(feature = "lock-stats")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LockStats {
+    pub acquisitions: usize,
+    /// Acquisitions that found the lock taken.
+    pub contended: usize,
+}
+
+/// The [`Relax`] strategy of a [`SpinLock`] gave up on the lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpinTimeout;
+
+impl fmt::Display for SpinTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("gave up waiting for the spin lock")
+    }
+}
+
+impl core::error::Error for SpinTimeout {}
+
+/// A spin lock around an allocator's metadata.
+///
+/// The lock sits on its own cache line(s), see [`CACHE_LINE_SIZE`](crate::CACHE_LINE_SIZE), so
+/// spinning on it doesn't contend with neighbouring statics.
+///
+/// `R` picks how to wait for the lock, see [`Relax`]. Locks with another strategy than [`Spin`]
+/// are created with [`SpinLock::with_relax`]:
+///
+/// ```ignore
+/// static HEAP: SpinLock<FreeListAllocator, Wfe> = SpinLock::with_relax(...);
+/// ```
+#[cfg_attr(
+    not(any(feature = "cache-line-32", feature = "cache-line-128")),
+    repr(align(64))
+)]
+#[cfg_attr(
+    all(feature = "cache-line-32", not(feature = "cache-line-128")),
+    repr(align(32))
+)]
+#[cfg_attr(feature = "cache-line-128", repr(align(128)))]
+pub struct SpinLock<T, R = Spin> {
     locked: AtomicBool,
     value: UnsafeCell<T>,
+    relax: PhantomData<fn() -> R>,
+    #[cfg(feature = "lock-stats")]
+    acquisitions: AtomicUsize,
+    #[cfg(feature = "lock-stats")]
+    contended: AtomicUsize,
 }
 
 impl<T> SpinLock<T> {
+    #[cfg(not(loom))]
     pub const fn new(value: T) -> Self {
+        Self::with_relax(value)
+    }
+
+    // loom's atomics can't be created in a const context
+    #[cfg(loom)]
+    pub fn new(value: T) -> Self {
+        Self::with_relax(value)
+    }
+}
+
+impl<T, R: Relax> SpinLock<T, R> {
+    #[cfg(not(loom))]
+    pub const fn with_relax(value: T) -> Self {
         Self {
             locked: AtomicBool::new(false),
             value: UnsafeCell::new(value),
+            relax: PhantomData,
+            #[cfg(feature = "lock-stats")]
+            acquisitions: AtomicUsize::new(0),
+            #[cfg(feature = "lock-stats")]
+            contended: AtomicUsize::new(0),
         }
     }
 
-    pub fn lock(&self) -> Guard<T> {
+    #[cfg(loom)]
+    pub fn with_relax(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+            relax: PhantomData,
+            #[cfg(feature = "lock-stats")]
+            acquisitions: AtomicUsize::new(0),
+            #[cfg(feature = "lock-stats")]
+            contended: AtomicUsize::new(0),
+        }
+    }
+
+    // the value of a lock that isn't shared yet, also while building a `static`
+    #[cfg(all(not(loom), feature = "freelist"))]
+    pub(crate) const fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Acquires the lock, waiting for it as long as `R` does.
+    ///
+    /// Panics if `R` gives up on the lock, use [`lock_or_timeout`](Self::lock_or_timeout) to
+    /// handle it. Allocating through the lock never panics, the allocation fails instead.
+    pub fn lock(&self) -> Guard<'_, T, R> {
+        match self.lock_or_timeout() {
+            Ok(guard) => guard,
+            Err(SpinTimeout) => panic!("gave up waiting for the spin lock"),
+        }
+    }
+
+    /// Acquires the lock, or fails if `R` gives up waiting for it.
+    pub fn lock_or_timeout(&self) -> Result<Guard<'_, T, R>, SpinTimeout> {
+        let mut spins = 0;
         while self
             .locked
             .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
         {
-            core::hint::spin_loop();
+            if !R::relax(spins) {
+                return Err(SpinTimeout);
+            }
+            spins += 1;
+        }
+
+        #[cfg(feature = "lock-stats")]
+        {
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+            if spins > 0 {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(Guard { lock: self })
+    }
+
+    /// Acquires the lock if it's free, without spinning.
+    pub fn try_lock(&self) -> Option<Guard<'_, T, R>> {
+        let guard = self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| Guard { lock: self });
+
+        #[cfg(feature = "lock-stats")]
+        if guard.is_some() {
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        }
+        guard
+    }
+
+    /// Whether the lock is held at the moment, which may have changed by the time the caller
+    /// looks at the answer.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Acquisitions of the lock so far, to tell whether it's a bottleneck.
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
         }
-        Guard { lock: self }
+    }
+
+    /// Runs `f` with the lock held, the lock is released as soon as `f` returns (or unwinds).
+    pub fn lock_with<U>(&self, f: impl FnOnce(&mut T) -> U) -> U {
+        let mut guard = self.lock();
+        f(guard.get_mut())
+    }
+
+    /// Runs `f` with the lock held, or fails without running it if `R` gives up waiting for the
+    /// lock. Allocating through the lock fails the allocation instead of panicking.
+    pub fn lock_with_or_timeout<U>(&self, f: impl FnOnce(&mut T) -> U) -> Result<U, SpinTimeout> {
+        let mut guard = self.lock_or_timeout()?;
+        Ok(f(guard.get_mut()))
     }
 
     /// Drops the guard, and consequently unlocks the mutex.
-    pub fn unlock(guard: Guard<'_, T>) {
+    pub fn unlock(guard: Guard<'_, T, R>) {
         drop(guard);
     }
 }
 
-unsafe impl<T> Sync for SpinLock<T> where T: Send {}
+unsafe impl<T, R> Sync for SpinLock<T, R> where T: Send {}
 
-pub struct Guard<'a, T> {
-    lock: &'a SpinLock<T>,
+// prints the value if the lock is free, without waiting for it
+impl<T: fmt::Debug, R: Relax> fmt::Debug for SpinLock<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("SpinLock");
+        match self.try_lock() {
+            Some(guard) => debug.field("value", guard.get()),
+            None => debug.field("value", &format_args!("<locked>")),
+        };
+        debug.finish()
+    }
 }
 
-impl<T> Guard<'_, T> {
+pub struct Guard<'a, T, R: Relax = Spin> {
+    lock: &'a SpinLock<T, R>,
+}
+
+impl<'a, T, R: Relax> Guard<'a, T, R> {
     /// Returns a mutable reference to the underlying data.
     pub fn get(&self) -> &T {
         // SAFETY: If we have a guard, then we have exclusively locked the lock
-        unsafe { &*self.lock.value.get() }
+        self.lock.value.with(|value| unsafe { &*value })
     }
 
     /// Returns a mutable reference to the underlying data.
-    pub fn get_mut(&self) -> &mut T {
+    pub fn get_mut(&mut self) -> &mut T {
         // SAFETY: If we have a guard, then we have exclusively locked the lock
-        unsafe { &mut *self.lock.value.get() }
+        self.lock.value.with_mut(|value| unsafe { &mut *value })
+    }
+
+    /// Narrows the guard down to a part of the locked value, e.g. to hand out access to the
+    /// stats of an allocator without the rest of it. The lock stays held until the new guard
+    /// is dropped.
+    ///
+    /// ```ignore
+    /// let stats = Guard::map(HEAP.lock(), |heap| &mut heap.stats);
+    /// ```
+    pub fn map<U: ?Sized>(
+        mut guard: Self,
+        f: impl FnOnce(&mut T) -> &mut U,
+    ) -> MappedGuard<'a, U, R> {
+        let value = NonNull::from(f(guard.get_mut()));
+        let locked = &guard.lock.locked;
+
+        // the lock is released by the mapped guard instead
+        mem::forget(guard);
+        MappedGuard {
+            locked,
+            value,
+            relax: PhantomData,
+        }
     }
 }
 
-impl<T> Drop for Guard<'_, T> {
+impl<T, R: Relax> Drop for Guard<'_, T, R> {
     fn drop(&mut self) {
         self.lock.locked.store(false, Ordering::Release);
+        R::wake();
+    }
+}
+
+/// A [`Guard`] narrowed down to a part of the locked value, see [`Guard::map`].
+pub struct MappedGuard<'a, U: ?Sized, R: Relax = Spin> {
+    locked: &'a AtomicBool,
+    value: NonNull<U>,
+    relax: PhantomData<fn() -> R>,
+}
+
+// SAFETY: the guard hands out references to the value like `&mut U` would
+unsafe impl<U: ?Sized + Sync, R: Relax> Sync for MappedGuard<'_, U, R> {}
+
+impl<U: ?Sized, R: Relax> MappedGuard<'_, U, R> {
+    /// Returns a reference to the part of the data the guard was narrowed down to.
+    pub fn get(&self) -> &U {
+        // SAFETY: the lock is held, and the value was borrowed from the locked data
+        unsafe { self.value.as_ref() }
+    }
+
+    /// Returns a mutable reference to the part of the data the guard was narrowed down to.
+    pub fn get_mut(&mut self) -> &mut U {
+        // SAFETY: the lock is held, and the value was borrowed from the locked data
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<U: ?Sized, R: Relax> Drop for MappedGuard<'_, U, R> {
+    fn drop(&mut self) {
+        self.locked.store(false, Ordering::Release);
+        R::wake();
+    }
+}
+
+/// A reader-writer spin lock, for values that are mostly read, e.g. stats or registries that
+/// are queried for diagnostics while allocations go on.
+///
+/// Any number of readers may hold the lock at once. A writer waiting for the lock keeps new
+/// readers out, so a steady stream of readers can't starve it.
+#[cfg_attr(
+    not(any(feature = "cache-line-32", feature = "cache-line-128")),
+    repr(align(64))
+)]
+#[cfg_attr(
+    all(feature = "cache-line-32", not(feature = "cache-line-128")),
+    repr(align(32))
+)]
+#[cfg_attr(feature = "cache-line-128", repr(align(128)))]
+pub struct RwSpinLock<T> {
+    // the number of readers times `READER`, plus the `WRITER` and `PENDING` flags
+    state: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+const WRITER: usize = 1;
+// a writer is waiting, readers back off
+const PENDING: usize = 2;
+const READER: usize = 4;
+
+impl<T> RwSpinLock<T> {
+    #[cfg(not(loom))]
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    // loom's atomics can't be created in a const context
+    #[cfg(loom)]
+    pub fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires shared access, waiting for the writer (and any waiting writer) to be done.
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            spin_loop();
+        }
+    }
+
+    /// Acquires shared access if no writer holds or waits for the lock, without spinning.
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let state = self.state.fetch_add(READER, Ordering::Acquire);
+
+        if state & (WRITER | PENDING) != 0 {
+            self.state.fetch_sub(READER, Ordering::Release);
+            return None;
+        }
+        Some(ReadGuard { lock: self })
+    }
+
+    /// Acquires exclusive access, waiting for the readers to be done.
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+
+            if state & !PENDING == 0 {
+                if self
+                    .state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    return WriteGuard { lock: self };
+                }
+            } else if state & PENDING == 0 {
+                self.state.fetch_or(PENDING, Ordering::Relaxed);
+            }
+            spin_loop();
+        }
+    }
+
+    /// Acquires exclusive access if the lock is free, without spinning.
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| WriteGuard { lock: self })
+    }
+
+    /// Runs `f` with shared access, the lock is released as soon as `f` returns (or unwinds).
+    pub fn read_with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let guard = self.read();
+        f(guard.get())
+    }
+
+    /// Runs `f` with exclusive access, the lock is released as soon as `f` returns (or unwinds).
+    pub fn write_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut guard = self.write();
+        f(guard.get_mut())
+    }
+}
+
+unsafe impl<T> Sync for RwSpinLock<T> where T: Send + Sync {}
+
+pub struct ReadGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+impl<T> ReadGuard<'_, T> {
+    /// Returns a reference to the underlying data.
+    pub fn get(&self) -> &T {
+        // SAFETY: while there are readers there is no writer
+        self.lock.value.with(|value| unsafe { &*value })
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+impl<T> WriteGuard<'_, T> {
+    /// Returns a reference to the underlying data.
+    pub fn get(&self) -> &T {
+        // SAFETY: the writer has exclusive access
+        self.lock.value.with(|value| unsafe { &*value })
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    pub fn get_mut(&mut self) -> &mut T {
+        // SAFETY: the writer has exclusive access
+        self.lock.value.with_mut(|value| unsafe { &mut *value })
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // readers trying their luck in the meantime briefly add to the count, so only the flag is
+        // cleared
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+// `core::cell::UnsafeCell` with the closure based accessors of loom's, so loom can check every
+// access to the value
+#[cfg(not(loom))]
+struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    const fn new(value: T) -> Self {
+        Self(core::cell::UnsafeCell::new(value))
+    }
+
+    #[cfg(feature = "freelist")]
+    const fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+
+    #[inline(always)]
+    fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    #[inline(always)]
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+#[cfg(loom)]
+use loom::cell::UnsafeCell;
+
+#[cfg(not(loom))]
+use core::hint::spin_loop;
+
+// loom has to be told that the thread is waiting for another one
+#[cfg(loom)]
+use loom::thread::yield_now as spin_loop;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug() {
+        let lock = SpinLock::new(7);
+        assert_eq!(format!("{lock:?}"), "SpinLock { value: 7 }");
+
+        // a held lock isn't waited for
+        let guard = lock.lock();
+        assert_eq!(format!("{lock:?}"), "SpinLock { value: <locked> }");
+        drop(guard);
+    }
+
+    #[test]
+    fn test_bounded_spinning() {
+        let lock: SpinLock<usize, Bounded<100>> = SpinLock::with_relax(0);
+
+        let guard = lock.lock();
+        assert_eq!(lock.lock_or_timeout().err(), Some(SpinTimeout));
+        drop(guard);
+
+        lock.lock_with(|value| *value += 1);
+        assert_eq!(*lock.lock_or_timeout().unwrap().get(), 1);
+    }
+
+    #[test]
+    fn test_is_locked() {
+        let lock = SpinLock::new(());
+        assert!(!lock.is_locked());
+
+        let guard = lock.lock();
+        assert!(lock.is_locked());
+        assert!(lock.try_lock().is_none());
+
+        drop(guard);
+        assert!(!lock.is_locked());
+    }
+
+    #[test]
+    fn test_map() {
+        let lock = SpinLock::new((0u32, [0u8; 4]));
+
+        let mut bytes = Guard::map(lock.lock(), |value| &mut value.1[..]);
+        bytes.get_mut()[2] = 7;
+        assert_eq!(bytes.get().len(), 4);
+
+        // the mapped guard holds the lock
+        assert!(lock.is_locked());
+        drop(bytes);
+        assert!(!lock.is_locked());
+
+        assert_eq!(*lock.lock().get(), (0, [0, 0, 7, 0]));
+    }
+
+    #[test]
+    #[cfg(feature = "lock-stats")]
+    fn test_lock_stats() {
+        use std::sync::atomic::AtomicUsize;
+
+        static WAITS: AtomicUsize = AtomicUsize::new(0);
+
+        // counts the times a thread found the lock taken
+        struct CountWaits;
+
+        impl Relax for CountWaits {
+            fn relax(_spins: usize) -> bool {
+                WAITS.fetch_add(1, Ordering::Relaxed);
+                spin_loop();
+                true
+            }
+        }
+
+        let lock = std::sync::Arc::new(SpinLock::<usize, CountWaits>::with_relax(0));
+
+        let guard = lock.lock();
+        let waiter = {
+            let lock = lock.clone();
+            std::thread::spawn(move || lock.lock_with(|value| *value += 1))
+        };
+
+        // release the lock only once the other thread found it taken
+        while WAITS.load(Ordering::Relaxed) == 0 {
+            assert!(lock.is_locked());
+            spin_loop();
+        }
+        drop(guard);
+        waiter.join().unwrap();
+
+        lock.try_lock().unwrap();
+        assert_eq!(
+            lock.stats(),
+            LockStats {
+                acquisitions: 3,
+                contended: 1,
+            }
+        );
     }
 }
@@ -1,115 +1,473 @@
-use super::utils::calc_padding_with_header;
-use super::{Arena, SpinLock};
+#[cfg(feature = "metrics")]
+use super::metrics::{Metered, Usage};
+#[cfg(feature = "std")]
+use super::save_state::{self, restore_arena, save_arena, SaveState};
+use super::scoped::{Resettable, Snapshot};
+use super::utils::{
+    calc_padding_with_header, init_block, release_block, unpoison, zeroed, PackedHeader,
+};
+#[cfg(feature = "valgrind")]
+use super::valgrind;
+use super::{Region, Relax, SingleThreaded, SpinLock};
 use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
 use core::mem::size_of;
 use core::ptr;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
 
-// Not needed anymore since we're using usize for padding instead of u8
-// which makes the `MAX_ALIGNMENT` huge
-// max_alignment = 2 ^ (8 * sizeof(padding) − 1)
-// const MAX_ALIGNMENT: usize = 128;
+// On 64-bit targets the padding shares the header word with the previous offset, see
+// `PackedHeader`, which caps the alignment at roughly `PackedHeader::MAX_PADDING / 2`
+
+/// Number of out of order frees a [`StackAllocator`] keeps track of with
+/// [`with_deferred_frees`](StackAllocator::with_deferred_frees).
+pub const DEFERRED_FREES: usize = 8;
 
 pub struct StackAllocator {
-    arena: Arena,
+    arena: Region,
     prev_offset: usize,
     curr_offset: usize,
+
+    // the blocks freed below the top of the stack, by the offset they start at and the previous
+    // offset stored in their header
+    deferred: [Option<(usize, usize)>; DEFERRED_FREES],
+    defer_frees: bool,
+
+    rejected_frees: usize,
+    free_error_hook: Option<fn(StackFreeError, *mut u8)>,
 }
 
+/// Reason a free couldn't be honored by the [`StackAllocator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackFreeError {
+    /// The pointer is outside of the arena.
+    OutOfBounds,
+    /// The pointer is past the top of the stack, so it isn't allocated.
+    NotAllocated,
+    /// The pointer isn't the top of the stack, its memory is leaked until the blocks above it are
+    /// freed. With [`with_deferred_frees`](StackAllocator::with_deferred_frees) it's only
+    /// reported once [`DEFERRED_FREES`] blocks are waiting already.
+    OutOfOrder,
+}
+
+impl fmt::Display for StackFreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StackFreeError::OutOfBounds => "pointer is outside of the arena",
+            StackFreeError::NotAllocated => "pointer is past the top of the stack",
+            StackFreeError::OutOfOrder => "pointer isn't the top of the stack",
+        })
+    }
+}
+
+impl core::error::Error for StackFreeError {}
+
 impl StackAllocator {
-    pub const fn new() -> Self {
+    pub const fn new(arena: Region) -> Self {
         StackAllocator {
-            arena: Arena::new(),
+            arena,
             prev_offset: 0,
             curr_offset: 0,
+            deferred: [None; DEFERRED_FREES],
+            defer_frees: false,
+            rejected_frees: 0,
+            free_error_hook: None,
         }
     }
-}
 
-unsafe impl GlobalAlloc for SpinLock<StackAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // Start of the critical section
-        let guard = self.lock();
+    /// Sets a callback that is told about every free that couldn't be honored. It's called
+    /// after the lock is released, so it may use the allocator.
+    pub const fn with_free_error_hook(mut self, hook: fn(StackFreeError, *mut u8)) -> Self {
+        self.free_error_hook = Some(hook);
+        self
+    }
 
-        let mut allocator = guard.get_mut();
+    /// Remembers blocks freed below the top of the stack instead of rejecting them, and pops
+    /// them as well once the blocks above them are freed, so frees that are only slightly out of
+    /// order still reclaim their memory.
+    ///
+    /// Up to [`DEFERRED_FREES`] blocks can wait at a time, further out of order frees are
+    /// rejected as usual and leak until the allocator is rewound past them.
+    pub const fn with_deferred_frees(mut self) -> Self {
+        self.defer_frees = true;
+        self
+    }
 
-        let curr_addr = allocator.curr_offset + allocator.arena.start();
+    /// Bytes of metadata every allocation costs, on top of the padding needed for its alignment.
+    pub const fn overhead_per_alloc() -> usize {
+        size_of::<StackHeader>()
+    }
 
-        let padding_with_header =
-            calc_padding_with_header(curr_addr, layout.align(), size_of::<StackHeader>());
+    /// Smallest number of bytes an allocation takes up in the arena, the header of a zero sized
+    /// one.
+    pub const fn min_block_size() -> usize {
+        Self::overhead_per_alloc()
+    }
 
-        let end = curr_addr + padding_with_header + layout.size();
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let curr_addr = self.curr_offset + self.arena.start();
 
-        if end > allocator.arena.end() {
-            // stack allocator is out of memory
-            SpinLock::unlock(guard);
+        let Some(padding_with_header) =
+            calc_padding_with_header(curr_addr, layout.align(), size_of::<StackHeader>())
+        else {
+            return ptr::null_mut();
+        };
+
+        // the padding doesn't fit in the header, or the offset of the previous block doesn't
+        // (past 256 TB into the arena), only ever the case for packed 64-bit headers
+        if padding_with_header > PackedHeader::MAX_PADDING
+            || self.curr_offset > PackedHeader::MAX_SIZE
+        {
             return ptr::null_mut();
         }
 
+        let end = (curr_addr + padding_with_header).checked_add(layout.size());
+
+        let Some(end) = end.filter(|&end| end <= self.arena.end()) else {
+            // stack allocator is out of memory
+            return ptr::null_mut();
+        };
+
         // store the header
         let header_addr = curr_addr + padding_with_header - size_of::<StackHeader>();
-        let header = StackHeader {
-            prev_offset: allocator.prev_offset,
-            padding: padding_with_header,
-        };
-        unsafe { ptr::write(header_addr as *mut StackHeader, header) };
+        let header = StackHeader::new(self.prev_offset, padding_with_header);
+        // blocks with a small alignment leave the header unaligned
+        unsafe {
+            // the header may land on a block freed earlier
+            unpoison(header_addr as *const u8, size_of::<StackHeader>());
+            ptr::write_unaligned(header_addr as *mut StackHeader, header);
+        }
 
         // update the offsets
-        allocator.prev_offset = allocator.curr_offset;
-        allocator.curr_offset = end - allocator.arena.start();
-
-        SpinLock::unlock(guard);
+        self.prev_offset = self.curr_offset;
+        self.curr_offset = end - self.arena.start();
 
         (curr_addr + padding_with_header) as *mut u8
     }
 
-    // layout is unused, since we are not zeroing the memory, all the data will be left there but
-    // overwritten whenever a new allocation occurs
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        let guard = self.lock();
-
-        let mut allocator = guard.get_mut();
-
+    // unless `zeroize-on-free` is enabled the data will be left there but overwritten whenever a
+    // new allocation occurs
+    fn deallocate(&mut self, ptr: *mut u8, layout: Layout) -> Result<(), StackFreeError> {
         let ptr_addr = ptr as usize;
 
         // memory out of bounds
-        if !(allocator.arena.start() <= ptr_addr && ptr_addr < allocator.arena.end()) {
-            return;
+        if !(self.arena.start() <= ptr_addr && ptr_addr < self.arena.end()) {
+            return Err(StackFreeError::OutOfBounds);
         }
 
         // the memory was not allocated yet
-        if ptr_addr >= allocator.arena.start() + allocator.curr_offset {
-            return;
+        if ptr_addr >= self.arena.start() + self.curr_offset {
+            return Err(StackFreeError::NotAllocated);
         }
 
+        unsafe { release_block(ptr, layout.size()) };
+
         let header_addr = (ptr_addr - size_of::<StackHeader>()) as *const StackHeader;
-        let header = unsafe { ptr::read(header_addr) };
+        let header = unsafe { ptr::read_unaligned(header_addr) };
 
-        let prev_offset = ptr_addr - header.padding - allocator.arena.start();
+        let prev_offset = ptr_addr - header.padding() - self.arena.start();
 
-        // out of order stack allocator free, should do something to indicate that
-        if prev_offset != allocator.prev_offset {
-            return;
+        // out of order stack allocator free
+        if prev_offset != self.prev_offset {
+            return self.defer(prev_offset, header.prev_offset());
         }
 
         // reset offsets
-        allocator.curr_offset = allocator.prev_offset;
-        allocator.prev_offset = header.prev_offset;
+        self.curr_offset = self.prev_offset;
+        self.prev_offset = header.prev_offset();
 
-        SpinLock::unlock(guard);
+        self.pop_deferred();
+
+        Ok(())
+    }
+
+    // keeps a block freed below the top to pop it later
+    fn defer(&mut self, offset: usize, prev_offset: usize) -> Result<(), StackFreeError> {
+        if !self.defer_frees {
+            return Err(StackFreeError::OutOfOrder);
+        }
+
+        if self.deferred.contains(&Some((offset, prev_offset))) {
+            // freed twice
+            return Err(StackFreeError::NotAllocated);
+        }
+
+        match self.deferred.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some((offset, prev_offset));
+                Ok(())
+            }
+            None => Err(StackFreeError::OutOfOrder),
+        }
+    }
+
+    // pops the blocks that were already freed off the top of the stack
+    fn pop_deferred(&mut self) {
+        while let Some((offset, prev_offset)) = self
+            .deferred
+            .iter_mut()
+            .find_map(|slot| slot.take_if(|(offset, _)| *offset == self.prev_offset))
+        {
+            self.curr_offset = offset;
+            self.prev_offset = prev_offset;
+        }
+    }
+
+    /// Saves the current state of the allocator, see [`restore`](Self::restore).
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.arena, self.prev_offset, self.curr_offset)
+    }
+
+    /// Releases every block allocated after `snapshot` was taken, whether or not they were
+    /// freed in order.
+    ///
+    /// # Safety
+    ///
+    /// `snapshot` must have been taken from this allocator, and none of the released blocks may
+    /// be used afterwards.
+    pub unsafe fn restore(&mut self, snapshot: Snapshot) {
+        let offsets = snapshot.offsets(self.arena, self.curr_offset);
+        unsafe { self.rewind(offsets) };
+    }
+
+    // whether `ptr` is the most recent allocation, the only one that can be resized in place
+    fn is_top(&self, ptr: *mut u8) -> bool {
+        let ptr_addr = ptr as usize;
+
+        if !(self.arena.start() + self.prev_offset < ptr_addr
+            && ptr_addr < self.arena.start() + self.curr_offset)
+        {
+            return false;
+        }
+
+        let header_addr = (ptr_addr - size_of::<StackHeader>()) as *const StackHeader;
+        let header = unsafe { ptr::read_unaligned(header_addr) };
+
+        ptr_addr - header.padding() - self.arena.start() == self.prev_offset
+    }
+
+    // moves the top of the stack so the top allocation ends `new_size` bytes after `ptr`
+    fn resize_top(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+        let end = match (ptr as usize).checked_add(new_size) {
+            Some(end) if end <= self.arena.end() => end,
+            // the top allocation can't grow any further
+            _ => return false,
+        };
+
+        if new_size < layout.size() {
+            unsafe { release_block(ptr.add(new_size), layout.size() - new_size) };
+        }
+
+        self.curr_offset = end - self.arena.start();
+
+        true
     }
 }
 
-struct StackHeader {
-    prev_offset: usize,
-    padding: usize,
+impl Resettable for StackAllocator {
+    // the previous and current offset
+    type Checkpoint = (usize, usize);
+
+    fn checkpoint(&self) -> (usize, usize) {
+        (self.prev_offset, self.curr_offset)
+    }
+
+    unsafe fn rewind(&mut self, (prev_offset, curr_offset): (usize, usize)) {
+        let start = (self.arena.start() + curr_offset) as *mut u8;
+        unsafe { release_block(start, self.curr_offset - curr_offset) };
+
+        self.prev_offset = prev_offset;
+        self.curr_offset = curr_offset;
+
+        // forget the blocks that were released, the new top may have been freed already
+        for slot in &mut self.deferred {
+            if matches!(slot, Some((offset, _)) if *offset >= curr_offset) {
+                *slot = None;
+            }
+        }
+        self.pop_deferred();
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.allocate(layout)
+    }
+}
+
+impl<R: Relax> SpinLock<StackAllocator, R> {
+    /// Number of frees that couldn't be honored so far.
+    pub fn rejected_frees(&self) -> usize {
+        self.lock_with(|allocator| allocator.rejected_frees)
+    }
+}
+
+impl fmt::Debug for StackAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let deferred = self.deferred.iter().filter(|slot| slot.is_some()).count();
+
+        f.debug_struct("StackAllocator")
+            .field("used", &self.curr_offset)
+            .field("free", &(self.arena.size() - self.curr_offset))
+            .field("top", &self.prev_offset)
+            .field("deferred_frees", &deferred)
+            .field("rejected_frees", &self.rejected_frees)
+            .finish()
+    }
+}
+
+// the offsets and the deferred frees, a pair of words each
+#[cfg(feature = "std")]
+const STATE_WORDS: usize = 2 + 2 * DEFERRED_FREES;
+
+#[cfg(feature = "std")]
+impl SaveState for StackAllocator {
+    fn save_state<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut state = [0; STATE_WORDS];
+        state[0] = self.prev_offset;
+        state[1] = self.curr_offset;
+
+        for (words, slot) in state[2..].chunks_exact_mut(2).zip(&self.deferred) {
+            words[0] = save_state::encode(slot.map(|(offset, _)| offset));
+            words[1] = slot.map_or(0, |(_, prev_offset)| prev_offset);
+        }
+
+        save_arena(writer, self.arena, &state, self.curr_offset)
+    }
+
+    unsafe fn restore_state<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        unsafe { self.rewind((0, 0)) };
+
+        let state: [usize; STATE_WORDS] = unsafe { restore_arena(reader, self.arena, true)? };
+        self.prev_offset = state[0];
+        self.curr_offset = state[1];
+
+        for (words, slot) in state[2..].chunks_exact(2).zip(&mut self.deferred) {
+            *slot = save_state::decode(words[0]).map(|offset| (offset, words[1]));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl<R: Relax> Metered for SpinLock<StackAllocator, R> {
+    fn usage(&self) -> Usage {
+        self.lock_with(|allocator| Usage {
+            used: allocator.curr_offset,
+            capacity: allocator.arena.size(),
+        })
+    }
+}
+
+impl_global_alloc!(StackAllocator, {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self
+            .lock_with_or_timeout(|allocator| allocator.allocate(layout))
+            .unwrap_or(ptr::null_mut());
+
+        #[cfg(feature = "valgrind")]
+        let ptr = valgrind::malloc_like(ptr, layout.size());
+
+        unsafe { init_block(ptr, layout.size()) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        unsafe { zeroed(self.alloc(layout), layout.size()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "valgrind")]
+        valgrind::free_like(ptr);
+
+        // a lock that can't be taken leaks the block, there's no way to fail a free
+        let rejected = self.lock_with_or_timeout(|allocator| {
+            let result = allocator.deallocate(ptr, layout);
+
+            if result.is_err() {
+                allocator.rejected_frees += 1;
+            }
+
+            result.err().zip(allocator.free_error_hook)
+        });
+
+        // the hook runs once the lock is released, so it may use the allocator
+        if let Ok(Some((error, hook))) = rejected {
+            hook(error, ptr);
+        }
+    }
+
+    // the top allocation is grown or shrunk in place, anything else is moved to the top of the
+    // stack, leaving the old block behind as an out of order free
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let resized = self.lock_with_or_timeout(|allocator| {
+            allocator
+                .is_top(ptr)
+                .then(|| allocator.resize_top(ptr, layout, new_size))
+        });
+
+        // the block is left as it is if the lock can't be taken
+        let Ok(resized) = resized else {
+            return ptr::null_mut();
+        };
+
+        match resized {
+            Some(true) => {
+                #[cfg(feature = "valgrind")]
+                valgrind::resize_in_place(ptr, layout.size(), new_size);
+
+                if new_size > layout.size() {
+                    unsafe { init_block(ptr.add(layout.size()), new_size - layout.size()) };
+                }
+                ptr
+            }
+            Some(false) => ptr::null_mut(),
+            None => {
+                let new_layout =
+                    unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+                let new_ptr = unsafe { self.alloc(new_layout) };
+
+                if !new_ptr.is_null() {
+                    unsafe {
+                        ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                        self.dealloc(ptr, layout);
+                    }
+                }
+
+                new_ptr
+            }
+        }
+    }
+});
+
+// the offset of the previous allocation and the padding of this one
+#[derive(Clone, Copy)]
+struct StackHeader(PackedHeader);
+
+// the padding in front of a block covers its header
+const _: () = assert!(size_of::<StackHeader>() <= PackedHeader::MAX_PADDING);
+
+impl StackHeader {
+    fn new(prev_offset: usize, padding: usize) -> Self {
+        Self(PackedHeader::new(prev_offset, padding))
+    }
+
+    fn prev_offset(self) -> usize {
+        self.0.size()
+    }
+
+    fn padding(self) -> usize {
+        self.0.padding()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Arena;
     use core::alloc::Layout;
 
-    static GLOBAL_ALLOC: SpinLock<StackAllocator> = SpinLock::new(StackAllocator::new());
+    static ARENA: Arena = Arena::new();
+    static GLOBAL_ALLOC: SpinLock<StackAllocator> =
+        SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
 
     const HEADER_SIZE: usize = core::mem::size_of::<StackHeader>();
 
@@ -122,16 +480,16 @@ mod tests {
         // successful allocation and alignment
         assert!(!ptr_1.is_null());
         assert!(
-            (ptr_1 as usize - HEADER_SIZE) % layout_u32.align() == 0
-                || ptr_1 as usize % layout_u32.align() == 0
+            (ptr_1 as usize - HEADER_SIZE).is_multiple_of(layout_u32.align())
+                || (ptr_1 as usize).is_multiple_of(layout_u32.align())
         );
 
         let ptr_2 = unsafe { GLOBAL_ALLOC.alloc(layout_u64) };
         // successful allocation and alignment
         assert!(!ptr_2.is_null());
         assert!(
-            (ptr_2 as usize - HEADER_SIZE) % layout_u64.align() == 0
-                || ptr_2 as usize % layout_u64.align() == 0
+            (ptr_2 as usize - HEADER_SIZE).is_multiple_of(layout_u64.align())
+                || (ptr_2 as usize).is_multiple_of(layout_u64.align())
         );
 
         // a pointer to a new location was given
@@ -160,4 +518,322 @@ mod tests {
             SpinLock::unlock(guard);
         }
     }
+
+    #[test]
+    fn test_rejected_frees() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static OUT_OF_ORDER: AtomicUsize = AtomicUsize::new(0);
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> = SpinLock::new(
+            StackAllocator::new(unsafe { ARENA.region() }).with_free_error_hook(|error, _| {
+                if error == StackFreeError::OutOfOrder {
+                    OUT_OF_ORDER.fetch_add(1, Ordering::Relaxed);
+                }
+            }),
+        );
+
+        let layout = Layout::new::<u64>();
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+
+        // freeing the bottom block first is reported, and the lock is released
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        assert_eq!(ALLOC.rejected_frees(), 1);
+        assert_eq!(OUT_OF_ORDER.load(Ordering::Relaxed), 1);
+
+        // a pointer outside of the arena
+        let mut outside = 0u64;
+        unsafe { ALLOC.dealloc(&mut outside as *mut u64 as *mut u8, layout) };
+        assert_eq!(ALLOC.rejected_frees(), 2);
+
+        // freeing in order works
+        unsafe { ALLOC.dealloc(ptr_2, layout) };
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        assert_eq!(ALLOC.rejected_frees(), 2);
+    }
+
+    #[test]
+    fn test_deferred_frees() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }).with_deferred_frees());
+
+        let layout = Layout::new::<u64>();
+        let offset = || ALLOC.lock_with(|allocator| allocator.curr_offset);
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        let ptr_3 = unsafe { ALLOC.alloc(layout) };
+        let top = offset();
+
+        // the blocks below the top wait for it
+        unsafe { ALLOC.dealloc(ptr_2, layout) };
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        assert_eq!(offset(), top);
+        assert_eq!(ALLOC.rejected_frees(), 0);
+
+        // a block can't be freed twice
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        assert_eq!(ALLOC.rejected_frees(), 1);
+
+        // freeing the top pops everything
+        unsafe { ALLOC.dealloc(ptr_3, layout) };
+        assert_eq!(offset(), 0);
+
+        // once every slot is taken out of order frees are rejected again
+        let ptrs: Vec<_> = (0..DEFERRED_FREES + 2)
+            .map(|_| unsafe { ALLOC.alloc(layout) })
+            .collect();
+        for &ptr in &ptrs[..=DEFERRED_FREES] {
+            unsafe { ALLOC.dealloc(ptr, layout) };
+        }
+        assert_eq!(ALLOC.rejected_frees(), 2);
+
+        // the rejected block leaks, the ones above it are popped
+        unsafe { ALLOC.dealloc(ptrs[DEFERRED_FREES + 1], layout) };
+        assert_eq!(
+            offset(),
+            ptrs[DEFERRED_FREES] as usize + layout.size() - unsafe { ARENA.region() }.start()
+        );
+    }
+
+    #[test]
+    fn test_rewind_pops_deferred_frees() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }).with_deferred_frees());
+
+        let layout = Layout::new::<u64>();
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        let snapshot = ALLOC.lock_with(|allocator| allocator.snapshot());
+        unsafe { ALLOC.alloc(layout) };
+
+        // the top of the snapshot was freed in the meantime
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        ALLOC.lock_with(|allocator| unsafe { allocator.restore(snapshot) });
+
+        assert_eq!(ALLOC.lock_with(|allocator| allocator.curr_offset), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "zero-on-alloc")]
+    fn test_zero_on_alloc() {
+        static ARENA: Arena = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let layout = Layout::new::<[u8; 64]>();
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        unsafe { ptr::write_bytes(ptr_1, 0xFF, layout.size()) };
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+
+        // the same block is handed out again, zeroed
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        assert_eq!(ptr_1, ptr_2);
+
+        let block = unsafe { core::slice::from_raw_parts(ptr_2, layout.size()) };
+        assert!(block.iter().all(|&byte| byte == 0x00));
+    }
+
+    #[test]
+    fn test_lock_released_under_contention() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let layout = Layout::new::<[u64; 4]>();
+
+        // interleaved frees are out of order, the arena runs out and pointers from the
+        // outside are thrown in, so every rejection path is taken
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    let mut outside = [0u64; 4];
+                    for _ in 0..1000 {
+                        let ptr = unsafe { ALLOC.alloc(layout) };
+                        unsafe { ALLOC.dealloc(outside.as_mut_ptr() as *mut u8, layout) };
+                        if !ptr.is_null() {
+                            unsafe { ALLOC.dealloc(ptr, layout) };
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(ALLOC.rejected_frees() >= 4000);
+        assert!(ALLOC.try_lock().is_some());
+    }
+
+    #[test]
+    fn test_realloc_in_place() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let layout = Layout::new::<[u8; 16]>();
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        unsafe { ptr::write_bytes(ptr_2, 0xAB, layout.size()) };
+
+        // the top allocation grows and shrinks without moving
+        let grown = unsafe { ALLOC.realloc(ptr_2, layout, 64) };
+        assert_eq!(grown, ptr_2);
+        let offset = ALLOC.lock_with(|allocator| allocator.curr_offset);
+        assert_eq!(
+            offset,
+            ptr_2 as usize + 64 - unsafe { ARENA.region() }.start()
+        );
+
+        let grown_layout = Layout::from_size_align(64, layout.align()).unwrap();
+        let shrunk = unsafe { ALLOC.realloc(grown, grown_layout, 8) };
+        assert_eq!(shrunk, ptr_2);
+        let block = unsafe { core::slice::from_raw_parts(shrunk, 8) };
+        assert!(block.iter().all(|&byte| byte == 0xAB));
+
+        // it can't grow past the end of the arena, the block is left untouched
+        let shrunk_layout = Layout::from_size_align(8, layout.align()).unwrap();
+        assert!(unsafe { ALLOC.realloc(shrunk, shrunk_layout, 2048) }.is_null());
+        assert_eq!(unsafe { *shrunk }, 0xAB);
+
+        // anything below the top is moved, its old block is an out of order free
+        let moved = unsafe { ALLOC.realloc(ptr_1, layout, 32) };
+        assert!(moved > ptr_2);
+        assert_eq!(ALLOC.rejected_frees(), 1);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_alignment_overflowing_header() {
+        static ARENA: Arena<{ 256 * 1024 }> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let align = (PackedHeader::MAX_PADDING + 1) * 2;
+
+        // move the top of the stack just past an `align` boundary
+        let start = unsafe { ARENA.region() }.start() + HEADER_SIZE;
+        let size = (align + 16 - start % align) % align;
+        assert!(!unsafe { ALLOC.alloc(Layout::from_size_align(size, 1).unwrap()) }.is_null());
+
+        // the padding this alignment needs can't be stored in the header
+        let layout = Layout::from_size_align(8, align).unwrap();
+        assert!(unsafe { ALLOC.alloc(layout) }.is_null());
+
+        let layout = Layout::from_size_align(8, 1024).unwrap();
+        assert!(!unsafe { ALLOC.alloc(layout) }.is_null());
+    }
+
+    #[test]
+    fn test_huge_layout() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        // fails whether or not the end of the block overflows
+        let layout = Layout::from_size_align(isize::MAX as usize, 1).unwrap();
+        assert!(unsafe { ALLOC.alloc(layout) }.is_null());
+
+        let layout = Layout::from_size_align(isize::MAX as usize / 2, 4096).unwrap();
+        assert!(unsafe { ALLOC.alloc(layout) }.is_null());
+
+        assert!(!unsafe { ALLOC.alloc(Layout::new::<u64>()) }.is_null());
+    }
+
+    #[test]
+    fn test_overhead_per_alloc() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let layout = Layout::new::<[u64; 7]>();
+        let block_size = layout.size() + StackAllocator::overhead_per_alloc();
+
+        let mut count = 0;
+        while !unsafe { ALLOC.alloc(layout) }.is_null() {
+            count += 1;
+        }
+
+        assert_eq!(count, 1024 / block_size);
+        assert_eq!(StackAllocator::min_block_size(), HEADER_SIZE);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        static ARENA: Arena<1024> = Arena::new();
+        let mut allocator = StackAllocator::new(unsafe { ARENA.region() });
+
+        let layout = Layout::new::<[u64; 4]>();
+        let kept = allocator.allocate(layout);
+
+        let snapshot = allocator.snapshot();
+        let first = allocator.allocate(layout);
+        let second = allocator.allocate(layout);
+
+        // freeing out of order doesn't keep the blocks from being rolled back
+        assert_eq!(
+            allocator.deallocate(first, layout),
+            Err(StackFreeError::OutOfOrder)
+        );
+        unsafe { allocator.restore(snapshot) };
+        assert_eq!(allocator.allocate(layout), first);
+
+        // the block allocated before the snapshot is still the one below, and can be freed
+        assert_eq!(allocator.deallocate(first, layout), Ok(()));
+        assert_eq!(allocator.deallocate(kept, layout), Ok(()));
+        assert_ne!(second, first);
+
+        if cfg!(debug_assertions) {
+            let result = std::panic::catch_unwind(move || unsafe { allocator.restore(snapshot) });
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_save_state() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }).with_deferred_frees());
+
+        let layout = Layout::new::<u64>();
+        let offset = || ALLOC.lock_with(|allocator| allocator.curr_offset);
+
+        let ptr_1 = unsafe { ALLOC.alloc(layout) };
+        let ptr_2 = unsafe { ALLOC.alloc(layout) };
+        let ptr_3 = unsafe { ALLOC.alloc(layout) };
+        unsafe { ptr_3.cast::<u64>().write(0xC0FFEE) };
+        unsafe { ALLOC.dealloc(ptr_2, layout) };
+        let top = offset();
+
+        let mut state = Vec::new();
+        ALLOC
+            .lock_with(|allocator| allocator.save_state(&mut state))
+            .unwrap();
+
+        // everything is freed and overwritten in the meantime
+        unsafe { ALLOC.dealloc(ptr_3, layout) };
+        unsafe { ALLOC.dealloc(ptr_1, layout) };
+        let ptr = unsafe { ALLOC.alloc(Layout::new::<[u64; 4]>()) };
+        unsafe { ptr.write_bytes(0, 32) };
+
+        unsafe { ALLOC.lock_with(|allocator| allocator.restore_state(&state[..])) }.unwrap();
+        assert_eq!(offset(), top);
+        assert_eq!(unsafe { ptr_3.cast::<u64>().read() }, 0xC0FFEE);
+
+        // the deferred free is still waiting for the top
+        unsafe { ALLOC.dealloc(ptr_3, layout) };
+        assert_eq!(
+            offset(),
+            ptr_1 as usize + layout.size() - unsafe { ARENA.region() }.start()
+        );
+    }
 }
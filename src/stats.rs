@@ -0,0 +1,117 @@
+//! Allocation counters for the allocators that don't serialize their calls behind a single lock,
+//! kept in atomics updated with relaxed ordering. Without the `stats` feature they're zero sized
+//! and every update compiles to nothing.
+
+#[cfg(feature = "stats")]
+use super::AllocStats;
+#[cfg(feature = "stats")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug)]
+pub(crate) struct Counters {
+    #[cfg(feature = "stats")]
+    allocs: AtomicUsize,
+    #[cfg(feature = "stats")]
+    deallocs: AtomicUsize,
+    #[cfg(feature = "stats")]
+    failed: AtomicUsize,
+    #[cfg(feature = "stats")]
+    live_bytes: AtomicUsize,
+    #[cfg(feature = "stats")]
+    peak_bytes: AtomicUsize,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "stats")]
+            allocs: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            deallocs: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            failed: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            live_bytes: AtomicUsize::new(0),
+            #[cfg(feature = "stats")]
+            peak_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counts an allocation of `size` bytes that returned `ptr`, null if it failed.
+    #[inline(always)]
+    pub fn allocated(&self, _ptr: *mut u8, _size: usize) {
+        #[cfg(feature = "stats")]
+        {
+            if _ptr.is_null() {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            self.allocs.fetch_add(1, Ordering::Relaxed);
+            let live = self.live_bytes.fetch_add(_size, Ordering::Relaxed) + _size;
+            self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a free of `size` bytes.
+    #[inline(always)]
+    pub fn freed(&self, _size: usize) {
+        #[cfg(feature = "stats")]
+        {
+            self.deallocs.fetch_add(1, Ordering::Relaxed);
+            self.live_bytes.fetch_sub(_size, Ordering::Relaxed);
+        }
+    }
+
+    /// The counters as they are, each of them read on its own, so a sample taken while other
+    /// threads allocate may be off by the calls in flight.
+    #[cfg(feature = "stats")]
+    pub fn snapshot(&self) -> AllocStats {
+        let allocs = self.allocs.load(Ordering::Relaxed);
+        let deallocs = self.deallocs.load(Ordering::Relaxed);
+
+        AllocStats {
+            allocs,
+            deallocs,
+            failed: self.failed.load(Ordering::Relaxed),
+            live_blocks: allocs.saturating_sub(deallocs),
+            live_bytes: self.live_bytes.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(feature = "stats"))]
+    fn test_zero_sized() {
+        assert_eq!(size_of::<Counters>(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "stats")]
+    fn test_counters() {
+        let counters = Counters::new();
+        let mut block = [0u8; 64];
+
+        counters.allocated(block.as_mut_ptr(), 64);
+        counters.allocated(block.as_mut_ptr(), 16);
+        counters.freed(64);
+        counters.allocated(core::ptr::null_mut(), 128);
+
+        assert_eq!(
+            counters.snapshot(),
+            AllocStats {
+                allocs: 2,
+                deallocs: 1,
+                failed: 1,
+                live_blocks: 1,
+                live_bytes: 16,
+                peak_bytes: 80,
+            }
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use super::Region;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::alloc::System;
+
+/// Serves allocations from the inner allocator and falls back to the system allocator once it's
+/// exhausted, so a fast fixed arena can take the hot path without failing hard when it fills up.
+///
+/// Blocks are freed by whichever allocator handed them out, told apart by whether they lie in
+/// `region`, the memory of the inner allocator.
+///
+/// ```ignore
+/// static ARENA: Arena = Arena::new();
+///
+/// #[global_allocator]
+/// static GLOBAL: WithSystemFallback<SpinLock<FreeListAllocator>> = WithSystemFallback::new(
+///     SpinLock::new(FreeListAllocator::new(
+///         unsafe { ARENA.region() },
+///         PlacementPolicy::FindFirst,
+///     )),
+///     unsafe { ARENA.region() },
+/// );
+/// ```
+pub struct WithSystemFallback<A> {
+    inner: A,
+    region: Region,
+    fallbacks: AtomicUsize,
+}
+
+impl<A> WithSystemFallback<A> {
+    pub const fn new(inner: A, region: Region) -> Self {
+        Self {
+            inner,
+            region,
+            fallbacks: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of allocations the system allocator served so far.
+    pub fn fallbacks(&self) -> usize {
+        self.fallbacks.load(Ordering::Relaxed)
+    }
+
+    fn owns(&self, ptr: *mut u8) -> bool {
+        self.region.contains(ptr)
+    }
+
+    unsafe fn fallback(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+
+        match zeroed {
+            true => unsafe { System.alloc_zeroed(layout) },
+            false => unsafe { System.alloc(layout) },
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for WithSystemFallback<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+
+        if ptr.is_null() {
+            return unsafe { self.fallback(layout, false) };
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+
+        if ptr.is_null() {
+            return unsafe { self.fallback(layout, true) };
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.owns(ptr) {
+            true => unsafe { self.inner.dealloc(ptr, layout) },
+            false => unsafe { System.dealloc(ptr, layout) },
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !self.owns(ptr) {
+            return unsafe { System.realloc(ptr, layout, new_size) };
+        }
+
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            return new_ptr;
+        }
+
+        // the arena is full, the block moves over to the system allocator
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.fallback(new_layout, false) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.inner.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock, CACHE_LINE_SIZE};
+
+    #[test]
+    fn test_fallback() {
+        // room for four blocks of `[u64; 6]`, but not for a fifth
+        const SIZE: usize =
+            (4 * (48 + FreeListAllocator::overhead_per_alloc())).next_multiple_of(CACHE_LINE_SIZE);
+        static ARENA: Arena<SIZE> = Arena::new();
+        static ALLOC: WithSystemFallback<SpinLock<FreeListAllocator>> = WithSystemFallback::new(
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { ARENA.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            unsafe { ARENA.region() },
+        );
+
+        let layout = Layout::new::<[u64; 6]>();
+        let blocks = [(); 4].map(|_| unsafe { ALLOC.alloc(layout) });
+        assert!(blocks.iter().all(|&block| ALLOC.owns(block)));
+        assert_eq!(ALLOC.fallbacks(), 0);
+
+        // the arena is full
+        let spilled = unsafe { ALLOC.alloc(layout) };
+        assert!(!spilled.is_null() && !ALLOC.owns(spilled));
+        assert_eq!(ALLOC.fallbacks(), 1);
+
+        // a block freed to the arena is handed out from it again
+        unsafe { ALLOC.dealloc(blocks[0], layout) };
+        assert_eq!(unsafe { ALLOC.alloc(layout) }, blocks[0]);
+
+        // there's no room to grow the block in the arena, so it moves, keeping its contents
+        unsafe { blocks[1].write(7) };
+        let grown = unsafe { ALLOC.realloc(blocks[1], layout, 128) };
+        assert!(!ALLOC.owns(grown));
+        assert_eq!(unsafe { grown.read() }, 7);
+
+        unsafe {
+            ALLOC.dealloc(spilled, layout);
+            ALLOC.dealloc(grown, Layout::from_size_align(128, 8).unwrap());
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Attribution of allocations to tags, see [`Tagged`].
+
+use super::SpinLock;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr;
+
+/// Number of distinct tags a [`Tagged`] allocator keeps count of, including [`UNTAGGED`].
+pub const MAX_TAGS: usize = 32;
+
+/// Tag of the blocks allocated without one, and of those whose tag didn't fit in the table.
+pub const UNTAGGED: &str = "untagged";
+
+/// Wrapper attributing every block to a tag, e.g. the engine subsystem it was allocated for, and
+/// counting the bytes in use per tag.
+///
+/// ```ignore
+/// static HEAP: Tagged<SpinLock<FreeListAllocator>> = Tagged::new(...);
+///
+/// let mesh = HEAP.alloc_tagged(layout, "render");
+///
+/// assert!(HEAP.bytes_in_use("render") <= RENDER_BUDGET);
+/// ```
+///
+/// Blocks allocated through [`GlobalAlloc`] are tagged with the innermost [`scope`] of the
+/// thread (with the `std` feature), or [`UNTAGGED`]. The tag is kept in a word before each
+/// block, so reallocated blocks keep theirs.
+pub struct Tagged<A> {
+    inner: A,
+    tags: SpinLock<TagTable>,
+}
+
+struct TagTable {
+    names: [&'static str; MAX_TAGS],
+    bytes: [usize; MAX_TAGS],
+    len: usize,
+}
+
+// the tag's index in the table, stored right before the pointer handed out
+const HEADER_SIZE: usize = size_of::<usize>();
+
+impl<A> Tagged<A> {
+    pub const fn new(inner: A) -> Self {
+        let mut names = [""; MAX_TAGS];
+        names[0] = UNTAGGED;
+
+        Self {
+            inner,
+            tags: SpinLock::new(TagTable {
+                names,
+                bytes: [0; MAX_TAGS],
+                len: 1,
+            }),
+        }
+    }
+
+    /// Bytes of the blocks tagged with `tag` that are still in use.
+    pub fn bytes_in_use(&self, tag: &str) -> usize {
+        self.tags
+            .lock_with(|table| table.find(tag).map_or(0, |index| table.bytes[index]))
+    }
+
+    /// Calls `f` with every tag seen so far and the bytes in use for it.
+    pub fn for_each_tag(&self, mut f: impl FnMut(&'static str, usize)) {
+        let (names, bytes, len) = self
+            .tags
+            .lock_with(|table| (table.names, table.bytes, table.len));
+
+        // called with the lock released, `f` may allocate
+        for index in 0..len {
+            f(names[index], bytes[index]);
+        }
+    }
+}
+
+impl<A: GlobalAlloc> Tagged<A> {
+    /// Allocates a block attributed to `tag`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`GlobalAlloc::alloc`].
+    pub unsafe fn alloc_tagged(&self, layout: Layout, tag: &'static str) -> *mut u8 {
+        let index = self.tags.lock_with(|table| table.index(tag));
+
+        unsafe { self.allocate(layout, index) }
+    }
+
+    unsafe fn allocate(&self, layout: Layout, index: usize) -> *mut u8 {
+        let Some(block_layout) = block_layout(layout) else {
+            return ptr::null_mut();
+        };
+
+        let block = unsafe { self.inner.alloc(block_layout) };
+        if block.is_null() {
+            return ptr::null_mut();
+        }
+
+        self.tags
+            .lock_with(|table| table.bytes[index] += layout.size());
+
+        let ptr = block as usize + offset(layout.align());
+        unsafe { ptr::write((ptr - HEADER_SIZE) as *mut usize, index) };
+
+        ptr as *mut u8
+    }
+}
+
+impl TagTable {
+    fn find(&self, tag: &str) -> Option<usize> {
+        self.names[..self.len].iter().position(|&name| name == tag)
+    }
+
+    // index of `tag`, adding it to the table if there's room
+    fn index(&mut self, tag: &'static str) -> usize {
+        if let Some(index) = self.find(tag) {
+            return index;
+        }
+
+        if self.len == MAX_TAGS {
+            return 0;
+        }
+
+        self.names[self.len] = tag;
+        self.len += 1;
+        self.len - 1
+    }
+}
+
+// distance between the start of the block and the pointer handed out
+fn offset(align: usize) -> usize {
+    align.max(HEADER_SIZE)
+}
+
+fn block_layout(layout: Layout) -> Option<Layout> {
+    let align = layout.align().max(align_of::<usize>());
+    let size = layout.size().checked_add(offset(layout.align()))?;
+
+    Layout::from_size_align(size, align).ok()
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Tagged<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.alloc_tagged(layout, current_tag()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let index = unsafe { ptr::read((ptr as usize - HEADER_SIZE) as *const usize) };
+
+        self.tags
+            .lock_with(|table| table.bytes[index] -= layout.size());
+
+        let block = (ptr as usize - offset(layout.align())) as *mut u8;
+        // the layout was valid when the block was allocated
+        let block_layout = unsafe { block_layout(layout).unwrap_unchecked() };
+
+        unsafe { self.inner.dealloc(block, block_layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let index = unsafe { ptr::read((ptr as usize - HEADER_SIZE) as *const usize) };
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+
+        // the new block keeps the tag of the old one
+        let new_ptr = unsafe { self.allocate(new_layout, index) };
+        if !new_ptr.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    static SCOPE: core::cell::Cell<&'static str> = const { core::cell::Cell::new(UNTAGGED) };
+}
+
+/// Tags every block the thread allocates through a [`Tagged`] allocator's [`GlobalAlloc`]
+/// implementation with `tag` while `f` runs. Scopes nest, the innermost tag wins.
+#[cfg(feature = "std")]
+pub fn scope<R>(tag: &'static str, f: impl FnOnce() -> R) -> R {
+    let outer = SCOPE.with(|scope| scope.replace(tag));
+
+    // restores the outer tag even if `f` unwinds
+    struct Restore(&'static str);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPE.with(|scope| scope.set(self.0));
+        }
+    }
+    let _restore = Restore(outer);
+
+    f()
+}
+
+fn current_tag() -> &'static str {
+    #[cfg(feature = "std")]
+    {
+        SCOPE.with(|scope| scope.get())
+    }
+
+    #[cfg(not(feature = "std"))]
+    {
+        UNTAGGED
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy};
+
+    #[test]
+    fn test_bytes_per_tag() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: Tagged<SpinLock<FreeListAllocator>> = Tagged::new(SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst),
+        ));
+
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        unsafe {
+            let mesh = ALLOC.alloc_tagged(layout, "render");
+            let voice = ALLOC.alloc_tagged(layout, "audio");
+            let other = ALLOC.alloc(layout);
+            assert_eq!(mesh as usize % 64, 0);
+
+            assert_eq!(ALLOC.bytes_in_use("render"), 100);
+            assert_eq!(ALLOC.bytes_in_use("audio"), 100);
+            assert_eq!(ALLOC.bytes_in_use(UNTAGGED), 100);
+
+            // grown blocks stay with their tag
+            let mesh = ALLOC.realloc(mesh, layout, 300);
+            assert_eq!(ALLOC.bytes_in_use("render"), 300);
+
+            ALLOC.dealloc(voice, layout);
+            ALLOC.dealloc(other, layout);
+            assert_eq!(ALLOC.bytes_in_use("audio"), 0);
+            assert_eq!(ALLOC.bytes_in_use(UNTAGGED), 0);
+
+            let mut tags = Vec::new();
+            ALLOC.for_each_tag(|tag, bytes| tags.push((tag, bytes)));
+            assert_eq!(tags, [(UNTAGGED, 0), ("render", 300), ("audio", 0)]);
+
+            ALLOC.dealloc(mesh, Layout::from_size_align(300, 64).unwrap());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_scope() {
+        static ARENA: Arena<4096> = Arena::new();
+        static ALLOC: Tagged<SpinLock<FreeListAllocator>> = Tagged::new(SpinLock::new(
+            FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst),
+        ));
+
+        let layout = Layout::new::<[u64; 4]>();
+        let (physics, contacts) = scope("physics", || unsafe {
+            let physics = ALLOC.alloc(layout);
+            let contacts = scope("contacts", || ALLOC.alloc(layout));
+            (physics, contacts)
+        });
+        let untagged = unsafe { ALLOC.alloc(layout) };
+
+        assert_eq!(ALLOC.bytes_in_use("physics"), 32);
+        assert_eq!(ALLOC.bytes_in_use("contacts"), 32);
+        assert_eq!(ALLOC.bytes_in_use(UNTAGGED), 32);
+
+        unsafe {
+            ALLOC.dealloc(physics, layout);
+            ALLOC.dealloc(contacts, layout);
+            ALLOC.dealloc(untagged, layout);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Conformance checks for [`GlobalAlloc`] implementations.
+//!
+//! The checks drive a pseudo random workload of allocations, frees and reallocations against an
+//! allocator and panic as soon as a property doesn't hold, so they can be called from a plain
+//! `#[test]`. They work with the allocators of this crate as well as with wrappers stacked on top
+//! of them:
+//!
+//! ```ignore
+//! #[test]
+//! fn conformance() {
+//!     static ARENA: Arena = Arena::new();
+//!     static ALLOC: ValidateLayout<SpinLock<StackAllocator>> =
+//!         ValidateLayout::new(SpinLock::new(StackAllocator::new(unsafe { ARENA.region() })));
+//!
+//!     Conformance::new(512, 64).check_all(&ALLOC);
+//! }
+//! ```
+//!
+//! Running out of memory is never a failure, allocators that can't reuse freed memory (or only
+//! some of it) simply see fewer blocks being allocated.
+
+use core::alloc::{GlobalAlloc, Layout};
+
+// blocks held at the same time by the workload
+const MAX_LIVE: usize = 32;
+
+/// Configures the workload the checks run.
+pub struct Conformance {
+    max_size: usize,
+    max_align: usize,
+    seed: u64,
+    rounds: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Check {
+    Alignment,
+    NonOverlap,
+    Integrity,
+    Realloc,
+}
+
+#[derive(Clone, Copy)]
+struct Block {
+    ptr: *mut u8,
+    layout: Layout,
+    pattern: u8,
+}
+
+impl Conformance {
+    /// Requests blocks of up to `max_size` bytes aligned to up to `max_align`, e.g. the chunk
+    /// size of a pool.
+    pub const fn new(max_size: usize, max_align: usize) -> Self {
+        assert!(max_size > 0, "blocks have to be at least a byte");
+        assert!(
+            max_align.is_power_of_two(),
+            "alignment must be a power of two"
+        );
+
+        Self {
+            max_size,
+            max_align,
+            seed: 0x5EED,
+            rounds: 1000,
+        }
+    }
+
+    /// Seeds the workload, the same seed always runs the same sequence of operations.
+    pub const fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Number of operations each check runs, 1000 by default.
+    pub const fn with_rounds(mut self, rounds: usize) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Runs every check below.
+    pub fn check_all<A: GlobalAlloc>(&self, allocator: &A) {
+        self.check_alignment(allocator);
+        self.check_non_overlap(allocator);
+        self.check_integrity(allocator);
+        self.check_realloc(allocator);
+    }
+
+    /// Every block is aligned as its layout requests.
+    pub fn check_alignment<A: GlobalAlloc>(&self, allocator: &A) {
+        self.workload(allocator, Check::Alignment);
+    }
+
+    /// No block handed out overlaps another live block.
+    pub fn check_non_overlap<A: GlobalAlloc>(&self, allocator: &A) {
+        self.workload(allocator, Check::NonOverlap);
+    }
+
+    /// What's written to a block reads back unchanged until it's freed, neither other blocks nor
+    /// the allocator's metadata overwrite it.
+    pub fn check_integrity<A: GlobalAlloc>(&self, allocator: &A) {
+        self.workload(allocator, Check::Integrity);
+    }
+
+    /// `realloc` preserves the contents of a block up to the smaller of the two sizes, and a
+    /// failed `realloc` leaves the block untouched.
+    pub fn check_realloc<A: GlobalAlloc>(&self, allocator: &A) {
+        self.workload(allocator, Check::Realloc);
+    }
+
+    fn workload<A: GlobalAlloc>(&self, allocator: &A, check: Check) {
+        let mut rng = self.seed;
+        let mut live: [Option<Block>; MAX_LIVE] = [None; MAX_LIVE];
+
+        for _ in 0..self.rounds {
+            let slot = below(&mut rng, MAX_LIVE);
+
+            let Some(block) = live[slot].take() else {
+                live[slot] = self.allocate(allocator, &live, check, &mut rng);
+                continue;
+            };
+
+            if check == Check::Integrity {
+                block.verify(block.layout.size(), "integrity");
+            }
+
+            if check == Check::Realloc && below(&mut rng, 2) == 0 {
+                live[slot] = Some(self.reallocate(allocator, block, &mut rng));
+                continue;
+            }
+
+            unsafe { allocator.dealloc(block.ptr, block.layout) };
+        }
+
+        for block in live.into_iter().flatten() {
+            if check == Check::Integrity {
+                block.verify(block.layout.size(), "integrity");
+            }
+
+            unsafe { allocator.dealloc(block.ptr, block.layout) };
+        }
+    }
+
+    fn allocate<A: GlobalAlloc>(
+        &self,
+        allocator: &A,
+        live: &[Option<Block>],
+        check: Check,
+        rng: &mut u64,
+    ) -> Option<Block> {
+        let layout = self.layout(rng);
+
+        let ptr = unsafe { allocator.alloc(layout) };
+        if ptr.is_null() {
+            return None;
+        }
+
+        let block = Block {
+            ptr,
+            layout,
+            pattern: next(rng) as u8,
+        };
+
+        match check {
+            Check::Alignment => assert!(
+                (ptr as usize).is_multiple_of(layout.align()),
+                "alignment: block at {ptr:p} isn't aligned to {}",
+                layout.align()
+            ),
+            Check::NonOverlap => {
+                for other in live.iter().flatten() {
+                    assert!(
+                        !block.overlaps(other),
+                        "non-overlap: block at {ptr:p} ({} bytes) overlaps the one at {:p} ({} bytes)",
+                        layout.size(),
+                        other.ptr,
+                        other.layout.size()
+                    );
+                }
+            }
+            Check::Integrity | Check::Realloc => {}
+        }
+
+        block.fill();
+        Some(block)
+    }
+
+    fn reallocate<A: GlobalAlloc>(&self, allocator: &A, block: Block, rng: &mut u64) -> Block {
+        let new_size = 1 + below(rng, self.max_size);
+
+        let ptr = unsafe { allocator.realloc(block.ptr, block.layout, new_size) };
+        if ptr.is_null() {
+            block.verify(block.layout.size(), "realloc");
+            return block;
+        }
+
+        let moved = Block {
+            ptr,
+            layout: Layout::from_size_align(new_size, block.layout.align()).unwrap(),
+            pattern: block.pattern,
+        };
+        moved.verify(block.layout.size().min(new_size), "realloc");
+
+        // the grown part is uninitialized, start over with a fresh pattern
+        let block = Block {
+            pattern: next(rng) as u8,
+            ..moved
+        };
+        block.fill();
+        block
+    }
+
+    fn layout(&self, rng: &mut u64) -> Layout {
+        let size = 1 + below(rng, self.max_size);
+        let align = 1 << below(rng, self.max_align.trailing_zeros() as usize + 1);
+
+        Layout::from_size_align(size, align).unwrap()
+    }
+}
+
+impl Block {
+    fn fill(&self) {
+        for i in 0..self.layout.size() {
+            unsafe { self.ptr.add(i).write(self.pattern.wrapping_add(i as u8)) };
+        }
+    }
+
+    fn verify(&self, size: usize, property: &str) {
+        for i in 0..size {
+            let byte = unsafe { self.ptr.add(i).read() };
+            assert!(
+                byte == self.pattern.wrapping_add(i as u8),
+                "{property}: byte {i} of the block at {:p} was overwritten",
+                self.ptr
+            );
+        }
+    }
+
+    fn overlaps(&self, other: &Block) -> bool {
+        let (start, end) = (self.ptr as usize, self.ptr as usize + self.layout.size());
+        let other_start = other.ptr as usize;
+        let other_end = other_start + other.layout.size();
+
+        start < other_end && other_start < end
+    }
+}
+
+// splitmix64, good enough to pick operations and layouts
+fn next(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+    let mut x = *state;
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+fn below(state: &mut u64, bound: usize) -> usize {
+    (next(state) % bound as u64) as usize
+}
+
+#[cfg(all(
+    test,
+    any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )
+))]
+mod tests {
+    use super::*;
+    use crate::{Arena, SpinLock};
+
+    #[test]
+    #[cfg(feature = "linear")]
+    fn test_linear() {
+        use crate::ArenaAllocator;
+
+        static ARENA: Arena = Arena::new();
+        static ALLOC: SpinLock<ArenaAllocator> =
+            SpinLock::new(ArenaAllocator::new(unsafe { ARENA.region() }));
+
+        Conformance::new(256, 64).with_rounds(200).check_all(&ALLOC);
+    }
+
+    #[test]
+    #[cfg(feature = "stack")]
+    fn test_stack() {
+        use crate::StackAllocator;
+
+        static ARENA: Arena = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        Conformance::new(256, 64).check_all(&ALLOC);
+    }
+
+    #[test]
+    #[cfg(feature = "pool")]
+    fn test_pool() {
+        use crate::PoolAllocator;
+
+        static ARENA: Arena = Arena::new();
+        static ALLOC: SpinLock<PoolAllocator> =
+            SpinLock::new(PoolAllocator::new(unsafe { ARENA.region() }, 256));
+
+        // chunks are only aligned as far as the chunk size allows
+        Conformance::new(256, 64).check_all(&ALLOC);
+    }
+
+    #[test]
+    #[cfg(feature = "freelist")]
+    fn test_freelist() {
+        use crate::{FreeListAllocator, PlacementPolicy};
+
+        static FIRST_ARENA: Arena = Arena::new();
+        static FIRST: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { FIRST_ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+        static BEST_ARENA: Arena = Arena::new();
+        static BEST: SpinLock<FreeListAllocator> = SpinLock::new(FreeListAllocator::new(
+            unsafe { BEST_ARENA.region() },
+            PlacementPolicy::FindBest,
+        ));
+
+        Conformance::new(512, 64).check_all(&FIRST);
+        Conformance::new(512, 64).check_all(&BEST);
+    }
+
+    #[test]
+    #[cfg(all(feature = "validate-layout", feature = "freelist"))]
+    fn test_wrapped() {
+        use crate::{FreeListAllocator, PlacementPolicy, ValidateLayout};
+
+        static ARENA: Arena = Arena::new();
+        static ALLOC: ValidateLayout<SpinLock<FreeListAllocator>> =
+            ValidateLayout::new(SpinLock::new(FreeListAllocator::new(
+                unsafe { ARENA.region() },
+                PlacementPolicy::FindFirst,
+            )));
+
+        Conformance::new(512, 64).check_all(&ALLOC);
+    }
+}
@@ -0,0 +1,364 @@
+//! Recording and replaying of allocation traces.
+//!
+//! [`Recorder`] wraps an allocator and writes every successful `alloc`, `dealloc` and `realloc`
+//! to a writer as a compact stream of [`Event`]s. [`replay`] runs such a trace against another
+//! allocator, e.g. to reproduce the fragmentation of a production workload and try fixes against
+//! the exact same sequence of requests.
+//!
+//! ```ignore
+//! static ARENA: Arena = Arena::new();
+//!
+//! #[global_allocator]
+//! static GLOBAL: Recorder<SpinLock<FreeListAllocator>, File> = ...;
+//!
+//! // later on, somewhere else
+//! let stats = trace::replay(&allocator, BufReader::new(File::open("alloc.trace")?))?;
+//! ```
+
+use super::SpinLock;
+use core::alloc::{GlobalAlloc, Layout};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+const ALLOC: u8 = 0;
+const DEALLOC: u8 = 1;
+const REALLOC: u8 = 2;
+
+// a tag and up to three LEB128 encoded words
+const MAX_EVENT_SIZE: usize = 1 + 3 * 10;
+
+/// A single call to the allocator, blocks are identified by their address in the recorded run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Alloc {
+        ptr: usize,
+        size: usize,
+        align: usize,
+    },
+    Dealloc {
+        ptr: usize,
+    },
+    Realloc {
+        ptr: usize,
+        new_ptr: usize,
+        new_size: usize,
+    },
+}
+
+impl Event {
+    /// Writes the event in its binary form, a tag byte followed by LEB128 encoded words.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut buf = [0u8; MAX_EVENT_SIZE];
+        let mut len = 0;
+
+        let (tag, words) = match *self {
+            Event::Alloc { ptr, size, align } => (ALLOC, [ptr, size, align]),
+            Event::Dealloc { ptr } => (DEALLOC, [ptr, 0, 0]),
+            Event::Realloc {
+                ptr,
+                new_ptr,
+                new_size,
+            } => (REALLOC, [ptr, new_ptr, new_size]),
+        };
+
+        buf[len] = tag;
+        len += 1;
+
+        let word_count = if tag == DEALLOC { 1 } else { 3 };
+        for mut word in words.into_iter().take(word_count) {
+            loop {
+                let byte = (word & 0x7F) as u8;
+                word >>= 7;
+
+                if word == 0 {
+                    buf[len] = byte;
+                    len += 1;
+                    break;
+                }
+
+                buf[len] = byte | 0x80;
+                len += 1;
+            }
+        }
+
+        // a single write, so events of different threads don't interleave
+        writer.write_all(&buf[..len])
+    }
+
+    /// Reads the next event, `None` once the trace is over.
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Option<Event>> {
+        let mut tag = [0u8];
+        if reader.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+
+        let event = match tag[0] {
+            ALLOC => Event::Alloc {
+                ptr: read_word(reader)?,
+                size: read_word(reader)?,
+                align: read_word(reader)?,
+            },
+            DEALLOC => Event::Dealloc {
+                ptr: read_word(reader)?,
+            },
+            REALLOC => Event::Realloc {
+                ptr: read_word(reader)?,
+                new_ptr: read_word(reader)?,
+                new_size: read_word(reader)?,
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown event")),
+        };
+
+        Ok(Some(event))
+    }
+}
+
+fn read_word<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut word = 0usize;
+
+    for shift in (0..usize::BITS).step_by(7) {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+
+        word |= ((byte[0] & 0x7F) as usize) << shift;
+
+        if byte[0] & 0x80 == 0 {
+            return Ok(word);
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::InvalidData, "word overflows"))
+}
+
+/// Wraps an allocator, writing every call that succeeded to `writer`.
+///
+/// Failing to write doesn't affect the allocations, the event is dropped. When recording the
+/// global allocator the writer must not allocate itself (a `File` doesn't, a `BufWriter` does).
+pub struct Recorder<A, W> {
+    inner: A,
+    writer: SpinLock<W>,
+}
+
+impl<A, W> Recorder<A, W> {
+    pub const fn new(inner: A, writer: W) -> Self {
+        Self {
+            inner,
+            writer: SpinLock::new(writer),
+        }
+    }
+
+    fn record(&self, event: Event)
+    where
+        W: Write,
+    {
+        let _ = self
+            .writer
+            .lock_with(|writer| event.write_to(writer).and_then(|_| writer.flush()));
+    }
+}
+
+unsafe impl<A: GlobalAlloc, W: Write> GlobalAlloc for Recorder<A, W> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+
+        if !ptr.is_null() {
+            self.record(Event::Alloc {
+                ptr: ptr as usize,
+                size: layout.size(),
+                align: layout.align(),
+            });
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+
+        if !ptr.is_null() {
+            self.record(Event::Alloc {
+                ptr: ptr as usize,
+                size: layout.size(),
+                align: layout.align(),
+            });
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // recorded first, once the block is freed its address may be handed out again
+        self.record(Event::Dealloc { ptr: ptr as usize });
+
+        unsafe { self.inner.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+
+        // a failed realloc leaves the block as it was
+        if !new_ptr.is_null() {
+            self.record(Event::Realloc {
+                ptr: ptr as usize,
+                new_ptr: new_ptr as usize,
+                new_size,
+            });
+        }
+        new_ptr
+    }
+}
+
+/// Outcome of a [`replay`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ReplayStats {
+    /// Events read from the trace.
+    pub events: usize,
+    /// Allocations (and reallocations) that succeeded when recorded but failed on replay.
+    pub failed: usize,
+    /// Most bytes held at once.
+    pub peak_bytes: usize,
+}
+
+/// Runs the trace read from `trace` against `allocator`.
+///
+/// Blocks that are still live at the end of the trace are left allocated. Blocks whose
+/// allocation failed on replay are skipped by the events that follow.
+pub fn replay<A: GlobalAlloc, R: Read>(allocator: &A, mut trace: R) -> io::Result<ReplayStats> {
+    let mut stats = ReplayStats::default();
+
+    // blocks by their address in the recorded run
+    let mut live: HashMap<usize, (*mut u8, Layout)> = HashMap::new();
+    let mut live_bytes = 0;
+
+    while let Some(event) = Event::read_from(&mut trace)? {
+        stats.events += 1;
+
+        match event {
+            Event::Alloc { ptr, size, align } => {
+                let layout = Layout::from_size_align(size, align)
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid layout"))?;
+
+                let block = unsafe { allocator.alloc(layout) };
+                if block.is_null() {
+                    stats.failed += 1;
+                    continue;
+                }
+
+                live.insert(ptr, (block, layout));
+                live_bytes += size;
+            }
+            Event::Dealloc { ptr } => {
+                if let Some((block, layout)) = live.remove(&ptr) {
+                    unsafe { allocator.dealloc(block, layout) };
+                    live_bytes -= layout.size();
+                }
+            }
+            Event::Realloc {
+                ptr,
+                new_ptr,
+                new_size,
+            } => {
+                let Some(&(block, layout)) = live.get(&ptr) else {
+                    continue;
+                };
+
+                let moved = unsafe { allocator.realloc(block, layout, new_size) };
+                if moved.is_null() {
+                    stats.failed += 1;
+                    continue;
+                }
+
+                live.remove(&ptr);
+                let new_layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+                live.insert(new_ptr, (moved, new_layout));
+                live_bytes = live_bytes - layout.size() + new_size;
+            }
+        }
+
+        stats.peak_bytes = stats.peak_bytes.max(live_bytes);
+    }
+
+    Ok(stats)
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy};
+
+    #[test]
+    fn test_event_round_trip() {
+        let events = [
+            Event::Alloc {
+                ptr: usize::MAX,
+                size: 0,
+                align: 4096,
+            },
+            Event::Dealloc { ptr: 0x1234 },
+            Event::Realloc {
+                ptr: 1,
+                new_ptr: 1 << 40,
+                new_size: 127,
+            },
+        ];
+
+        let mut trace = Vec::new();
+        for event in events {
+            event.write_to(&mut trace).unwrap();
+        }
+
+        let mut reader = trace.as_slice();
+        for event in events {
+            assert_eq!(Event::read_from(&mut reader).unwrap(), Some(event));
+        }
+        assert_eq!(Event::read_from(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_record_replay() {
+        static ARENA: Arena<4096> = Arena::new();
+        static REPLAY_ARENA: Arena<4096> = Arena::new();
+        static SMALL_ARENA: Arena<512> = Arena::new();
+
+        let recorder = Recorder::new(
+            SpinLock::new(FreeListAllocator::new(
+                unsafe { ARENA.region() },
+                PlacementPolicy::FindFirst,
+            )),
+            Vec::new(),
+        );
+
+        let small = Layout::new::<[u64; 8]>();
+        let large = Layout::new::<[u64; 64]>();
+        unsafe {
+            let a = recorder.alloc(small);
+            let b = recorder.alloc(large);
+            recorder.dealloc(a, small);
+            let b = recorder.realloc(b, large, 1024);
+            recorder.dealloc(b, Layout::from_size_align(1024, 8).unwrap());
+        }
+
+        let trace = recorder.writer.lock_with(|writer| writer.clone());
+
+        let allocator = SpinLock::new(FreeListAllocator::new(
+            unsafe { REPLAY_ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+        let stats = replay(&allocator, trace.as_slice()).unwrap();
+        assert_eq!(
+            stats,
+            ReplayStats {
+                events: 5,
+                failed: 0,
+                peak_bytes: 1024,
+            }
+        );
+
+        // the same workload doesn't fit in a smaller arena
+        let allocator = SpinLock::new(FreeListAllocator::new(
+            unsafe { SMALL_ARENA.region() },
+            PlacementPolicy::FindFirst,
+        ));
+        let stats = replay(&allocator, trace.as_slice()).unwrap();
+        assert_eq!(stats.failed, 1);
+    }
+}
@@ -0,0 +1,154 @@
+use super::AllocError;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+/// Allocation of uninitialized values without pointer casts, available on every [`GlobalAlloc`]
+/// and meant for the arena and stack allocators, whose blocks are released all at once.
+///
+/// The memory is never freed on its own, give it back with [`GlobalAlloc::dealloc`] using the
+/// layout of `T` (or of the slice) if the allocator supports it.
+pub trait AllocUninit: GlobalAlloc {
+    /// Allocates room for a `T`, returns `None` if the allocator is out of memory.
+    // the value points into the allocator's memory, not into `self`
+    #[allow(clippy::mut_from_ref)]
+    fn alloc_uninit<T>(&self) -> Option<&mut MaybeUninit<T>> {
+        let ptr = unsafe { alloc_raw::<MaybeUninit<T>, _>(self, Layout::new::<T>()) }?;
+
+        // SAFETY: the memory is valid for a `T` and `MaybeUninit` doesn't need it initialized
+        Some(unsafe { &mut *ptr })
+    }
+
+    /// Allocates room for `len` values of `T`, returns `None` if the allocator is out of memory.
+    #[allow(clippy::mut_from_ref)]
+    fn alloc_uninit_slice<T>(&self, len: usize) -> Option<&mut [MaybeUninit<T>]> {
+        let layout = Layout::array::<T>(len).ok()?;
+        let ptr = unsafe { alloc_raw::<MaybeUninit<T>, _>(self, layout) }?;
+
+        Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    /// Allocates room for a header `H` followed right away by `len` values of `T`, the layout of
+    /// a struct ending in a flexible array member in C, e.g. a packet and its payload. The block
+    /// is freed with the layout from [`header_with_slice_layout`].
+    #[allow(clippy::mut_from_ref, clippy::type_complexity)]
+    fn alloc_header_with_slice<H, T>(
+        &self,
+        len: usize,
+    ) -> Result<(&mut MaybeUninit<H>, &mut [MaybeUninit<T>]), AllocError> {
+        let (layout, slice_offset) =
+            header_with_slice_layout::<H, T>(len).ok_or(AllocError::OutOfMemory)?;
+
+        let block = if layout.size() == 0 {
+            // dangling, but aligned for both parts
+            layout.align() as *mut u8
+        } else {
+            unsafe { alloc_raw::<u8, _>(self, layout) }.ok_or(AllocError::OutOfMemory)?
+        };
+
+        // SAFETY: both parts are in the block, aligned, and don't overlap
+        unsafe {
+            let header = &mut *(block as *mut MaybeUninit<H>);
+            let slice = block.add(slice_offset) as *mut MaybeUninit<T>;
+
+            Ok((header, core::slice::from_raw_parts_mut(slice, len)))
+        }
+    }
+}
+
+impl<A: GlobalAlloc> AllocUninit for A {}
+
+/// The layout of a header `H` followed by `len` values of `T`, and the offset the values start
+/// at, `None` if it overflows. Padding is added after the values so the layout can be freed
+/// like any other (and used in an array).
+pub fn header_with_slice_layout<H, T>(len: usize) -> Option<(Layout, usize)> {
+    let (layout, offset) = Layout::new::<H>()
+        .extend(Layout::array::<T>(len).ok()?)
+        .ok()?;
+
+    Some((layout.pad_to_align(), offset))
+}
+
+// zero sized requests can't go through the allocator, they get a dangling pointer instead
+unsafe fn alloc_raw<T, A: GlobalAlloc + ?Sized>(allocator: &A, layout: Layout) -> Option<*mut T> {
+    if layout.size() == 0 {
+        return Some(NonNull::<T>::dangling().as_ptr());
+    }
+
+    let ptr = unsafe { allocator.alloc(layout) } as *mut T;
+    (!ptr.is_null()).then_some(ptr)
+}
+
+#[cfg(all(test, feature = "stack"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, SpinLock, StackAllocator};
+
+    #[test]
+    fn test_alloc_uninit() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let value = ALLOC.alloc_uninit::<u64>().unwrap();
+        assert_eq!(*value.write(42), 42);
+        assert!((value.as_ptr() as usize).is_multiple_of(align_of::<u64>()));
+
+        let slice = ALLOC.alloc_uninit_slice::<u32>(16).unwrap();
+        assert_eq!(slice.len(), 16);
+        for (i, item) in slice.iter_mut().enumerate() {
+            item.write(i as u32);
+        }
+
+        let empty = ALLOC.alloc_uninit_slice::<u64>(0).unwrap();
+        assert!(empty.is_empty());
+        assert!(ALLOC.alloc_uninit::<()>().is_some());
+
+        // more than the arena holds
+        assert!(ALLOC.alloc_uninit_slice::<u64>(1024).is_none());
+        assert!(ALLOC.alloc_uninit::<[u8; 2048]>().is_none());
+    }
+
+    #[test]
+    fn test_alloc_header_with_slice() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        #[repr(C)]
+        struct Packet {
+            kind: u8,
+            len: u16,
+        }
+
+        let (header, payload) = ALLOC.alloc_header_with_slice::<Packet, u64>(4).unwrap();
+        header.write(Packet { kind: 1, len: 4 });
+        for (i, item) in payload.iter_mut().enumerate() {
+            item.write(i as u64);
+        }
+
+        // the payload starts at the first offset aligned for it, right after the header
+        let header_addr = header.as_ptr() as usize;
+        assert_eq!(payload.as_ptr() as usize, header_addr + 8);
+        assert_eq!(payload.len(), 4);
+
+        let (layout, offset) = header_with_slice_layout::<Packet, u64>(4).unwrap();
+        assert_eq!((layout.size(), layout.align(), offset), (40, 8, 8));
+
+        // an empty payload, and zero sized parts
+        assert!(ALLOC
+            .alloc_header_with_slice::<Packet, u64>(0)
+            .unwrap()
+            .1
+            .is_empty());
+        assert!(ALLOC.alloc_header_with_slice::<(), ()>(8).is_ok());
+
+        assert_eq!(
+            ALLOC
+                .alloc_header_with_slice::<Packet, u64>(1024)
+                .unwrap_err(),
+            AllocError::OutOfMemory
+        );
+        assert!(header_with_slice_layout::<Packet, u64>(usize::MAX).is_none());
+    }
+}
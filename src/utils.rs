@@ -1,71 +1,398 @@
-pub fn is_power_of_two(x: usize) -> bool {
-    (x & (x - 1)) == 0
+/// `addr` rounded up to a multiple of `alignment`, `None` if that's past the address space or
+/// `alignment` isn't a power of two (zero included).
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+pub fn align_forward(addr: usize, alignment: usize) -> Option<usize> {
+    if !alignment.is_power_of_two() {
+        return None;
+    }
+
+    // the low bits are masked off after going past the next multiple
+    addr.checked_add(alignment - 1)
+        .map(|addr| addr & !(alignment - 1))
+}
+
+/// Bytes between `ptr` and the first address past a header of `header_size` bytes that is
+/// aligned to `alignment`, `None` if that's past the address space.
+#[cfg(any(feature = "stack", feature = "freelist"))]
+pub fn calc_padding_with_header(ptr: usize, alignment: usize, header_size: usize) -> Option<usize> {
+    let data = align_forward(ptr.checked_add(header_size)?, alignment)?;
+    Some(data - ptr)
 }
 
-pub fn align_forward(mut addr: usize, alignment: usize) -> usize {
-    assert!(is_power_of_two(alignment));
+/// Words an allocation header takes up. On 64-bit targets the size and the padding share a word,
+/// narrower ones keep them in a word each: a quarter of 32 bits would cap the padding, and with
+/// it the alignment, below what DMA buffers and vector tables need.
+#[cfg(any(feature = "stack", feature = "freelist"))]
+pub const HEADER_WORDS: usize = if usize::BITS >= 64 { 1 } else { 2 };
 
-    // Same as (addr % alignment) but faster as 'alignment' is a power of two
-    let modulo = addr & (alignment - 1);
+/// The allocation header of the target, see [`PackedHeaderOf`].
+#[cfg(any(feature = "stack", feature = "freelist"))]
+pub type PackedHeader = PackedHeaderOf<HEADER_WORDS>;
 
-    if modulo != 0 {
-        addr += alignment - modulo;
-    }
+/// A size (or offset) and a padding, packed into a single word when `WORDS` is 1 so the
+/// allocation headers take up one word instead of two. The padding takes the low quarter of the
+/// bits, which leaves 64 KB of padding and 256 TB of size on 64-bit targets. With two words
+/// neither is limited.
+#[cfg(any(feature = "stack", feature = "freelist"))]
+#[derive(Clone, Copy)]
+pub struct PackedHeaderOf<const WORDS: usize>([usize; WORDS]);
 
-    addr
-}
+#[cfg(any(feature = "stack", feature = "freelist"))]
+impl<const WORDS: usize> PackedHeaderOf<WORDS> {
+    const PADDING_BITS: u32 = usize::BITS / 4;
 
-pub fn calc_padding_with_header(ptr: usize, alignment: usize, header_size: usize) -> usize {
-    assert!(is_power_of_two(alignment));
+    /// Largest padding a header can hold, allocators fail requests that would need more.
+    pub const MAX_PADDING: usize = if WORDS == 1 {
+        (1 << Self::PADDING_BITS) - 1
+    } else {
+        usize::MAX
+    };
 
-    // (ptr % alignment) as 'alignment' is a power of two
-    let modulo = ptr & (alignment - 1);
+    /// Largest size (or offset) a header can hold, allocators fail requests that would need more.
+    pub const MAX_SIZE: usize = if WORDS == 1 {
+        usize::MAX >> Self::PADDING_BITS
+    } else {
+        usize::MAX
+    };
 
-    let mut padding = 0;
+    pub fn new(size: usize, padding: usize) -> Self {
+        const { assert!(WORDS == 1 || WORDS == 2, "a header is one or two words") };
+        debug_assert!(padding <= Self::MAX_PADDING, "padding overflows the header");
+        debug_assert!(size <= Self::MAX_SIZE, "size overflows the header");
 
-    if modulo != 0 {
-        padding = alignment - modulo;
+        let mut words = [0; WORDS];
+        if WORDS == 1 {
+            words[0] = (size << Self::PADDING_BITS) | padding;
+        } else {
+            words[0] = size;
+            words[WORDS - 1] = padding;
+        }
+        Self(words)
     }
 
-    // the header is smaller than the padding we're done
-    // else align the left over (header - padding)
-    if padding < header_size {
-        let diff = header_size - padding;
+    pub fn size(self) -> usize {
+        if WORDS == 1 {
+            self.0[0] >> Self::PADDING_BITS
+        } else {
+            self.0[0]
+        }
+    }
 
-        if (diff & (alignment - 1)) != 0 {
-            padding += alignment * (1 + diff / alignment);
+    pub fn padding(self) -> usize {
+        if WORDS == 1 {
+            self.0[0] & Self::MAX_PADDING
         } else {
-            padding = header_size;
+            self.0[WORDS - 1]
         }
     }
+}
+
+/// Prepares a block that is about to be handed out, zeroing it if `zero-on-alloc` is enabled.
+/// Null pointers (failed allocations) are passed through.
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+#[inline(always)]
+pub unsafe fn init_block(ptr: *mut u8, _size: usize) -> *mut u8 {
+    if !ptr.is_null() {
+        unsafe { unpoison(ptr, _size) };
+    }
+
+    #[cfg(feature = "zero-on-alloc")]
+    if !ptr.is_null() {
+        unsafe { zero_block(ptr, _size) };
+    }
 
-    padding
+    ptr
 }
 
-pub fn ref_as_usize<T>(var_ref: &T) -> usize {
-    var_ref as *const T as usize
+/// Releases a block that was just freed, scrubbing it if `zeroize-on-free` is enabled and
+/// poisoning it for AddressSanitizer with `asan` or memcheck with `valgrind`.
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+#[inline(always)]
+pub unsafe fn release_block(_ptr: *mut u8, _size: usize) {
+    #[cfg(feature = "zeroize-on-free")]
+    {
+        // a block leaving the quarantine is already poisoned
+        unsafe { unpoison(_ptr, _size) };
+
+        // volatile writes so the scrubbing isn't optimized away as a dead store
+        for i in 0.._size {
+            unsafe { core::ptr::write_volatile(_ptr.add(i), 0x00) };
+        }
+        core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+    }
+
+    unsafe { poison(_ptr, _size) };
+}
+
+#[cfg(feature = "asan")]
+extern "C" {
+    fn __asan_poison_memory_region(addr: *const u8, size: usize);
+    fn __asan_unpoison_memory_region(addr: *const u8, size: usize);
+}
+
+/// Marks `size` bytes at `ptr` as off limits to AddressSanitizer with `asan` and to memcheck with
+/// `valgrind`, any access reports a use after free until they're unpoisoned.
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+#[inline(always)]
+pub unsafe fn poison(_ptr: *const u8, _size: usize) {
+    #[cfg(feature = "asan")]
+    unsafe {
+        __asan_poison_memory_region(_ptr, _size)
+    };
+
+    #[cfg(feature = "valgrind")]
+    crate::valgrind::make_mem_noaccess(_ptr, _size);
+}
+
+/// Makes `size` bytes at `ptr` accessible again with `asan` or `valgrind`, for blocks handed out
+/// and for the metadata allocators write into freed blocks.
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+#[inline(always)]
+pub unsafe fn unpoison(_ptr: *const u8, _size: usize) {
+    #[cfg(feature = "asan")]
+    unsafe {
+        __asan_unpoison_memory_region(_ptr, _size)
+    };
+
+    // accessible, but nothing written yet
+    #[cfg(feature = "valgrind")]
+    crate::valgrind::make_mem_undefined(_ptr, _size);
+}
+
+/// Zeroes `size` bytes starting at `ptr`, a word at a time where possible.
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+pub unsafe fn zero_block(ptr: *mut u8, size: usize) {
+    const WORD: usize = core::mem::size_of::<usize>();
+
+    let mut addr = ptr as usize;
+    let end = addr + size;
+
+    // bytes before the first word boundary
+    while addr < end && addr & (WORD - 1) != 0 {
+        unsafe { core::ptr::write(addr as *mut u8, 0x00) };
+        addr += 1;
+    }
+
+    while addr + WORD <= end {
+        unsafe { core::ptr::write(addr as *mut usize, 0) };
+        addr += WORD;
+    }
+
+    // bytes after the last word boundary
+    while addr < end {
+        unsafe { core::ptr::write(addr as *mut u8, 0x00) };
+        addr += 1;
+    }
+}
+
+/// Zeroes a block returned by `alloc`, used to implement `GlobalAlloc::alloc_zeroed`.
+#[cfg(any(
+    feature = "linear",
+    feature = "stack",
+    feature = "pool",
+    feature = "freelist"
+))]
+pub unsafe fn zeroed(ptr: *mut u8, size: usize) -> *mut u8 {
+    // with `zero-on-alloc` the block was already zeroed when handed out
+    if !ptr.is_null() && !cfg!(feature = "zero-on-alloc") {
+        unsafe { zero_block(ptr, size) };
+    }
+
+    ptr
 }
 
-#[cfg(test)]
+#[cfg(all(
+    test,
+    any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )
+))]
 mod test {
     use super::*;
 
     #[test]
+    #[cfg(any(feature = "linear", feature = "freelist"))]
     fn test_align_forward() {
-        assert_eq!(align_forward(10, 4), 12);
-        assert_eq!(align_forward(20, 8), 24);
-        assert_eq!(align_forward(100, 32), 128);
+        assert_eq!(align_forward(10, 4), Some(12));
+        assert_eq!(align_forward(20, 8), Some(24));
+        assert_eq!(align_forward(100, 32), Some(128));
+
+        // at the top of the address space
+        assert_eq!(align_forward(usize::MAX - 7, 8), Some(usize::MAX - 7));
+        assert_eq!(align_forward(usize::MAX - 2, 8), None);
     }
 
     #[test]
-    #[should_panic]
+    #[cfg(any(feature = "linear", feature = "freelist"))]
     fn test_align_forward_with_non_power_of_two() {
-        align_forward(10, 5);
+        assert_eq!(align_forward(10, 5), None);
+        assert_eq!(align_forward(10, 0), None);
     }
 
     #[test]
+    #[cfg(any(feature = "stack", feature = "freelist"))]
     fn test_calc_padding_with_header() {
-        assert_eq!(calc_padding_with_header(3, 8, 8), 13);
-        assert_eq!(calc_padding_with_header(3, 8, 29), 29);
+        assert_eq!(calc_padding_with_header(3, 8, 8), Some(13));
+        assert_eq!(calc_padding_with_header(3, 8, 29), Some(29));
+
+        // the header or the data would be past the address space
+        assert_eq!(calc_padding_with_header(usize::MAX - 4, 1, 8), None);
+        assert_eq!(calc_padding_with_header(usize::MAX - 12, 16, 8), None);
+    }
+
+    #[test]
+    fn test_zero_block() {
+        let mut block = [0xFFu8; 37];
+
+        // start at an unaligned address so every path is taken
+        unsafe { zero_block(block.as_mut_ptr().add(1), 34) };
+
+        assert_eq!(block[0], 0xFF);
+        assert!(block[1..35].iter().all(|&byte| byte == 0x00));
+        assert_eq!(block[35..], [0xFF, 0xFF]);
+    }
+
+    #[test]
+    #[cfg(feature = "asan")]
+    fn test_poison_released_blocks() {
+        extern "C" {
+            fn __asan_address_is_poisoned(addr: *const u8) -> i32;
+        }
+        let is_poisoned = |ptr: *const u8| unsafe { __asan_address_is_poisoned(ptr) } != 0;
+
+        let mut block = [0u64; 4];
+        let ptr = block.as_mut_ptr() as *mut u8;
+
+        unsafe { release_block(ptr, 32) };
+        assert!((0..32).all(|i| is_poisoned(ptr.wrapping_add(i))));
+
+        unsafe { init_block(ptr, 16) };
+        assert!((0..16).all(|i| !is_poisoned(ptr.wrapping_add(i))));
+        assert!(is_poisoned(ptr.wrapping_add(16)));
+
+        // the stack frame has to be usable again once the test returns
+        unsafe { unpoison(ptr, 32) };
+    }
+
+    #[test]
+    #[cfg(any(feature = "stack", feature = "freelist"))]
+    fn test_packed_header() {
+        let header = PackedHeader::new(128 * 1024, PackedHeader::MAX_PADDING);
+
+        assert_eq!(
+            core::mem::size_of::<PackedHeader>(),
+            core::mem::size_of::<usize>()
+        );
+        assert_eq!(header.size(), 128 * 1024);
+        assert_eq!(header.padding(), PackedHeader::MAX_PADDING);
+    }
+
+    #[test]
+    #[cfg(any(feature = "stack", feature = "freelist"))]
+    fn test_two_word_header() {
+        // the header of a 32-bit target, its padding doesn't fit in a quarter of 32 bits once
+        // blocks are aligned to 256 bytes or more
+        let header_size = 2 * size_of::<u32>();
+        for align in [256, 512, 4096] {
+            // the header runs just past an aligned address, so the data goes one further
+            let padding = calc_padding_with_header(align - 4, align, header_size).unwrap();
+            assert_eq!(padding, align + 4);
+            assert!(padding > (1 << (u32::BITS / 4)) - 1);
+
+            let header = PackedHeaderOf::<2>::new(64 * 1024 * 1024, padding);
+            assert_eq!(
+                (header.size(), header.padding()),
+                (64 * 1024 * 1024, padding)
+            );
+        }
+    }
+}
+
+// proofs of the padding math for every address and alignment, run with `cargo kani`
+#[cfg(all(kani, feature = "freelist"))]
+mod proofs {
+    use super::*;
+
+    // a symbolic power of two up to 4096
+    fn any_alignment() -> usize {
+        let shift: u32 = kani::any();
+        kani::assume(shift <= 12);
+        1 << shift
+    }
+
+    #[kani::proof]
+    fn align_forward_is_aligned_and_minimal() {
+        let addr: usize = kani::any();
+        let alignment = any_alignment();
+
+        // fails exactly when the next multiple is past the address space
+        let Some(aligned) = align_forward(addr, alignment) else {
+            assert!(addr.checked_add(alignment - 1).is_none());
+            return;
+        };
+
+        assert!(aligned.is_multiple_of(alignment));
+        assert!(aligned >= addr);
+        assert!(aligned - addr < alignment);
+    }
+
+    #[kani::proof]
+    fn align_forward_rejects_other_alignments() {
+        let alignment: usize = kani::any();
+        kani::assume(!alignment.is_power_of_two());
+
+        assert!(align_forward(kani::any(), alignment).is_none());
+    }
+
+    #[kani::proof]
+    fn padding_fits_the_header_and_aligns() {
+        let ptr: usize = kani::any();
+        let alignment = any_alignment();
+        let header_size: usize = kani::any();
+        kani::assume(header_size <= 64);
+
+        let Some(padding) = calc_padding_with_header(ptr, alignment, header_size) else {
+            let end = ptr.checked_add(header_size);
+            assert!(end.and_then(|end| end.checked_add(alignment - 1)).is_none());
+            return;
+        };
+
+        assert!((ptr + padding).is_multiple_of(alignment));
+        assert!(padding >= header_size);
+        // no more than a single alignment's worth is wasted in front of the header
+        assert!(padding - header_size < alignment);
     }
 }
@@ -0,0 +1,148 @@
+//! Valgrind client requests, so memcheck tracks the blocks handed out from an arena as heap
+//! blocks of their own instead of one defined blob.
+//!
+//! Allocations are reported with `VALGRIND_MALLOCLIKE_BLOCK` and frees with
+//! `VALGRIND_FREELIKE_BLOCK`, which gets use after free, double free, leak and uninitialized read
+//! reports with the stack trace of the allocation. Freed memory is marked as inaccessible and the
+//! allocators make their own bookkeeping in it accessible again before writing it.
+//!
+//! A request is a few instructions that do nothing outside valgrind, so the feature costs little
+//! in a regular run. On architectures other than x86_64 and aarch64 it does nothing at all.
+
+// only the allocators report their blocks
+#![cfg_attr(
+    not(any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )),
+    allow(dead_code)
+)]
+
+// the core requests, see `valgrind.h`
+const MALLOCLIKE_BLOCK: usize = 0x1301;
+const FREELIKE_BLOCK: usize = 0x1302;
+#[cfg(feature = "stack")]
+const RESIZEINPLACE_BLOCK: usize = 0x130b;
+
+// the memcheck requests, numbered from `VG_USERREQ_TOOL_BASE('M', 'C')`
+const MAKE_MEM_NOACCESS: usize = 0x4d43_0000;
+const MAKE_MEM_UNDEFINED: usize = 0x4d43_0001;
+
+/// Reports a block of `size` bytes handed out at `ptr`, its contents are undefined until
+/// written. Null pointers (failed allocations) are passed through.
+#[inline]
+pub(crate) fn malloc_like(ptr: *mut u8, size: usize) -> *mut u8 {
+    if !ptr.is_null() {
+        // no red zone around the block, its contents aren't zeroed
+        unsafe { request(MALLOCLIKE_BLOCK, [ptr as usize, size, 0, 0, 0]) };
+    }
+
+    ptr
+}
+
+/// Reports the block at `ptr` as freed, memcheck marks it as inaccessible.
+#[inline]
+pub(crate) fn free_like(ptr: *mut u8) {
+    unsafe { request(FREELIKE_BLOCK, [ptr as usize, 0, 0, 0, 0]) };
+}
+
+/// Reports the block at `ptr` as grown or shrunk in place from `old_size` to `new_size` bytes.
+#[cfg(feature = "stack")]
+#[inline]
+pub(crate) fn resize_in_place(ptr: *mut u8, old_size: usize, new_size: usize) {
+    unsafe {
+        request(
+            RESIZEINPLACE_BLOCK,
+            [ptr as usize, old_size, new_size, 0, 0],
+        )
+    };
+}
+
+/// Marks `size` bytes at `ptr` as inaccessible.
+#[inline]
+pub(crate) fn make_mem_noaccess(ptr: *const u8, size: usize) {
+    unsafe { request(MAKE_MEM_NOACCESS, [ptr as usize, size, 0, 0, 0]) };
+}
+
+/// Marks `size` bytes at `ptr` as accessible but undefined.
+#[inline]
+pub(crate) fn make_mem_undefined(ptr: *const u8, size: usize) {
+    unsafe { request(MAKE_MEM_UNDEFINED, [ptr as usize, size, 0, 0, 0]) };
+}
+
+// Issues a client request, the special instruction sequence is a no-op unless the program runs
+// under valgrind, in which case it returns the answer to the request instead of 0.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn request(code: usize, args: [usize; 5]) -> usize {
+    let request = [code, args[0], args[1], args[2], args[3], args[4]];
+    let result: usize;
+
+    unsafe {
+        core::arch::asm!(
+            // the rotations add up to 128 bits, leaving `rdi` as it was
+            "rol rdi, 3",
+            "rol rdi, 13",
+            "rol rdi, 61",
+            "rol rdi, 51",
+            "xchg rbx, rbx",
+            in("rax") request.as_ptr(),
+            inout("rdx") 0usize => result,
+            options(nostack),
+        )
+    };
+    result
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+unsafe fn request(code: usize, args: [usize; 5]) -> usize {
+    let request = [code, args[0], args[1], args[2], args[3], args[4]];
+    let result: usize;
+
+    unsafe {
+        core::arch::asm!(
+            // the rotations add up to 128 bits, leaving `x12` as it was
+            "ror x12, x12, #3",
+            "ror x12, x12, #13",
+            "ror x12, x12, #51",
+            "ror x12, x12, #61",
+            "orr x10, x10, x10",
+            in("x4") request.as_ptr(),
+            inout("x3") 0usize => result,
+            options(nostack),
+        )
+    };
+    result
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+#[inline(always)]
+unsafe fn request(_code: usize, _args: [usize; 5]) -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNNING_ON_VALGRIND: usize = 0x1001;
+
+    #[test]
+    fn test_requests_outside_valgrind() {
+        let mut block = [0xABu8; 32];
+        let ptr = block.as_mut_ptr();
+
+        // nothing answers the requests
+        assert_eq!(unsafe { request(RUNNING_ON_VALGRIND, [0; 5]) }, 0);
+
+        // and they leave the memory alone
+        assert_eq!(malloc_like(ptr, block.len()), ptr);
+        #[cfg(feature = "stack")]
+        resize_in_place(ptr, 32, 16);
+        free_like(ptr);
+        assert_eq!(block, [0xAB; 32]);
+    }
+}
@@ -0,0 +1,79 @@
+use super::layout_header::LayoutHeader;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+/// Debugging wrapper that records the layout of each allocation and asserts that `dealloc` is
+/// given the same one, catching blocks freed with the wrong `Layout`.
+///
+/// Each allocation is prefixed with a header holding its size and alignment, so wrapping an
+/// allocator costs at least two words per block.
+pub struct ValidateLayout<A> {
+    inner: A,
+}
+
+impl<A> ValidateLayout<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for ValidateLayout<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let block_layout = match LayoutHeader::block_layout(layout) {
+            Some(block_layout) => block_layout,
+            None => return ptr::null_mut(),
+        };
+
+        let block = unsafe { self.inner.alloc(block_layout) };
+        if block.is_null() {
+            return ptr::null_mut();
+        }
+
+        unsafe { LayoutHeader::write(block, layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = unsafe { LayoutHeader::read(ptr) };
+
+        assert!(
+            header.size == layout.size() && header.align == layout.align(),
+            "block of {} bytes freed with a layout of {} bytes (align {})",
+            header.size,
+            layout.size(),
+            layout.align(),
+        );
+
+        let (block, block_layout) = header.block(ptr);
+        unsafe { self.inner.dealloc(block, block_layout) };
+    }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, FreeListAllocator, PlacementPolicy, SpinLock};
+
+    static ARENA: Arena<4096> = Arena::new();
+    static ALLOC: ValidateLayout<SpinLock<FreeListAllocator>> = ValidateLayout::new(SpinLock::new(
+        FreeListAllocator::new(unsafe { ARENA.region() }, PlacementPolicy::FindFirst),
+    ));
+
+    #[test]
+    fn test_matching_layout() {
+        let layout = Layout::new::<[u32; 8]>();
+
+        let ptr = unsafe { ALLOC.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % layout.align(), 0);
+
+        unsafe { ALLOC.dealloc(ptr, layout) };
+    }
+
+    #[test]
+    #[should_panic(expected = "freed with a layout")]
+    fn test_wrong_layout() {
+        let ptr = unsafe { ALLOC.alloc(Layout::new::<[u32; 8]>()) };
+
+        unsafe { ALLOC.dealloc(ptr, Layout::new::<[u32; 4]>()) };
+    }
+}
@@ -0,0 +1,266 @@
+use super::Region;
+use std::io;
+
+/// Backing storage reserved from the operating system with `mmap`, for heaps too large to be a
+/// `static` or whose size is only known at runtime.
+///
+/// The memory is returned to the OS when the arena is dropped, so it has to outlive every
+/// allocator using it, [`region`](VirtualArena::region) enforces this by requiring a `'static`
+/// arena (e.g. leaked or stored in a `OnceLock`).
+pub struct VirtualArena {
+    start: *mut u8,
+    size: usize,
+    // locked in RAM, scrubbed before it's unmapped
+    locked: bool,
+}
+
+// SAFETY: the mapping is only ever accessed through the allocators, which are behind a lock
+unsafe impl Send for VirtualArena {}
+unsafe impl Sync for VirtualArena {}
+
+/// Huge pages to back a [`VirtualArena`] with, so a large heap needs fewer TLB entries.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePages {
+    /// Transparent huge pages, advised with `madvise(MADV_HUGEPAGE)`. The kernel backs the
+    /// arena with huge pages when it can and falls back to regular pages otherwise.
+    Transparent,
+    /// Pages from the pool reserved through `/proc/sys/vm/nr_hugepages`, mapped with
+    /// `MAP_HUGETLB`. Fails if there aren't enough of them.
+    Explicit,
+}
+
+/// Size of a huge page, the default one on x86_64 and aarch64.
+#[cfg(target_os = "linux")]
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+impl VirtualArena {
+    /// Maps `size` bytes (rounded up to whole pages) of zeroed, readable and writable memory.
+    pub fn new(size: usize) -> io::Result<Self> {
+        let size = size.next_multiple_of(page_size());
+        let start = map(size, 0)?;
+
+        Ok(Self {
+            start,
+            size,
+            locked: false,
+        })
+    }
+
+    /// Maps `size` bytes (rounded up to whole pages) locked in RAM with `mlock`, for keys and
+    /// credentials that must never be written to swap. On Linux the arena is also left out of
+    /// core dumps, and it's zeroed before it's unmapped (pair it with `zeroize-on-free` to scrub
+    /// the blocks as soon as they're freed).
+    ///
+    /// Fails if the arena exceeds what the process may lock, see `RLIMIT_MEMLOCK`.
+    pub fn new_locked(size: usize) -> io::Result<Self> {
+        let mut arena = Self::new(size)?;
+
+        if unsafe { libc::mlock(arena.start as *const libc::c_void, arena.size) } != 0 {
+            // unmapped by the drop of the arena
+            return Err(io::Error::last_os_error());
+        }
+        arena.locked = true;
+
+        #[cfg(target_os = "linux")]
+        unsafe {
+            libc::madvise(
+                arena.start as *mut libc::c_void,
+                arena.size,
+                libc::MADV_DONTDUMP,
+            )
+        };
+
+        Ok(arena)
+    }
+
+    /// Maps `size` bytes (rounded up to whole huge pages) backed by huge pages.
+    #[cfg(target_os = "linux")]
+    pub fn with_huge_pages(size: usize, pages: HugePages) -> io::Result<Self> {
+        let size = size.next_multiple_of(HUGE_PAGE_SIZE);
+
+        let start = match pages {
+            HugePages::Explicit => map(size, libc::MAP_HUGETLB)?,
+            HugePages::Transparent => {
+                // huge pages have to be aligned to their size, so the mapping is made a huge
+                // page larger and trimmed down to an aligned one
+                let mapped = map(size + HUGE_PAGE_SIZE, 0)? as usize;
+                let start = mapped.next_multiple_of(HUGE_PAGE_SIZE);
+                let tail = mapped + HUGE_PAGE_SIZE - start;
+
+                unsafe {
+                    if start > mapped {
+                        libc::munmap(mapped as *mut libc::c_void, start - mapped);
+                    }
+                    if tail > 0 {
+                        libc::munmap((start + size) as *mut libc::c_void, tail);
+                    }
+
+                    // only a hint, kernels without transparent huge pages keep regular ones
+                    libc::madvise(start as *mut libc::c_void, size, libc::MADV_HUGEPAGE);
+                }
+
+                start as *mut u8
+            }
+        };
+
+        Ok(Self {
+            start,
+            size,
+            locked: false,
+        })
+    }
+
+    /// Returns the region of memory covered by this arena.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Arena::region`](crate::Arena::region).
+    pub unsafe fn region(&'static self) -> Region {
+        unsafe { Region::from_raw_parts(self.start, self.size) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the physical memory behind the whole pages of `region` to the OS with
+    /// `madvise(MADV_DONTNEED)`, they read as zeroes the next time they're touched. Meant for
+    /// [`ArenaAllocator::with_purge_fn`](crate::ArenaAllocator::with_purge_fn), the partial pages
+    /// at either end of the region are left alone.
+    pub fn purge(region: Region) {
+        let start = region.start().next_multiple_of(page_size());
+        let end = region.end() / page_size() * page_size();
+
+        if end > start {
+            unsafe { libc::madvise(start as *mut libc::c_void, end - start, libc::MADV_DONTNEED) };
+        }
+    }
+}
+
+impl Drop for VirtualArena {
+    fn drop(&mut self) {
+        if self.locked {
+            unsafe { self.start.write_bytes(0, self.size) };
+            // the writes can't be dropped as dead stores right before the unmap
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
+        }
+
+        unsafe { libc::munmap(self.start as *mut libc::c_void, self.size) };
+    }
+}
+
+// maps `size` bytes of anonymous memory, with `flags` on top of the usual ones
+fn map(size: usize, flags: libc::c_int) -> io::Result<*mut u8> {
+    let start = unsafe {
+        libc::mmap(
+            core::ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | flags,
+            -1,
+            0,
+        )
+    };
+
+    if start == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(start as *mut u8)
+}
+
+pub(crate) fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+#[cfg(all(test, feature = "freelist"))]
+mod tests {
+    use super::*;
+    use crate::{FreeListAllocator, PlacementPolicy, SpinLock};
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_virtual_arena() {
+        let arena: &'static VirtualArena = Box::leak(Box::new(VirtualArena::new(1 << 20).unwrap()));
+        let heap = SpinLock::new(FreeListAllocator::new(
+            unsafe { arena.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let layout = Layout::new::<[u64; 1024]>();
+
+        let ptr = unsafe { heap.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!(
+            unsafe { arena.region() }.start() <= ptr as usize
+                && (ptr as usize) < unsafe { arena.region() }.end()
+        );
+
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+
+    #[test]
+    fn test_locked_arena() {
+        let arena: &'static VirtualArena =
+            Box::leak(Box::new(VirtualArena::new_locked(64 * 1024).unwrap()));
+        let heap = SpinLock::new(FreeListAllocator::new(
+            unsafe { arena.region() },
+            PlacementPolicy::FindFirst,
+        ));
+
+        let layout = Layout::new::<[u8; 32]>();
+        let key = unsafe { heap.alloc(layout) };
+        unsafe { key.write_bytes(0x5A, layout.size()) };
+        unsafe { heap.dealloc(key, layout) };
+
+        // locking more than the process may fails cleanly
+        assert!(VirtualArena::new_locked(1 << 40).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "linear")]
+    fn test_purge_on_reset() {
+        use crate::ArenaAllocator;
+
+        let arena: &'static VirtualArena = Box::leak(Box::new(VirtualArena::new(1 << 20).unwrap()));
+        let heap = SpinLock::new(
+            ArenaAllocator::new(unsafe { arena.region() }).with_purge_fn(VirtualArena::purge),
+        );
+
+        let layout = Layout::new::<[u8; 4096]>();
+        let blocks = [(); 16].map(|_| unsafe { heap.alloc(layout) });
+        for block in blocks {
+            unsafe { block.write_bytes(0xAB, layout.size()) };
+        }
+
+        unsafe { heap.lock().get_mut().reset() };
+
+        // the pages were given back, so they read as zeroes again
+        let block = unsafe { heap.alloc(layout) };
+        assert_eq!(block, blocks[0]);
+        assert!(unsafe { core::slice::from_raw_parts(block, 4096) }
+            .iter()
+            .all(|&byte| byte == 0));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_transparent_huge_pages() {
+        let arena = VirtualArena::with_huge_pages(3 * 1024 * 1024, HugePages::Transparent).unwrap();
+        let arena: &'static VirtualArena = Box::leak(Box::new(arena));
+
+        assert_eq!(arena.size(), 2 * HUGE_PAGE_SIZE);
+        assert_eq!(unsafe { arena.region() }.start() % HUGE_PAGE_SIZE, 0);
+
+        // the whole arena is usable
+        let heap = SpinLock::new(FreeListAllocator::new(
+            unsafe { arena.region() },
+            PlacementPolicy::FindFirst,
+        ));
+        let layout = Layout::from_size_align(3 * 1024 * 1024, 8).unwrap();
+        let ptr = unsafe { heap.alloc_zeroed(layout) };
+        assert!(!ptr.is_null());
+        unsafe { ptr.add(layout.size() - 1).write(1) };
+    }
+}
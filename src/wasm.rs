@@ -0,0 +1,33 @@
+//! Memory for the allocators straight from the WebAssembly linear memory, so small modules can
+//! use the free list allocator as their global allocator instead of shipping dlmalloc:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static HEAP: SpinLock<FreeListAllocator> = SpinLock::new(
+//!     FreeListAllocator::empty(PlacementPolicy::FindFirst).with_grow_fn(rsalloc::wasm::grow_memory),
+//! );
+//! ```
+//!
+//! The heap starts out empty and grows a few pages at a time as it fills up. Linear memory only
+//! ever grows at its end, so as long as nothing else calls `memory.grow` every new region
+//! extends the previous one.
+
+use super::Region;
+use core::arch::wasm32;
+
+/// Size of a WebAssembly page.
+pub const PAGE_SIZE: usize = 64 * 1024;
+
+/// Grows the linear memory by enough pages to hold `min_size` bytes, returns the new pages or
+/// `None` if the memory can't grow any further.
+pub fn grow_memory(min_size: usize) -> Option<Region> {
+    let pages = min_size.div_ceil(PAGE_SIZE);
+
+    // the previous size of the memory in pages, which is where the new pages start
+    let previous = wasm32::memory_grow::<0>(pages);
+    if previous == usize::MAX {
+        return None;
+    }
+
+    Some(unsafe { Region::from_raw_parts((previous * PAGE_SIZE) as *mut u8, pages * PAGE_SIZE) })
+}
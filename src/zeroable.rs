@@ -0,0 +1,79 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+/// Types for which the all-zero bit pattern is a valid value.
+///
+/// # Safety
+///
+/// Implementing it for a type where zeroed memory isn't a valid value (references, `NonZero*`,
+/// most enums) is undefined behavior.
+pub unsafe trait Zeroable {}
+
+macro_rules! impl_zeroable {
+    ($($ty:ty),*) => {
+        $(unsafe impl Zeroable for $ty {})*
+    };
+}
+
+impl_zeroable!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool, char
+);
+
+unsafe impl<T> Zeroable for MaybeUninit<T> {}
+unsafe impl<T> Zeroable for *const T {}
+unsafe impl<T> Zeroable for *mut T {}
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+
+/// `calloc`-style allocation of zeroed slices, available on every [`GlobalAlloc`].
+pub trait AllocZeroedSlice: GlobalAlloc {
+    /// Allocates `len` zeroed values of `T`, returns `None` if the allocator is out of memory.
+    ///
+    /// The slice is never freed on its own, give it back with [`GlobalAlloc::dealloc`] using
+    /// `Layout::array::<T>(len)` if the allocator supports it.
+    // the slice points into the allocator's memory, not into `self`
+    #[allow(clippy::mut_from_ref)]
+    fn alloc_zeroed_slice<T: Zeroable>(&self, len: usize) -> Option<&mut [T]> {
+        let layout = Layout::array::<T>(len).ok()?;
+
+        // zero sized requests can't go through the allocator
+        if layout.size() == 0 {
+            let ptr = NonNull::<T>::dangling().as_ptr();
+            return Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) });
+        }
+
+        let ptr = unsafe { self.alloc_zeroed(layout) } as *mut T;
+
+        if ptr.is_null() {
+            return None;
+        }
+
+        // SAFETY: the memory is zeroed and `T` is valid for the all-zero pattern
+        Some(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+    }
+}
+
+impl<A: GlobalAlloc> AllocZeroedSlice for A {}
+
+#[cfg(all(test, feature = "stack"))]
+mod tests {
+    use super::*;
+    use crate::{Arena, SpinLock, StackAllocator};
+
+    #[test]
+    fn test_alloc_zeroed_slice() {
+        static ARENA: Arena<1024> = Arena::new();
+        static ALLOC: SpinLock<StackAllocator> =
+            SpinLock::new(StackAllocator::new(unsafe { ARENA.region() }));
+
+        let slice = ALLOC.alloc_zeroed_slice::<u32>(16).unwrap();
+        assert_eq!(slice.len(), 16);
+        assert!(slice.iter().all(|&value| value == 0));
+
+        let empty = ALLOC.alloc_zeroed_slice::<u64>(0).unwrap();
+        assert!(empty.is_empty());
+
+        // more than the arena holds
+        assert!(ALLOC.alloc_zeroed_slice::<u64>(1024).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! Model checks of the spin lock and the allocators behind it, explores every interleaving of
+//! the threads:
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --test loom --release
+//! ```
+
+#![cfg(loom)]
+
+use loom::sync::Arc;
+use loom::thread;
+use rsalloc::{Arena, PoolAllocator, RwSpinLock, SpinLock, StackAllocator};
+use std::alloc::{GlobalAlloc, Layout};
+
+#[test]
+fn mutual_exclusion() {
+    loom::model(|| {
+        let lock = Arc::new(SpinLock::new(0usize));
+
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let lock = lock.clone();
+                thread::spawn(move || {
+                    // a non atomic read-modify-write, loom reports a data race if the lock
+                    // doesn't order the accesses
+                    lock.lock_with(|value| *value += 1);
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(lock.lock_with(|value| *value), 2);
+    });
+}
+
+#[test]
+fn try_lock_excludes_lock() {
+    loom::model(|| {
+        let lock = Arc::new(SpinLock::new(()));
+
+        let guard = lock.lock();
+        let other = {
+            let lock = lock.clone();
+            thread::spawn(move || lock.try_lock().is_some())
+        };
+
+        // the other thread can't take the lock while the guard is alive
+        assert!(!other.join().unwrap());
+        SpinLock::unlock(guard);
+
+        assert!(lock.try_lock().is_some());
+    });
+}
+
+#[test]
+fn rw_lock_writers_exclude_readers() {
+    loom::model(|| {
+        let lock = Arc::new(RwSpinLock::new((0usize, 0usize)));
+
+        let writer = {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                lock.write_with(|(first, second)| {
+                    *first += 1;
+                    *second += 1;
+                })
+            })
+        };
+
+        // a reader never sees a half done write
+        let (first, second) = lock.read_with(|&value| value);
+        assert_eq!(first, second);
+
+        writer.join().unwrap();
+        assert_eq!(lock.read_with(|&value| value), (1, 1));
+    });
+}
+
+#[test]
+fn rw_lock_shares_reads() {
+    loom::model(|| {
+        let lock = Arc::new(RwSpinLock::new(()));
+
+        let guard = lock.read();
+        let other = {
+            let lock = lock.clone();
+            thread::spawn(move || (lock.try_read().is_some(), lock.try_write().is_some()))
+        };
+
+        // another reader gets in, a writer doesn't
+        assert_eq!(other.join().unwrap(), (true, false));
+        drop(guard);
+
+        assert!(lock.try_write().is_some());
+    });
+}
+
+#[test]
+fn pool_hands_out_distinct_chunks() {
+    static ARENA: Arena<256> = Arena::new();
+
+    loom::model(|| {
+        let pool = Arc::new(SpinLock::new(PoolAllocator::new(
+            unsafe { ARENA.region() },
+            64,
+        )));
+        let layout = Layout::new::<[u8; 64]>();
+
+        let other = {
+            let pool = pool.clone();
+            thread::spawn(move || unsafe { pool.alloc(layout) } as usize)
+        };
+
+        let held = unsafe { pool.alloc(layout) } as usize;
+
+        // both chunks are still held, so they can't be the same one
+        assert_ne!(other.join().unwrap(), held);
+    });
+}
+
+#[test]
+fn stack_frees_in_order() {
+    static ARENA: Arena<256> = Arena::new();
+
+    loom::model(|| {
+        let stack = Arc::new(SpinLock::new(StackAllocator::new(unsafe {
+            ARENA.region()
+        })));
+        let layout = Layout::new::<u64>();
+
+        let other = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                let ptr = unsafe { stack.alloc(layout) };
+                unsafe { stack.dealloc(ptr, layout) };
+            })
+        };
+
+        let ptr = unsafe { stack.alloc(layout) };
+        unsafe { stack.dealloc(ptr, layout) };
+        other.join().unwrap();
+
+        // interleaved frees are rejected rather than corrupting the offsets, so each block is
+        // either released or accounted for
+        let rejected = stack.rejected_frees();
+        let top = unsafe { stack.alloc(layout) } as usize;
+        assert!(rejected <= 1);
+        assert!(rejected == 1 || top == unsafe { ARENA.region() }.start() + 8);
+    });
+}
@@ -0,0 +1,140 @@
+//! Requests no allocator can serve, frees while the lock is held elsewhere, and exhausted arenas:
+//! the `GlobalAlloc` impls have to fail with a null pointer (or leak the freed block) instead of
+//! panicking, which would unwind out of the global allocator.
+
+#![cfg(all(
+    not(loom),
+    any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )
+))]
+
+use rsalloc::{Arena, Bounded, SpinLock};
+use std::alloc::{GlobalAlloc, Layout};
+use std::panic::{self, AssertUnwindSafe};
+
+// gives up on the lock right away
+type Lock<T> = SpinLock<T, Bounded<1>>;
+
+// runs `f`, failing the test with `what` if it panics
+fn no_panic<T>(what: &str, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| panic!("{what} panicked"))
+}
+
+fn hostile_layouts() -> Vec<Layout> {
+    let huge = isize::MAX as usize;
+    [
+        (huge, 1),
+        (huge / 2, 4096),
+        (1, 1 << 30),
+        (0, 1 << 40),
+        (64 * 1024, 8),
+    ]
+    .into_iter()
+    .map(|(size, align)| Layout::from_size_align(size, align).unwrap())
+    .collect()
+}
+
+fn check<T>(name: &str, allocator: &Lock<T>)
+where
+    Lock<T>: GlobalAlloc,
+{
+    for layout in hostile_layouts() {
+        let ptr = no_panic(name, || unsafe { allocator.alloc(layout) });
+        if !ptr.is_null() {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    // the lock is held elsewhere: the allocation fails, the free leaks the block
+    let layout = Layout::new::<u64>();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null(), "{name} can't allocate a word");
+
+    let guard = allocator.lock_or_timeout().unwrap();
+    assert!(no_panic(name, || unsafe { allocator.alloc(layout) }).is_null());
+    no_panic(name, || unsafe { allocator.dealloc(ptr, layout) });
+    assert!(no_panic(name, || unsafe { allocator.realloc(ptr, layout, 16) }).is_null());
+    drop(guard);
+
+    // until the arena runs out
+    let mut count = 0;
+    while !no_panic(name, || unsafe { allocator.alloc(layout) }).is_null() {
+        count += 1;
+        assert!(count < 1024, "{name} never runs out");
+    }
+    let ptr = no_panic(name, || unsafe { allocator.realloc(ptr, layout, 4096) });
+    assert!(ptr.is_null());
+}
+
+#[test]
+#[cfg(feature = "freelist")]
+fn free_list() {
+    use rsalloc::{FreeListAllocator, PlacementPolicy};
+
+    for policy in [PlacementPolicy::FindFirst, PlacementPolicy::FindBest] {
+        let arena = Box::leak(Box::new(Arena::<1024>::new()));
+        check(
+            "free list",
+            &Lock::with_relax(FreeListAllocator::new(unsafe { arena.region() }, policy)),
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "pool")]
+fn pool() {
+    use rsalloc::PoolAllocator;
+
+    static ARENA: Arena<1024> = Arena::new();
+    check(
+        "pool",
+        &Lock::with_relax(PoolAllocator::new(unsafe { ARENA.region() }, 64)),
+    );
+
+    // frees into the middle of a chunk and of chunks that aren't allocated are counted
+    static BAD_FREE_ARENA: Arena<1024> = Arena::new();
+    let pool = Lock::with_relax(PoolAllocator::new(unsafe { BAD_FREE_ARENA.region() }, 64));
+    let layout = Layout::new::<[u8; 64]>();
+    let ptr = unsafe { pool.alloc(layout) };
+    no_panic("pool", || unsafe { pool.dealloc(ptr.add(8), layout) });
+    no_panic("pool", || unsafe { pool.dealloc(ptr, layout) });
+    no_panic("pool", || unsafe { pool.dealloc(ptr, layout) });
+    assert_eq!(
+        pool.rejected_frees(),
+        if cfg!(debug_assertions) { 2 } else { 1 }
+    );
+}
+
+#[test]
+#[cfg(feature = "stack")]
+fn stack() {
+    use rsalloc::StackAllocator;
+
+    static ARENA: Arena<1024> = Arena::new();
+    check(
+        "stack",
+        &Lock::with_relax(StackAllocator::new(unsafe { ARENA.region() })),
+    );
+}
+
+#[test]
+#[cfg(feature = "linear")]
+fn linear() {
+    use rsalloc::{ArenaAllocator, BumpDownAllocator};
+
+    static ARENA: Arena<1024> = Arena::new();
+    check(
+        "arena",
+        &Lock::with_relax(ArenaAllocator::new(unsafe { ARENA.region() })),
+    );
+
+    static BUMP_ARENA: Arena<1024> = Arena::new();
+    check(
+        "bump down",
+        &Lock::with_relax(BumpDownAllocator::new(unsafe { BUMP_ARENA.region() })),
+    );
+}
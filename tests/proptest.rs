@@ -0,0 +1,225 @@
+//! Random sequences of allocations, frees and reallocations run against every allocator, checked
+//! against a shadow model of the live blocks: they're aligned, in the arena, apart from each
+//! other, and keep the pattern written into them whatever happens to the other blocks.
+//!
+//! ```sh
+//! PROPTEST_CASES=10000 cargo test --test proptest --release
+//! ```
+
+#![cfg(all(
+    not(loom),
+    any(
+        feature = "linear",
+        feature = "stack",
+        feature = "pool",
+        feature = "freelist"
+    )
+))]
+
+use proptest::prelude::*;
+use rsalloc::Region;
+use std::alloc::{GlobalAlloc, Layout};
+use std::collections::BTreeMap;
+
+const ARENA_SIZE: usize = 8 * 1024;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Alloc { size: usize, align_shift: u32 },
+    Dealloc { index: usize },
+    Realloc { index: usize, new_size: usize },
+}
+
+// operations on blocks of up to `max_size` bytes aligned to at most `1 << max_align_shift`
+fn ops(max_size: usize, max_align_shift: u32) -> impl Strategy<Value = Vec<Op>> {
+    let op = prop_oneof![
+        2 => (1..=max_size, 0..=max_align_shift)
+            .prop_map(|(size, align_shift)| Op::Alloc { size, align_shift }),
+        1 => any::<usize>().prop_map(|index| Op::Dealloc { index }),
+        1 => (any::<usize>(), 1..=max_size)
+            .prop_map(|(index, new_size)| Op::Realloc { index, new_size }),
+    ];
+    prop::collection::vec(op, 1..64)
+}
+
+// a live block: where it ends, its layout, and the byte it was filled with
+struct Block {
+    end: usize,
+    layout: Layout,
+    pattern: u8,
+}
+
+// live blocks by start address
+#[derive(Default)]
+struct Shadow {
+    blocks: BTreeMap<usize, Block>,
+    next_pattern: u8,
+}
+
+impl Shadow {
+    fn insert(&mut self, arena: Region, ptr: *mut u8, layout: Layout) -> Result<(), TestCaseError> {
+        let (start, end) = (ptr as usize, ptr as usize + layout.size());
+
+        prop_assert!(
+            start.is_multiple_of(layout.align()),
+            "{ptr:p} isn't aligned"
+        );
+        prop_assert!(
+            arena.start() <= start && end <= arena.end(),
+            "{ptr:p} is outside of the arena"
+        );
+
+        // the closest live block below must end before this one starts, the one above start after
+        if let Some((_, prev)) = self.blocks.range(..=start).next_back() {
+            prop_assert!(prev.end <= start, "{ptr:p} overlaps a live block");
+        }
+        if let Some((&next_start, _)) = self.blocks.range(start..).next() {
+            prop_assert!(end <= next_start, "{ptr:p} overlaps a live block");
+        }
+
+        self.next_pattern = self.next_pattern.wrapping_add(1);
+        unsafe { ptr.write_bytes(self.next_pattern, layout.size()) };
+
+        let pattern = self.next_pattern;
+        self.blocks.insert(
+            start,
+            Block {
+                end,
+                layout,
+                pattern,
+            },
+        );
+        Ok(())
+    }
+
+    // the `index`th live block, wrapping around
+    fn pick(&self, index: usize) -> Option<(usize, Layout)> {
+        let (&start, block) = self
+            .blocks
+            .iter()
+            .nth(index.checked_rem(self.blocks.len())?)?;
+        Some((start, block.layout))
+    }
+
+    // every live block still holds its pattern
+    fn check(&self) -> Result<(), TestCaseError> {
+        for (&start, block) in &self.blocks {
+            let bytes =
+                unsafe { std::slice::from_raw_parts(start as *const u8, block.end - start) };
+            prop_assert!(
+                bytes.iter().all(|&byte| byte == block.pattern),
+                "the block at {start:#x} was overwritten"
+            );
+        }
+        Ok(())
+    }
+}
+
+fn run<A: GlobalAlloc>(allocator: &A, arena: Region, ops: &[Op]) -> Result<(), TestCaseError> {
+    let mut shadow = Shadow::default();
+
+    for op in ops {
+        match *op {
+            Op::Alloc { size, align_shift } => {
+                let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+
+                // running out of memory is fine, handing out a bad block isn't
+                if !ptr.is_null() {
+                    shadow.insert(arena, ptr, layout)?;
+                }
+            }
+            Op::Dealloc { index } => {
+                if let Some((start, layout)) = shadow.pick(index) {
+                    shadow.blocks.remove(&start);
+                    unsafe { allocator.dealloc(start as *mut u8, layout) };
+                }
+            }
+            Op::Realloc { index, new_size } => {
+                if let Some((start, layout)) = shadow.pick(index) {
+                    let pattern = shadow.blocks[&start].pattern;
+                    let ptr = unsafe { allocator.realloc(start as *mut u8, layout, new_size) };
+
+                    // on failure the block is left as it was
+                    if ptr.is_null() {
+                        continue;
+                    }
+
+                    // the contents up to the smaller of the sizes moved along
+                    let kept = layout.size().min(new_size);
+                    let bytes = unsafe { std::slice::from_raw_parts(ptr, kept) };
+                    prop_assert!(
+                        bytes.iter().all(|&byte| byte == pattern),
+                        "realloc lost the data"
+                    );
+
+                    shadow.blocks.remove(&start);
+                    let layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+                    shadow.insert(arena, ptr, layout)?;
+                }
+            }
+        }
+
+        shadow.check()?;
+    }
+
+    for (&start, block) in &shadow.blocks {
+        unsafe { allocator.dealloc(start as *mut u8, block.layout) };
+    }
+    Ok(())
+}
+
+// a fresh arena for every case, on the heap of the test itself
+fn arena() -> (Vec<u64>, Region) {
+    let mut memory = vec![0u64; ARENA_SIZE / 8];
+    let region = unsafe { Region::from_raw_parts(memory.as_mut_ptr().cast(), ARENA_SIZE) };
+    (memory, region)
+}
+
+proptest! {
+    #[test]
+    #[cfg(feature = "freelist")]
+    fn free_list(ops in ops(512, 6), find_best in any::<bool>()) {
+        use rsalloc::{FreeListAllocator, PlacementPolicy, SpinLock};
+
+        let policy = if find_best { PlacementPolicy::FindBest } else { PlacementPolicy::FindFirst };
+        let (_memory, region) = arena();
+        run(&SpinLock::new(FreeListAllocator::new(region, policy)), region, &ops)?;
+    }
+
+    #[test]
+    #[cfg(feature = "pool")]
+    fn pool(ops in ops(64, 3)) {
+        use rsalloc::{PoolAllocator, SpinLock};
+
+        let (_memory, region) = arena();
+        run(&SpinLock::new(PoolAllocator::new(region, 64)), region, &ops)?;
+    }
+
+    #[test]
+    #[cfg(feature = "stack")]
+    fn stack(ops in ops(512, 6)) {
+        use rsalloc::{SpinLock, StackAllocator};
+
+        let (_memory, region) = arena();
+        run(&SpinLock::new(StackAllocator::new(region)), region, &ops)?;
+    }
+
+    #[test]
+    #[cfg(feature = "linear")]
+    fn linear(ops in ops(512, 6)) {
+        use rsalloc::{ArenaAllocator, SpinLock};
+
+        let (_memory, region) = arena();
+        run(&SpinLock::new(ArenaAllocator::new(region)), region, &ops)?;
+    }
+
+    #[test]
+    #[cfg(feature = "linear")]
+    fn bump_down(ops in ops(512, 6)) {
+        use rsalloc::{BumpDownAllocator, SpinLock};
+
+        let (_memory, region) = arena();
+        run(&SpinLock::new(BumpDownAllocator::new(region)), region, &ops)?;
+    }
+}